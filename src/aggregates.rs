@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use crate::VideoMetrics;
+
+// A watch of more than this share of the video counts as completing it.
+const COMPLETION_THRESHOLD: f64 = 0.9;
+
+// How a video's per-watch averages are kept. `Mean` is the exact mean over
+// every view. `Ema` weights the newest view by alpha, or by an equal share
+// while the video has fewer than 1/alpha views, so the first views are not
+// pulled towards zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Aggregation {
+    #[default]
+    Mean,
+    Ema(f64),
+}
+
+impl Aggregation {
+    // "off" for exact means, or the moving average's alpha.
+    pub fn parse(value: &str) -> Option<Self> {
+        if value == "off" {
+            return Some(Aggregation::Mean);
+        }
+        value.parse().ok()
+            .filter(|alpha: &f64| *alpha > 0.0 && *alpha <= 1.0)
+            .map(Aggregation::Ema)
+    }
+    
+    // Weight of the newest of `count` samples.
+    fn weight(self, count: u64) -> f64 {
+        let mean = 1.0 / count.max(1) as f64;
+        match self {
+            Aggregation::Mean => mean,
+            Aggregation::Ema(alpha) => alpha.max(mean),
+        }
+    }
+}
+
+fn update(average: &mut f64, sample: f64, weight: f64) {
+    *average += weight * (sample - *average);
+}
+
+// Adds a view to the video's averages. Completion rate is the average of
+// whether each view completed the video, so a partial view lowers it.
+pub fn record_watch(metrics: &mut VideoMetrics, watch_duration: Duration, video_duration: Duration, aggregation: Aggregation) {
+    metrics.views += 1;
+    let weight = aggregation.weight(metrics.views);
+    
+    let mut avg_watch_time = metrics.avg_watch_time.as_secs_f64();
+    update(&mut avg_watch_time, watch_duration.as_secs_f64(), weight);
+    metrics.avg_watch_time = Duration::from_secs_f64(avg_watch_time.max(0.0));
+    
+    if video_duration.is_zero() {
+        return;
+    }
+    let watch_percentage = watch_duration.as_secs_f64() / video_duration.as_secs_f64();
+    update(&mut metrics.avg_watch_percentage, watch_percentage, weight);
+    let completed = if watch_percentage > COMPLETION_THRESHOLD { 1.0 } else { 0.0 };
+    update(&mut metrics.completion_rate, completed, weight);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "expected {}, got {}", expected, actual);
+    }
+    
+    fn watched(seconds: &[u64], aggregation: Aggregation) -> VideoMetrics {
+        let mut metrics = VideoMetrics::default();
+        for &watch in seconds {
+            record_watch(&mut metrics, Duration::from_secs(watch), Duration::from_secs(100), aggregation);
+        }
+        metrics
+    }
+    
+    #[test]
+    fn running_mean_matches_batch_mean() {
+        let seconds = [30, 90, 120, 60, 95];
+        let metrics = watched(&seconds, Aggregation::Mean);
+        
+        let n = seconds.len() as f64;
+        let mean_seconds = seconds.iter().sum::<u64>() as f64 / n;
+        let completed = seconds.iter().filter(|&&watch| watch as f64 / 100.0 > COMPLETION_THRESHOLD).count() as f64;
+        assert_eq!(metrics.views, seconds.len() as u64);
+        assert_close(metrics.avg_watch_time.as_secs_f64(), mean_seconds);
+        assert_close(metrics.avg_watch_percentage, mean_seconds / 100.0);
+        assert_close(metrics.completion_rate, completed / n);
+    }
+    
+    #[test]
+    fn moving_average_starts_as_a_mean() {
+        let metrics = watched(&[30, 90], Aggregation::Ema(0.5));
+        assert_close(metrics.avg_watch_time.as_secs_f64(), 60.0);
+        
+        let metrics = watched(&[30, 90, 120], Aggregation::Ema(0.5));
+        assert_close(metrics.avg_watch_time.as_secs_f64(), 90.0);
+    }
+}
@@ -21,6 +21,12 @@ struct ChannelEngagement {
     _subscriber_retention: f64,
 }
 
+impl Default for AnalyticsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AnalyticsEngine {
     pub fn new() -> Self {
         AnalyticsEngine {
@@ -157,7 +163,7 @@ impl AnalyticsEngine {
         }
         
         let mut words: Vec<(String, usize)> = word_counts.into_iter().collect();
-        words.sort_by(|a, b| b.1.cmp(&a.1));
+        words.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
         
         words.iter().take(10).map(|(word, _)| word.clone()).collect()
     }
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use chrono_tz::Tz;
+use rand::Rng;
+
+use crate::event_log::EngineEvent;
+use crate::negative::NegativeFeedback;
+use crate::patterns::EventCounts;
+use crate::storage::{MemoryStorage, Storage};
+use crate::temporal::TemporalProfile;
+use crate::{EngineConfig, InteractionPatterns, RecommendationEngine, User, Video, VideoMetrics};
+
+const CATEGORIES: &[&str] = &["tech", "gaming", "music", "comedy", "education", "sports", "news", "travel"];
+
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    pub threads: Vec<usize>,
+    pub write_ratios: Vec<f64>,
+    pub duration: Duration,
+    pub users: usize,
+    pub videos: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            threads: vec![1, 2, 4, 8],
+            write_ratios: vec![0.0, 0.1, 0.5],
+            duration: Duration::from_secs(2),
+            users: 200,
+            videos: 500,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Locking {
+    // The previous design: one engine-wide mutex taken by every request.
+    Global,
+    FineGrained,
+}
+
+struct BenchResult {
+    reads: u64,
+    writes: u64,
+    read_nanos: u64,
+    write_nanos: u64,
+}
+
+pub fn run(config: &BenchConfig) -> io::Result<()> {
+    println!(
+        "Benchmarking {} users x {} videos, {:?} per run",
+        config.users, config.videos, config.duration
+    );
+    println!(
+        "{:<13} {:>7} {:>11} {:>12} {:>12} {:>12} {:>14} {:>15}",
+        "locking", "threads", "write ratio", "ops/s", "reads/s", "writes/s", "read mean (us)", "write mean (us)"
+    );
+    
+    for &write_ratio in &config.write_ratios {
+        for &threads in &config.threads {
+            for locking in [Locking::Global, Locking::FineGrained] {
+                let engine = seeded_engine(config.users, config.videos)?;
+                let result = run_workload(engine, locking, threads, write_ratio, config);
+                let secs = config.duration.as_secs_f64();
+                
+                println!(
+                    "{:<13} {:>7} {:>11.2} {:>12.0} {:>12.0} {:>12.0} {:>14.1} {:>15.1}",
+                    format!("{:?}", locking),
+                    threads,
+                    write_ratio,
+                    (result.reads + result.writes) as f64 / secs,
+                    result.reads as f64 / secs,
+                    result.writes as f64 / secs,
+                    mean_micros(result.read_nanos, result.reads),
+                    mean_micros(result.write_nanos, result.writes),
+                );
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+fn mean_micros(nanos: u64, ops: u64) -> f64 {
+    if ops == 0 { 0.0 } else { nanos as f64 / ops as f64 / 1000.0 }
+}
+
+fn run_workload(
+    engine: RecommendationEngine,
+    locking: Locking,
+    threads: usize,
+    write_ratio: f64,
+    config: &BenchConfig,
+) -> BenchResult {
+    let engine = Arc::new(engine);
+    let global = Arc::new(Mutex::new(()));
+    let reads = Arc::new(AtomicU64::new(0));
+    let writes = Arc::new(AtomicU64::new(0));
+    let read_nanos = Arc::new(AtomicU64::new(0));
+    let write_nanos = Arc::new(AtomicU64::new(0));
+    let deadline = Instant::now() + config.duration;
+    
+    let handles: Vec<_> = (0..threads).map(|_| {
+        let engine = engine.clone();
+        let global = global.clone();
+        let (reads, writes) = (reads.clone(), writes.clone());
+        let (read_nanos, write_nanos) = (read_nanos.clone(), write_nanos.clone());
+        let (users, videos) = (config.users, config.videos);
+        
+        std::thread::spawn(move || {
+            let mut rng = rand::rng();
+            while Instant::now() < deadline {
+                let user_id = format!("user{}", rng.random_range(0..users));
+                let is_write = rng.random_bool(write_ratio);
+                
+                let started = Instant::now();
+                let _guard = match locking {
+                    Locking::Global => Some(global.lock().unwrap()),
+                    Locking::FineGrained => None,
+                };
+                
+                if is_write {
+                    let event = EngineEvent::Watch {
+                        user_id,
+                        video_id: format!("video{}", rng.random_range(0..videos)),
+                        watch_duration: Duration::from_secs(rng.random_range(10..600)),
+                        interactions: Vec::new(),
+                        timestamp: Utc::now(),
+                    };
+                    engine.record(event).expect("in-memory record failed");
+                    writes.fetch_add(1, Ordering::Relaxed);
+                    write_nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                } else {
+                    // Reads bypass the recommendation cache, so they measure
+                    // storage contention rather than cache hits.
+                    engine.recommend_candidates(&user_id, 10, engine.config.rng, None, Utc::now())
+                        .expect("in-memory recommendations failed");
+                    reads.fetch_add(1, Ordering::Relaxed);
+                    read_nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                }
+            }
+        })
+    }).collect();
+    
+    for handle in handles {
+        handle.join().expect("benchmark thread panicked");
+    }
+    
+    BenchResult {
+        reads: reads.load(Ordering::Relaxed),
+        writes: writes.load(Ordering::Relaxed),
+        read_nanos: read_nanos.load(Ordering::Relaxed),
+        write_nanos: write_nanos.load(Ordering::Relaxed),
+    }
+}
+
+// Benchmarks run without an event log so they measure lock contention in the
+// engine rather than disk latency.
+fn seeded_engine(users: usize, videos: usize) -> io::Result<RecommendationEngine> {
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let mut rng = rand::rng();
+    
+    for i in 0..videos {
+        let category = CATEGORIES[rng.random_range(0..CATEGORIES.len())];
+        storage.put_video(Video {
+            id: format!("video{}", i),
+            title: format!("Benchmark video {}", i),
+            channel_id: format!("channel{}", i % 50),
+            duration: Duration::from_secs(rng.random_range(60..1800)),
+            categories: vec![category.to_string()],
+            tags: vec![category.to_string()],
+            upload_date: Utc::now() - chrono::Duration::days(rng.random_range(0..60)),
+            metrics: VideoMetrics {
+                views: rng.random_range(0..10_000),
+                likes: rng.random_range(0..1_000),
+                ..VideoMetrics::default()
+            },
+            embedding: (0..5).map(|_| rng.random::<f32>()).collect(),
+        })?;
+    }
+    
+    for i in 0..users {
+        let content_preferences: HashMap<String, f64> = CATEGORIES.iter()
+            .map(|category| (category.to_string(), rng.random::<f64>()))
+            .collect();
+        let subscriptions: HashSet<String> = (0..3)
+            .map(|_| format!("channel{}", rng.random_range(0..50)))
+            .collect();
+        
+        storage.put_user(User {
+            id: format!("user{}", i),
+            subscriptions,
+            watch_history: Vec::new(),
+            timezone: Tz::UTC,
+            preferences_updated_at: None,
+            short_term_preferences: HashMap::new(),
+            short_term_updated_at: None,
+            negative_feedback: NegativeFeedback::default(),
+            content_preferences,
+            interaction_patterns: InteractionPatterns {
+                avg_watch_percentage: 0.5,
+                avg_comment_length: 10,
+                time_of_day_preferences: HashMap::new(),
+                day_of_week_preferences: HashMap::new(),
+                temporal_profile: TemporalProfile::default(),
+                counts: EventCounts::default(),
+                like_to_view_ratio: 0.1,
+                share_frequency: 0.01,
+            },
+        })?;
+    }
+    
+    Ok(RecommendationEngine::with_storage(storage, EngineConfig::default()))
+}
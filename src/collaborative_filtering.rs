@@ -1,69 +1,84 @@
-use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
-use rand::{rng, Rng};
-use crate::User;
+use std::collections::HashMap;
+use std::io;
+use chrono::{DateTime, Utc};
+use crate::decay::DecayConfig;
+use crate::implicit::Feedback;
+use crate::randomness::Jitter;
+use crate::storage::Storage;
 
+// How many of the most similar users vote on a user's recommendations.
+const NEIGHBOURS: usize = 30;
+
+// User-user collaborative filtering. Ratings count as preference times
+// confidence, so a video someone liked and shared outweighs one they only
+// watched. The most similar users vote for the videos this user has not
+// rated, each vote being their weighted rating scaled by their similarity.
 pub fn collaborative_filtering_recommendations(
     user_id: &str,
-    count: usize, 
-    user_video_matrix: &Arc<Mutex<HashMap<String, HashMap<String, f64>>>>,
-    _users: &Arc<Mutex<HashMap<String, User>>>
-) -> Vec<(String, f64)> {
-    let mut recommendations = Vec::new();
-    let mut rng = rng();
-    
-    let user_video_matrix_guard = user_video_matrix.lock().unwrap();
-    let user_ratings = user_video_matrix_guard.get(user_id);
-    
-    
-    let dummy_videos = vec![
-        "video_cf_1", "video_cf_2", "video_cf_3", "video_cf_4", "video_cf_5",
-        "video_cf_6", "video_cf_7", "video_cf_8", "video_cf_9", "video_cf_10",
-        "video_cf_11", "video_cf_12", "video_cf_13", "video_cf_14", "video_cf_15",
-    ];
-    
-    let dummy_similar_users = vec![
-        ("user2", 0.85),
-        ("user5", 0.74),
-        ("user8", 0.68),
-        ("user9", 0.61),
-        ("user15", 0.58),
-    ];
-    
-    let has_ratings = user_ratings.is_some() && !user_ratings.unwrap().is_empty();
+    user_ratings: &HashMap<String, Feedback>,
+    count: usize,
+    storage: &dyn Storage,
+    decay: &DecayConfig,
+    now: DateTime<Utc>,
+    jitter: &mut Jitter
+) -> io::Result<Vec<(String, f64)>> {
+    if user_ratings.is_empty() {
+        return Ok(Vec::new());
+    }
     
-    if has_ratings {
-        let user_video_ids: HashSet<&String> = user_ratings.unwrap().keys().collect();
-        
-        for &(_, similarity) in &dummy_similar_users {
-            for _ in 0..5 {
-                let video_id = dummy_videos[rng.random_range(0..dummy_videos.len())].to_string();
-                if user_video_ids.contains(&video_id) {
-                    continue;
-                }
-                
-                let score = similarity * (0.7 + rng.random::<f64>() * 0.3);
-                recommendations.push((video_id, score));
+    let mut neighbours = Vec::new();
+    storage.for_each_user_ratings(&mut |other_id, ratings| {
+        if other_id == user_id {
+            return;
+        }
+        let ratings: HashMap<String, Feedback> = ratings.iter()
+            .filter_map(|(video_id, rating)| decay.feedback(rating, now).map(|feedback| (video_id.clone(), feedback)))
+            .collect();
+        let similarity = calculate_user_similarity(user_ratings, &ratings);
+        if similarity > 0.0 {
+            neighbours.push((similarity, other_id.to_string(), ratings));
+            if neighbours.len() >= 2 * NEIGHBOURS {
+                keep_most_similar(&mut neighbours);
             }
         }
-    } else {
-        for video_id in dummy_videos {
-            recommendations.push((video_id.to_string(), rng.random_range(0.3..0.6)));
+    })?;
+    keep_most_similar(&mut neighbours);
+    
+    let total_similarity: f64 = neighbours.iter().map(|(similarity, _, _)| similarity).sum();
+    let mut votes: HashMap<&str, f64> = HashMap::new();
+    for (similarity, _, ratings) in &neighbours {
+        for (video_id, feedback) in ratings {
+            if !user_ratings.contains_key(video_id) {
+                *votes.entry(video_id).or_insert(0.0) += similarity * weighted(feedback);
+            }
         }
     }
     
-    recommendations.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-    
-    let mut seen = HashSet::new();
-    recommendations.retain(|(id, _)| seen.insert(id.clone()));
+    // Votes are put in a fixed order before drawing any jitter, so a seeded
+    // policy gives the same list every time.
+    let mut votes: Vec<(&str, f64)> = votes.into_iter().filter(|(_, vote)| *vote > 0.0).collect();
+    votes.sort_by(|a, b| a.0.cmp(b.0));
+    let mut recommendations: Vec<(String, f64)> = votes.into_iter()
+        .map(|(video_id, vote)| (video_id.to_string(), (vote / total_similarity).tanh() * jitter.between(0.9, 1.0)))
+        .collect();
     
+    recommendations.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then_with(|| a.0.cmp(&b.0)));
     recommendations.truncate(count);
-    recommendations
+    Ok(recommendations)
+}
+
+fn keep_most_similar(neighbours: &mut Vec<(f64, String, HashMap<String, Feedback>)>) {
+    neighbours.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then_with(|| a.1.cmp(&b.1)));
+    neighbours.truncate(NEIGHBOURS);
+}
+
+fn weighted(feedback: &Feedback) -> f64 {
+    feedback.preference * feedback.confidence
 }
 
 fn calculate_user_similarity(
-    user1_ratings: &HashMap<String, f64>,
-    user2_ratings: &HashMap<String, f64>
+    user1_ratings: &HashMap<String, Feedback>,
+    user2_ratings: &HashMap<String, Feedback>
 ) -> f64 {
     let mut dot_product = 0.0;
     let mut magnitude1 = 0.0;
@@ -71,13 +86,13 @@ fn calculate_user_similarity(
     
     for (video_id, rating1) in user1_ratings {
         if let Some(rating2) = user2_ratings.get(video_id) {
-            dot_product += rating1 * rating2;
+            dot_product += weighted(rating1) * weighted(rating2);
         }
-        magnitude1 += rating1 * rating1;
+        magnitude1 += weighted(rating1) * weighted(rating1);
     }
     
     for rating2 in user2_ratings.values() {
-        magnitude2 += rating2 * rating2;
+        magnitude2 += weighted(rating2) * weighted(rating2);
     }
     
     let magnitude = magnitude1.sqrt() * magnitude2.sqrt();
@@ -86,4 +101,72 @@ fn calculate_user_similarity(
     } else {
         0.0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::negative::{NegativeFeedback, NegativeTarget};
+    use crate::randomness::RngPolicy;
+    use crate::storage::{MemoryStorage, Rating};
+    
+    fn rate(storage: &MemoryStorage, user_id: &str, video_id: &str, preference: f64, confidence: f64, now: DateTime<Utc>) {
+        let rating = Rating { value: preference * confidence, confidence, updated_at: now };
+        storage.put_rating(user_id, video_id, rating).unwrap();
+    }
+    
+    fn recommend(storage: &MemoryStorage, user_ratings: &HashMap<String, Feedback>, now: DateTime<Utc>) -> Vec<String> {
+        let mut jitter = RngPolicy::Off.jitter("collaborative", "user1", now);
+        collaborative_filtering_recommendations("user1", user_ratings, 10, storage, &DecayConfig::default(), now, &mut jitter)
+            .unwrap()
+            .into_iter()
+            .map(|(video_id, _)| video_id)
+            .collect()
+    }
+    
+    // Two neighbours who agree with user1 about `shared`; one also liked
+    // `a` and the other `b`, with the given confidence.
+    fn neighbours(b_confidence: f64, now: DateTime<Utc>) -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        rate(&storage, "user2", "shared", 1.0, 1.0, now);
+        rate(&storage, "user2", "a", 1.0, 1.0, now);
+        rate(&storage, "user3", "shared", 1.0, 1.0, now);
+        rate(&storage, "user3", "b", 1.0, b_confidence, now);
+        storage
+    }
+    
+    #[test]
+    fn recommends_what_similar_users_rated_highly() {
+        let now = Utc::now();
+        let storage = neighbours(1.0, now);
+        rate(&storage, "user4", "c", 1.0, 1.0, now);
+        let user_ratings = HashMap::from([("shared".to_string(), Feedback { preference: 1.0, confidence: 1.0 })]);
+        
+        assert_eq!(recommend(&storage, &user_ratings, now), vec!["a", "b"]);
+        assert!(recommend(&storage, &HashMap::new(), now).is_empty());
+    }
+    
+    #[test]
+    fn higher_confidence_ranks_higher() {
+        let now = Utc::now();
+        let user_ratings = HashMap::from([("shared".to_string(), Feedback { preference: 1.0, confidence: 1.0 })]);
+        
+        assert_eq!(recommend(&neighbours(1.0, now), &user_ratings, now), vec!["a", "b"]);
+        assert_eq!(recommend(&neighbours(3.0, now), &user_ratings, now), vec!["b", "a"]);
+    }
+    
+    #[test]
+    fn negatives_turn_away_neighbours_who_liked_them() {
+        let now = Utc::now();
+        let storage = neighbours(1.0, now);
+        rate(&storage, "user2", "unwanted", 1.0, 1.0, now);
+        let mut user_ratings = HashMap::from([("shared".to_string(), Feedback { preference: 1.0, confidence: 1.0 })]);
+        
+        let mut negative_feedback = NegativeFeedback::default();
+        negative_feedback.add(&NegativeTarget::Video("unwanted".to_string()), now);
+        let negative = Feedback { preference: -1.0, confidence: 5.0 };
+        negative_feedback.add_negatives(&mut user_ratings, negative, &storage).unwrap();
+        
+        assert_eq!(recommend(&storage, &user_ratings, now), vec!["b"]);
+    }
+}
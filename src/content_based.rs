@@ -1,55 +1,57 @@
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
-use rand::{rng, Rng};
-use crate::{User, Video};
+use std::io;
+use chrono::{DateTime, Utc};
+use crate::randomness::Jitter;
+use crate::storage::Storage;
+use crate::User;
 
 pub fn content_based_recommendations(
-    user_id: &str,
+    user: Option<&User>,
     count: usize,
-    users: &Arc<Mutex<HashMap<String, User>>>,
-    videos: &Arc<Mutex<HashMap<String, Video>>>
-) -> Vec<(String, f64)> {
+    storage: &dyn Storage,
+    jitter: &mut Jitter,
+    now: DateTime<Utc>
+) -> io::Result<Vec<(String, f64)>> {
     let mut recommendations = Vec::new();
-    let mut rng = rng();
     
-    let users_guard = users.lock().unwrap();
-    let user_opt = users_guard.get(user_id);
-    
-    let videos_guard = videos.lock().unwrap();
-    
-    
-    if let Some(user) = user_opt {
+    if let Some(user) = user {
         let watched_videos: HashSet<_> = user.watch_history.iter()
             .map(|event| &event.video_id)
             .collect();
         
-        for (video_id, video) in videos_guard.iter() {
-            if watched_videos.contains(video_id) {
-                continue;
-            }
-            
-            let mut score = 0.0;
-            
-            for category in &video.categories {
-                if let Some(preference) = user.content_preferences.get(category) {
-                    score += preference * (0.8 + rng.random::<f64>() * 0.4);
-                }
+        let mut candidates = Vec::new();
+        storage.for_each_video(&mut |video| {
+            if watched_videos.contains(&video.id) {
+                return;
             }
             
-            if score == 0.0 {
-                score = rng.random_range(0.1..0.3);
-            }
+            let preferences: Vec<f64> = video.categories.iter()
+                .filter_map(|category| user.content_preferences.get(category).copied())
+                .collect();
             
-            let video_age_days = (chrono::Utc::now() - video.upload_date).num_days();
+            let video_age_days = (now - video.upload_date).num_days();
             let recency_boost = if video_age_days < 30 {
                 0.2 * (30 - video_age_days) as f64 / 30.0
             } else {
                 0.0
             };
             
-            score += recency_boost;
+            candidates.push((video.id.clone(), preferences, recency_boost));
+        })?;
+        
+        // Storage iterates in no particular order, so videos are put in a fixed
+        // order before drawing any jitter.
+        candidates.sort_by(|a, b| a.0.cmp(&b.0));
+        for (video_id, preferences, recency_boost) in candidates {
+            let mut score: f64 = preferences.iter()
+                .map(|preference| preference * (0.8 + jitter.unit() * 0.4))
+                .sum();
+            
+            if score == 0.0 {
+                score = jitter.between(0.1, 0.3);
+            }
             
-            recommendations.push((video_id.clone(), score));
+            recommendations.push((video_id, score + recency_boost));
         }
     } else {
         let dummy_videos = vec![
@@ -58,19 +60,20 @@ pub fn content_based_recommendations(
         ];
         
         for video_id in dummy_videos {
-            recommendations.push((video_id.to_string(), rng.random_range(0.2..0.7)));
+            recommendations.push((video_id.to_string(), jitter.between(0.2, 0.7)));
         }
     }
     
-    recommendations.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    recommendations.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then_with(|| a.0.cmp(&b.0)));
     
     let mut seen = HashSet::new();
     recommendations.retain(|(id, _)| seen.insert(id.clone()));
     
     recommendations.truncate(count);
-    recommendations
+    Ok(recommendations)
 }
 
+#[allow(dead_code)]
 fn calculate_content_similarity(
     user_preferences: &HashMap<String, f64>,
     video_categories: &[String],
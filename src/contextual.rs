@@ -0,0 +1,416 @@
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::TAU;
+use std::io;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::evaluation::Source;
+use crate::impressions::{Impression, LogEntry, Outcome, OutcomeKind};
+use crate::storage::Storage;
+use crate::Candidate;
+
+const CONTEXT_FEATURES: usize = 11;
+const ITEM_FEATURES: usize = 12;
+const DIMENSIONS: usize = CONTEXT_FEATURES * ITEM_FEATURES;
+const RECENT_REQUESTS: usize = 10_000;
+
+const SCORED_SOURCES: [Source; 5] = [
+    Source::Collaborative,
+    Source::ContentBased,
+    Source::Popularity,
+    Source::Temporal,
+    Source::Engagement,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Device {
+    Desktop,
+    Mobile,
+    Tablet,
+    Tv,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Surface {
+    Home,
+    Search,
+    UpNext,
+    Subscriptions,
+    Notification,
+}
+
+// What the client knows about the request. Every field is optional; missing
+// ones simply contribute nothing to the context features.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RequestContext {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<Device>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_hour: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub surface: Option<Surface>,
+}
+
+impl RequestContext {
+    pub fn is_valid(&self) -> bool {
+        self.local_hour.is_none_or(|hour| hour < 24)
+    }
+    
+    // Desktop and the home surface are the baselines the one-hot columns are
+    // measured against, and the hour goes round the clock.
+    fn features(&self) -> Vec<f64> {
+        let flag = |on: bool| if on { 1.0 } else { 0.0 };
+        let angle = self.local_hour.map(|hour| TAU * hour as f64 / 24.0);
+        
+        vec![
+            1.0,
+            flag(self.device == Some(Device::Mobile)),
+            flag(self.device == Some(Device::Tablet)),
+            flag(self.device == Some(Device::Tv)),
+            angle.map_or(0.0, f64::sin),
+            angle.map_or(0.0, f64::cos),
+            self.session_seconds.map_or(0.0, |secs| (secs as f64 / 3600.0).min(1.0)),
+            flag(self.surface == Some(Surface::Search)),
+            flag(self.surface == Some(Surface::UpNext)),
+            flag(self.surface == Some(Surface::Subscriptions)),
+            flag(self.surface == Some(Surface::Notification)),
+        ]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ContextualConfig {
+    pub enabled: bool,
+    pub alpha: f64,
+    pub pool: usize,
+}
+
+impl Default for ContextualConfig {
+    fn default() -> Self {
+        ContextualConfig {
+            enabled: true,
+            alpha: 0.25,
+            pool: 3,
+        }
+    }
+}
+
+// Ridge regression on the outer product of context and item features, kept
+// as the inverse design matrix so each observation is a rank-one
+// Sherman-Morrison update rather than a fresh inversion.
+struct LinUcb {
+    a_inv: Vec<f64>,
+    b: Vec<f64>,
+    pulls: u64,
+    rewards: u64,
+}
+
+impl LinUcb {
+    fn new() -> Self {
+        let mut a_inv = vec![0.0; DIMENSIONS * DIMENSIONS];
+        for i in 0..DIMENSIONS {
+            a_inv[i * DIMENSIONS + i] = 1.0;
+        }
+        LinUcb {
+            a_inv,
+            b: vec![0.0; DIMENSIONS],
+            pulls: 0,
+            rewards: 0,
+        }
+    }
+    
+    fn times(&self, x: &[f64]) -> Vec<f64> {
+        self.a_inv.chunks(DIMENSIONS)
+            .map(|row| row.iter().zip(x).map(|(a, x)| a * x).sum())
+            .collect()
+    }
+    
+    fn pull(&mut self, x: &[f64]) {
+        let ax = self.times(x);
+        let denominator = 1.0 + dot(x, &ax);
+        for i in 0..DIMENSIONS {
+            for j in 0..DIMENSIONS {
+                self.a_inv[i * DIMENSIONS + j] -= ax[i] * ax[j] / denominator;
+            }
+        }
+        self.pulls += 1;
+    }
+    
+    // The pull already counted the observation as a zero reward, so a watch
+    // only has to add its features to the response vector.
+    fn reward(&mut self, x: &[f64]) {
+        for (b, x) in self.b.iter_mut().zip(x) {
+            *b += x;
+        }
+        self.rewards += 1;
+    }
+    
+    fn theta(&self) -> Vec<f64> {
+        self.times(&self.b)
+    }
+    
+    fn upper_bound(&self, theta: &[f64], x: &[f64], alpha: f64) -> f64 {
+        dot(theta, x) + alpha * dot(x, &self.times(x)).max(0.0).sqrt()
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn cross(context: &[f64], item: &[f64]) -> Vec<f64> {
+    context.iter().flat_map(|c| item.iter().map(move |v| c * v)).collect()
+}
+
+struct LoggedRequest {
+    context: Vec<f64>,
+    items: HashMap<String, Vec<f64>>,
+}
+
+#[derive(Default)]
+struct RecentRequests {
+    order: VecDeque<String>,
+    by_id: HashMap<String, LoggedRequest>,
+}
+
+// Re-ranks a larger candidate pool for requests that carry a context. Every
+// shown slot is a pull and an attributed watch is its reward; features are
+// stored on the impression so the model can be rebuilt from the log.
+pub struct ContextualBandit {
+    config: ContextualConfig,
+    model: Mutex<LinUcb>,
+    recent: Mutex<RecentRequests>,
+}
+
+impl ContextualBandit {
+    pub fn new(config: ContextualConfig) -> Self {
+        ContextualBandit {
+            config,
+            model: Mutex::new(LinUcb::new()),
+            recent: Mutex::new(RecentRequests::default()),
+        }
+    }
+    
+    // How many candidates to fetch for a list of `count`.
+    pub fn pool_size(&self, count: usize, context: Option<&RequestContext>) -> usize {
+        match context {
+            Some(_) if self.config.enabled => count * self.config.pool.max(1),
+            _ => count,
+        }
+    }
+    
+    // Item features describe the candidate in the pool it was drawn from:
+    // its blended and per-source scores relative to the best in the pool, the
+    // video's engagement, and how well it fits the user's habits.
+    fn item_features(
+        candidates: &[Candidate],
+        user_id: &str,
+        context: &RequestContext,
+        storage: &dyn Storage,
+    ) -> io::Result<Vec<Vec<f64>>> {
+        let patterns = storage.get_user(user_id)?.map(|user| user.interaction_patterns);
+        let videos = candidates.iter()
+            .map(|candidate| storage.get_video(&candidate.video_id))
+            .collect::<io::Result<Vec<_>>>()?;
+        
+        let relative = |value: f64, max: f64| if max > 0.0 { value / max } else { 0.0 };
+        let top_score = candidates.iter().map(|candidate| candidate.score).fold(0.0, f64::max);
+        let top_source: Vec<f64> = SCORED_SOURCES.iter()
+            .map(|source| candidates.iter()
+                .filter_map(|candidate| candidate.source_scores.get(source).copied())
+                .fold(0.0, f64::max))
+            .collect();
+        let max_log_views = videos.iter().flatten()
+            .map(|video| (video.metrics.views as f64).ln_1p())
+            .fold(0.0, f64::max);
+        
+        let hour_preference = match (&patterns, context.local_hour) {
+            (Some(patterns), Some(hour)) => {
+                let max = patterns.time_of_day_preferences.values().copied().fold(0.0, f64::max);
+                relative(patterns.time_of_day_preferences.get(&hour).copied().unwrap_or(0.0), max)
+            }
+            _ => 0.0,
+        };
+        
+        Ok(candidates.iter().zip(&videos).map(|(candidate, video)| {
+            let mut features = Vec::with_capacity(ITEM_FEATURES);
+            features.push(1.0);
+            features.push(relative(candidate.score, top_score));
+            for (source, top) in SCORED_SOURCES.iter().zip(&top_source) {
+                features.push(relative(candidate.source_scores.get(source).copied().unwrap_or(0.0), *top));
+            }
+            
+            match video {
+                Some(video) => {
+                    features.push(relative(video.metrics.likes as f64, video.metrics.views as f64).min(1.0));
+                    features.push(video.metrics.completion_rate);
+                    features.push(patterns.as_ref().map_or(0.0, |patterns| {
+                        1.0 - (patterns.avg_watch_percentage - video.metrics.avg_watch_percentage).abs().min(1.0)
+                    }));
+                    features.push(relative((video.metrics.views as f64).ln_1p(), max_log_views));
+                }
+                None => features.extend([0.0; 4]),
+            }
+            features.push(hour_preference);
+            features
+        }).collect())
+    }
+    
+    // Keeps the `count` candidates with the highest upper confidence bound on
+    // their watch rate, added to their relative blended score so an untrained
+    // model leaves the blend's order mostly intact.
+    pub fn rerank(
+        &self,
+        user_id: &str,
+        context: &RequestContext,
+        candidates: Vec<Candidate>,
+        count: usize,
+        storage: &dyn Storage,
+    ) -> io::Result<Vec<(Candidate, Vec<f64>)>> {
+        if !self.config.enabled || candidates.is_empty() {
+            return Ok(candidates.into_iter().take(count).map(|candidate| (candidate, Vec::new())).collect());
+        }
+        
+        let context_features = context.features();
+        let items = Self::item_features(&candidates, user_id, context, storage)?;
+        
+        let model = self.model.lock().unwrap();
+        let theta = model.theta();
+        let mut scored: Vec<_> = candidates.into_iter().zip(items)
+            .map(|(candidate, item)| {
+                let bound = model.upper_bound(&theta, &cross(&context_features, &item), self.config.alpha);
+                (item[1] + bound, candidate, item)
+            })
+            .collect();
+        drop(model);
+        
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.video_id.cmp(&b.1.video_id)));
+        Ok(scored.into_iter().take(count).map(|(_, candidate, item)| (candidate, item)).collect())
+    }
+    
+    pub fn replay(&self, entries: &[LogEntry]) {
+        for entry in entries {
+            match entry {
+                LogEntry::Impression(impression) => self.record_impression(impression),
+                LogEntry::Outcome(outcome) => self.record_outcome(outcome),
+            }
+        }
+    }
+    
+    pub fn record_impression(&self, impression: &Impression) {
+        let Some(context) = &impression.context else {
+            return;
+        };
+        let items: HashMap<String, Vec<f64>> = impression.items.iter()
+            .filter(|item| item.features.len() == ITEM_FEATURES)
+            .map(|item| (item.video_id.clone(), item.features.clone()))
+            .collect();
+        if items.is_empty() {
+            return;
+        }
+        
+        let context = context.features();
+        let mut model = self.model.lock().unwrap();
+        for item in items.values() {
+            model.pull(&cross(&context, item));
+        }
+        drop(model);
+        
+        let mut recent = self.recent.lock().unwrap();
+        recent.order.push_back(impression.request_id.clone());
+        recent.by_id.insert(impression.request_id.clone(), LoggedRequest { context, items });
+        while recent.order.len() > RECENT_REQUESTS {
+            if let Some(oldest) = recent.order.pop_front() {
+                recent.by_id.remove(&oldest);
+            }
+        }
+    }
+    
+    pub fn record_outcome(&self, outcome: &Outcome) {
+        if outcome.kind != OutcomeKind::Watch {
+            return;
+        }
+        let x = {
+            let recent = self.recent.lock().unwrap();
+            let Some(request) = recent.by_id.get(&outcome.request_id) else {
+                return;
+            };
+            let Some(item) = request.items.get(&outcome.video_id) else {
+                return;
+            };
+            cross(&request.context, item)
+        };
+        self.model.lock().unwrap().reward(&x);
+    }
+    
+    pub fn stats(&self) -> serde_json::Value {
+        let model = self.model.lock().unwrap();
+        serde_json::json!({
+            "enabled": self.config.enabled,
+            "alpha": self.config.alpha,
+            "pool": self.config.pool,
+            "dimensions": DIMENSIONS,
+            "pulls": model.pulls,
+            "rewards": model.rewards,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+    
+    #[test]
+    fn linucb_update_matches_ridge_regression() {
+        let mut x = vec![0.0; DIMENSIONS];
+        x[0] = 1.0;
+        
+        // With A = I + x x' and b = x, theta = A^-1 b = x / 2 and the
+        // confidence width is sqrt(x' A^-1 x) = sqrt(1 / 2).
+        let mut model = LinUcb::new();
+        model.pull(&x);
+        model.reward(&x);
+        let theta = model.theta();
+        assert_close(theta[0], 0.5);
+        assert_close(model.upper_bound(&theta, &x, 1.0), 0.5 + 0.5f64.sqrt());
+        
+        model.pull(&x);
+        assert_close(model.theta()[0], 1.0 / 3.0);
+        assert_eq!((model.pulls, model.rewards), (2, 1));
+    }
+    
+    #[test]
+    fn sherman_morrison_keeps_the_exact_inverse() {
+        let xs: Vec<Vec<f64>> = (0..3)
+            .map(|k| (0..DIMENSIONS).map(|i| ((i * 7 + k * 3) % 5) as f64 / 4.0 - 0.5).collect())
+            .collect();
+        let mut model = LinUcb::new();
+        let mut a = vec![0.0; DIMENSIONS * DIMENSIONS];
+        for i in 0..DIMENSIONS {
+            a[i * DIMENSIONS + i] = 1.0;
+        }
+        for x in &xs {
+            model.pull(x);
+            for i in 0..DIMENSIONS {
+                for j in 0..DIMENSIONS {
+                    a[i * DIMENSIONS + j] += x[i] * x[j];
+                }
+            }
+        }
+        
+        for i in 0..DIMENSIONS {
+            for j in 0..DIMENSIONS {
+                let product: f64 = (0..DIMENSIONS).map(|k| a[i * DIMENSIONS + k] * model.a_inv[k * DIMENSIONS + j]).sum();
+                assert_close(product, if i == j { 1.0 } else { 0.0 });
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::implicit::Feedback;
+use crate::storage::Rating;
+use crate::User;
+
+// Ratings whose confidence has faded below this are treated as forgotten.
+const FORGOTTEN_RATING: f64 = 0.01;
+
+// How far one event moves a category preference towards its adjustment. The
+// short-term profile moves much further, so it follows the latest interests.
+const LONG_TERM_RATE: f64 = 0.05;
+const SHORT_TERM_RATE: f64 = 0.3;
+
+#[derive(Clone, Debug)]
+pub struct DecayConfig {
+    pub preference_half_life: Option<Duration>,
+    pub rating_half_life: Option<Duration>,
+    pub short_term_half_life: Option<Duration>,
+    pub short_term_weight: f64,
+}
+
+impl Default for DecayConfig {
+    fn default() -> Self {
+        DecayConfig {
+            preference_half_life: Some(Duration::days(30)),
+            rating_half_life: Some(Duration::days(60)),
+            short_term_half_life: Some(Duration::hours(24)),
+            short_term_weight: 0.5,
+        }
+    }
+}
+
+// Share of a value left after the time since `since`. No half-life, no
+// timestamp or a timestamp in the future leave it as it is.
+pub fn factor(half_life: Option<Duration>, since: Option<DateTime<Utc>>, now: DateTime<Utc>) -> f64 {
+    match (half_life, since) {
+        (Some(half_life), Some(since)) if now > since && half_life > Duration::zero() => {
+            0.5f64.powf((now - since).num_milliseconds() as f64 / half_life.num_milliseconds() as f64)
+        }
+        _ => 1.0,
+    }
+}
+
+fn scaled(values: &HashMap<String, f64>, factor: f64) -> HashMap<String, f64> {
+    values.iter().map(|(key, value)| (key.clone(), value * factor)).collect()
+}
+
+fn latest(updated_at: Option<DateTime<Utc>>, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    updated_at.map_or(timestamp, |updated_at| updated_at.max(timestamp))
+}
+
+// Stored preferences and ratings are as of their last update. Everything that
+// ranks with them reads them through here, decayed to the time of reading, so
+// interests fade for users who have stopped interacting too.
+impl DecayConfig {
+    pub fn long_term(&self, user: &User, now: DateTime<Utc>) -> HashMap<String, f64> {
+        scaled(&user.content_preferences, factor(self.preference_half_life, user.preferences_updated_at, now))
+    }
+    
+    pub fn short_term(&self, user: &User, now: DateTime<Utc>) -> HashMap<String, f64> {
+        if self.short_term_half_life.is_none() {
+            return HashMap::new();
+        }
+        scaled(&user.short_term_preferences, factor(self.short_term_half_life, user.short_term_updated_at, now))
+    }
+    
+    // The long-term preferences with the short-term profile added on top.
+    pub fn interests(&self, user: &User, now: DateTime<Utc>) -> HashMap<String, f64> {
+        let mut interests = self.long_term(user, now);
+        for (category, preference) in self.short_term(user, now) {
+            *interests.entry(category).or_insert(0.0) += self.short_term_weight * preference;
+        }
+        interests
+    }
+    
+    // Decaying the value and confidence alike leaves the preference as it is
+    // and only lowers how much it is trusted. Forgotten ratings give `None`.
+    pub fn feedback(&self, rating: &Rating, now: DateTime<Utc>) -> Option<Feedback> {
+        let feedback = Feedback {
+            preference: rating.preference(),
+            confidence: rating.confidence * factor(self.rating_half_life, Some(rating.updated_at), now),
+        };
+        (feedback.confidence >= FORGOTTEN_RATING).then_some(feedback)
+    }
+    
+    pub fn ratings(&self, ratings: HashMap<String, Rating>, now: DateTime<Utc>) -> HashMap<String, Feedback> {
+        ratings.into_iter()
+            .filter_map(|(video_id, rating)| self.feedback(&rating, now).map(|feedback| (video_id, feedback)))
+            .collect()
+    }
+    
+    pub fn add_feedback(&self, rating: Option<Rating>, feedback: Feedback, timestamp: DateTime<Utc>) -> Rating {
+        let (value, confidence) = rating.map_or((0.0, 0.0), |rating| {
+            let factor = factor(self.rating_half_life, Some(rating.updated_at), timestamp);
+            (rating.value * factor, rating.confidence * factor)
+        });
+        Rating {
+            value: value + feedback.preference * feedback.confidence,
+            confidence: confidence + feedback.confidence,
+            updated_at: latest(rating.map(|rating| rating.updated_at), timestamp),
+        }
+    }
+    
+    // Decays both profiles to `timestamp` before moving the video's
+    // categories towards the adjustment.
+    pub fn update_preferences(&self, user: &mut User, categories: &[String], adjustment: f64, timestamp: DateTime<Utc>) {
+        user.content_preferences = self.long_term(user, timestamp);
+        user.preferences_updated_at = Some(latest(user.preferences_updated_at, timestamp));
+        for category in categories {
+            let entry = user.content_preferences.entry(category.clone()).or_insert(0.0);
+            *entry = *entry * (1.0 - LONG_TERM_RATE) + adjustment * LONG_TERM_RATE;
+        }
+        
+        if self.short_term_half_life.is_some() {
+            user.short_term_preferences = self.short_term(user, timestamp);
+            user.short_term_updated_at = Some(latest(user.short_term_updated_at, timestamp));
+            for category in categories {
+                let entry = user.short_term_preferences.entry(category.clone()).or_insert(0.0);
+                *entry = *entry * (1.0 - SHORT_TERM_RATE) + adjustment * SHORT_TERM_RATE;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+    
+    #[test]
+    fn factor_halves_every_half_life() {
+        let now = Utc::now();
+        let half_life = Some(Duration::days(30));
+        
+        assert_close(factor(half_life, Some(now), now), 1.0);
+        assert_close(factor(half_life, Some(now - Duration::days(15)), now), 0.5f64.sqrt());
+        assert_close(factor(half_life, Some(now - Duration::days(30)), now), 0.5);
+        assert_close(factor(half_life, Some(now - Duration::days(90)), now), 0.125);
+    }
+    
+    #[test]
+    fn factor_leaves_values_without_decay() {
+        let now = Utc::now();
+        let earlier = Some(now - Duration::days(30));
+        
+        assert_eq!(factor(None, earlier, now), 1.0);
+        assert_eq!(factor(Some(Duration::days(30)), None, now), 1.0);
+        assert_eq!(factor(Some(Duration::zero()), earlier, now), 1.0);
+        assert_eq!(factor(Some(Duration::days(30)), Some(now + Duration::days(1)), now), 1.0);
+    }
+    
+    #[test]
+    fn decayed_ratings_keep_their_preference() {
+        let now = Utc::now();
+        let config = DecayConfig::default();
+        let ratings = HashMap::from([("video1".to_string(), Rating {
+            value: 1.5,
+            confidence: 2.0,
+            updated_at: now - Duration::days(60),
+        })]);
+        
+        let feedback = config.ratings(ratings, now)["video1"];
+        assert_close(feedback.preference, 0.75);
+        assert_close(feedback.confidence, 1.0);
+    }
+}
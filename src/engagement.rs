@@ -1,23 +1,19 @@
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::io;
 
-use crate::{User, Video};
+use crate::storage::Storage;
 
 pub fn get_engagement_recommendations(
     user_id: &str,
     count: usize,
-    users: &Arc<Mutex<HashMap<String, User>>>,
-    videos: &Arc<Mutex<HashMap<String, Video>>>
-) -> Vec<(String, f64)> {
+    storage: &dyn Storage
+) -> io::Result<Vec<(String, f64)>> {
     let mut recommendations = Vec::new();
     let mut scored_videos: HashMap<String, f64> = HashMap::new();
     
-    let user_patterns = {
-        let users_guard = users.lock().unwrap();
-        match users_guard.get(user_id) {
-            Some(user) => user.interaction_patterns.clone(),
-            None => return recommendations,
-        }
+    let user_patterns = match storage.get_user(user_id)? {
+        Some(user) => user.interaction_patterns,
+        None => return Ok(recommendations),
     };
     
     let user_avg_watch_pct = user_patterns.avg_watch_percentage;
@@ -25,9 +21,7 @@ pub fn get_engagement_recommendations(
     let user_like_ratio = user_patterns.like_to_view_ratio;
     let user_share_frequency = user_patterns.share_frequency;
     
-    let videos_guard = videos.lock().unwrap();
-    
-    for (video_id, video) in videos_guard.iter() {
+    storage.for_each_video(&mut |video| {
         let mut score = 0.0;
         
         let watch_time_compatibility = 1.0 - (user_avg_watch_pct - video.metrics.avg_watch_percentage).abs();
@@ -68,16 +62,18 @@ pub fn get_engagement_recommendations(
         score = score.min(5.0);
         
         if score > 0.0 {
-            scored_videos.insert(video_id.clone(), score);
+            scored_videos.insert(video.id.clone(), score);
         }
-    }
+    })?;
     
     let mut scored_list: Vec<(String, f64)> = scored_videos.into_iter().collect();
-    scored_list.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    scored_list.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.cmp(&b.0))
+    });
     
     recommendations = scored_list.into_iter().take(count).collect();
     
-    recommendations
+    Ok(recommendations)
 }
 
 pub fn get_dummy_engagement_recommendations(_user_id: &str, count: usize) -> Vec<(String, f64)> {
@@ -0,0 +1,403 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::event_log::EngineEvent;
+use crate::storage::{MemoryStorage, Storage};
+use crate::{EngineConfig, RecommendationEngine, User, Video, WatchEvent};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Blend,
+    Collaborative,
+    ContentBased,
+    Popularity,
+    Temporal,
+    Engagement,
+    Session,
+    Sequence,
+    Exploration,
+}
+
+impl Source {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "blend" => Some(Source::Blend),
+            "collaborative" => Some(Source::Collaborative),
+            "content_based" => Some(Source::ContentBased),
+            "popularity" => Some(Source::Popularity),
+            "temporal" => Some(Source::Temporal),
+            "engagement" => Some(Source::Engagement),
+            "session" => Some(Source::Session),
+            "sequence" => Some(Source::Sequence),
+            "exploration" => Some(Source::Exploration),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EvaluationConfig {
+    pub source: Source,
+    pub k: usize,
+    pub test_fraction: f64,
+}
+
+impl Default for EvaluationConfig {
+    fn default() -> Self {
+        EvaluationConfig {
+            source: Source::Blend,
+            k: 10,
+            test_fraction: 0.2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationReport {
+    pub source: Source,
+    pub k: usize,
+    pub test_fraction: f64,
+    pub cutoff: Option<DateTime<Utc>>,
+    pub train_events: usize,
+    pub test_events: usize,
+    pub users_evaluated: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub map: f64,
+    pub ndcg: f64,
+    pub mrr: f64,
+    pub hit_rate: f64,
+    pub coverage: f64,
+    pub novelty: f64,
+    pub diversity: f64,
+    pub evaluated_at: DateTime<Utc>,
+}
+
+// Watch histories split at a single point in time: everything before the
+// cutoff is replayed into a fresh engine, and the videos each user went on to
+// watch afterwards (and had not seen before) are the ones we hope to retrieve.
+pub struct EvaluationSplit {
+    test_fraction: f64,
+    cutoff: Option<DateTime<Utc>>,
+    users: Vec<User>,
+    videos: Vec<Video>,
+    train: UserWatches,
+    test: HashMap<String, HashSet<String>>,
+    test_events: usize,
+    train_popularity: HashMap<String, usize>,
+    train_users: usize,
+}
+
+type UserWatches = Vec<(String, WatchEvent)>;
+
+// Every user's watches, oldest first.
+fn watch_events(storage: &dyn Storage) -> io::Result<(Vec<User>, UserWatches)> {
+    let mut users = Vec::new();
+    let mut events = Vec::new();
+    storage.for_each_user(&mut |user| {
+        for watch in &user.watch_history {
+            events.push((user.id.clone(), watch.clone()));
+        }
+        users.push(user.clone());
+    })?;
+    events.sort_by_key(|(_, watch)| watch.timestamp);
+    Ok((users, events))
+}
+
+fn split_at_cutoff(
+    events: UserWatches,
+    test_fraction: f64,
+) -> (Option<DateTime<Utc>>, UserWatches, UserWatches) {
+    let train_len = ((events.len() as f64) * (1.0 - test_fraction)).round() as usize;
+    let cutoff = events.get(train_len).map(|(_, watch)| watch.timestamp);
+    let (train, test) = events.into_iter()
+        .partition(|(_, watch)| cutoff.is_none_or(|cutoff| watch.timestamp < cutoff));
+    (cutoff, train, test)
+}
+
+impl EvaluationSplit {
+    pub fn new(storage: &dyn Storage, test_fraction: f64) -> io::Result<Self> {
+        let (users, events) = watch_events(storage)?;
+        Self::from_events(storage, users, events, test_fraction)
+    }
+    
+    // Drops the period `new` holds out for testing and splits what is left the
+    // same way, so tuning never scores configurations on the test watches.
+    pub fn validation(storage: &dyn Storage, test_fraction: f64) -> io::Result<Self> {
+        let (users, events) = watch_events(storage)?;
+        let (_, train, _) = split_at_cutoff(events, test_fraction);
+        Self::from_events(storage, users, train, test_fraction)
+    }
+    
+    fn from_events(
+        storage: &dyn Storage,
+        users: Vec<User>,
+        events: UserWatches,
+        test_fraction: f64,
+    ) -> io::Result<Self> {
+        let mut videos = Vec::new();
+        storage.for_each_video(&mut |video| videos.push(video.clone()))?;
+        
+        let (cutoff, train, test_events) = split_at_cutoff(events, test_fraction);
+        
+        let mut seen: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for (user_id, watch) in &train {
+            seen.entry(user_id).or_default().insert(&watch.video_id);
+        }
+        
+        let mut train_popularity = HashMap::new();
+        for videos in seen.values() {
+            for video_id in videos {
+                *train_popularity.entry(video_id.to_string()).or_insert(0) += 1;
+            }
+        }
+        
+        let mut test: HashMap<String, HashSet<String>> = HashMap::new();
+        for (user_id, watch) in &test_events {
+            let Some(user_seen) = seen.get(user_id.as_str()) else {
+                continue;
+            };
+            if !user_seen.contains(watch.video_id.as_str()) {
+                test.entry(user_id.clone()).or_default().insert(watch.video_id.clone());
+            }
+        }
+        
+        Ok(EvaluationSplit {
+            test_fraction,
+            cutoff,
+            train_users: seen.len(),
+            users,
+            videos,
+            test,
+            test_events: test_events.len(),
+            train,
+            train_popularity,
+        })
+    }
+    
+    // Aggregate video metrics and interaction patterns are not versioned over
+    // time, so the training engine starts every video and user from zero and
+    // rebuilds them from the training watches; otherwise test-period views
+    // would leak into popularity and temporal matching.
+    fn train_engine(&self, engine_config: &EngineConfig) -> io::Result<RecommendationEngine> {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        
+        for video in &self.videos {
+            let mut video = video.clone();
+            video.metrics = Default::default();
+            storage.put_video(video)?;
+        }
+        for user in &self.users {
+            let mut user = user.clone();
+            user.watch_history.clear();
+            user.content_preferences.clear();
+            user.preferences_updated_at = None;
+            user.short_term_preferences.clear();
+            user.short_term_updated_at = None;
+            user.interaction_patterns = Default::default();
+            storage.put_user(user)?;
+        }
+        
+        let engine = RecommendationEngine::with_storage(storage, engine_config.clone());
+        for (user_id, watch) in &self.train {
+            engine.apply_event(&EngineEvent::Watch {
+                user_id: user_id.clone(),
+                video_id: watch.video_id.clone(),
+                watch_duration: watch.watch_duration,
+                interactions: watch.interactions.clone(),
+                timestamp: watch.timestamp,
+            })?;
+        }
+        Ok(engine)
+    }
+    
+    pub fn evaluate(&self, engine_config: &EngineConfig, source: Source, k: usize) -> io::Result<EvaluationReport> {
+        let engine = self.train_engine(engine_config)?;
+        let categories: HashMap<&str, HashSet<&str>> = self.videos.iter()
+            .map(|video| (video.id.as_str(), video.categories.iter().map(String::as_str).collect()))
+            .collect();
+        
+        let mut totals = MetricTotals::default();
+        let mut recommended_catalog = HashSet::new();
+        
+        let mut test_users: Vec<_> = self.test.iter().collect();
+        test_users.sort_by(|a, b| a.0.cmp(b.0));
+        
+        for (user_id, relevant) in test_users {
+            let ranked = engine.source_recommendations(source, user_id, k)?;
+            for video_id in &ranked {
+                if categories.contains_key(video_id.as_str()) {
+                    recommended_catalog.insert(video_id.clone());
+                }
+                let popularity = self.train_popularity.get(video_id).copied().unwrap_or(0);
+                totals.novelty += -((popularity + 1) as f64 / (self.train_users + 1) as f64).log2();
+                totals.recommended += 1;
+            }
+            
+            totals.add_ranking(&ranked, relevant, k);
+            if let Some(diversity) = intra_list_diversity(&ranked, &categories) {
+                totals.diversity += diversity;
+                totals.diverse_lists += 1;
+            }
+        }
+        
+        let users = totals.users as f64;
+        let mean = |sum: f64, count: f64| if count > 0.0 { sum / count } else { 0.0 };
+        
+        Ok(EvaluationReport {
+            source,
+            k,
+            test_fraction: self.test_fraction,
+            cutoff: self.cutoff,
+            train_events: self.train.len(),
+            test_events: self.test_events,
+            users_evaluated: totals.users,
+            precision: mean(totals.precision, users),
+            recall: mean(totals.recall, users),
+            map: mean(totals.average_precision, users),
+            ndcg: mean(totals.ndcg, users),
+            mrr: mean(totals.reciprocal_rank, users),
+            hit_rate: mean(totals.hits, users),
+            coverage: mean(recommended_catalog.len() as f64, self.videos.len() as f64),
+            novelty: mean(totals.novelty, totals.recommended as f64),
+            diversity: mean(totals.diversity, totals.diverse_lists as f64),
+            evaluated_at: Utc::now(),
+        })
+    }
+}
+
+pub fn evaluate(storage: &dyn Storage, engine_config: &EngineConfig, config: &EvaluationConfig) -> io::Result<EvaluationReport> {
+    EvaluationSplit::new(storage, config.test_fraction)?.evaluate(engine_config, config.source, config.k)
+}
+
+#[derive(Default)]
+struct MetricTotals {
+    users: usize,
+    precision: f64,
+    recall: f64,
+    average_precision: f64,
+    ndcg: f64,
+    reciprocal_rank: f64,
+    hits: f64,
+    novelty: f64,
+    recommended: usize,
+    diversity: f64,
+    diverse_lists: usize,
+}
+
+impl MetricTotals {
+    fn add_ranking(&mut self, ranked: &[String], relevant: &HashSet<String>, k: usize) {
+        let mut hits = 0;
+        let mut precision_sum = 0.0;
+        let mut dcg = 0.0;
+        let mut first_hit = None;
+        
+        for (index, video_id) in ranked.iter().take(k).enumerate() {
+            if relevant.contains(video_id) {
+                hits += 1;
+                precision_sum += hits as f64 / (index + 1) as f64;
+                dcg += 1.0 / ((index + 2) as f64).log2();
+                first_hit.get_or_insert(index + 1);
+            }
+        }
+        
+        let ideal_hits = relevant.len().min(k);
+        let idcg: f64 = (0..ideal_hits).map(|index| 1.0 / ((index + 2) as f64).log2()).sum();
+        
+        self.users += 1;
+        self.precision += hits as f64 / k as f64;
+        self.recall += hits as f64 / relevant.len() as f64;
+        self.average_precision += precision_sum / ideal_hits as f64;
+        self.ndcg += dcg / idcg;
+        self.reciprocal_rank += first_hit.map_or(0.0, |rank| 1.0 / rank as f64);
+        self.hits += if hits > 0 { 1.0 } else { 0.0 };
+    }
+}
+
+// Mean pairwise Jaccard distance between the category sets of the listed
+// videos. Videos outside the catalog are ignored.
+fn intra_list_diversity(ranked: &[String], categories: &HashMap<&str, HashSet<&str>>) -> Option<f64> {
+    let known: Vec<&HashSet<&str>> = ranked.iter()
+        .filter_map(|video_id| categories.get(video_id.as_str()))
+        .collect();
+    if known.len() < 2 {
+        return None;
+    }
+    
+    let mut distance = 0.0;
+    let mut pairs = 0;
+    for (i, a) in known.iter().enumerate() {
+        for b in &known[i + 1..] {
+            let union = a.union(b).count();
+            let similarity = if union > 0 { a.intersection(b).count() as f64 / union as f64 } else { 1.0 };
+            distance += 1.0 - similarity;
+            pairs += 1;
+        }
+    }
+    Some(distance / pairs as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ids(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+    
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+    
+    #[test]
+    fn ranking_metrics_match_hand_computed_values() {
+        // Hits at ranks 2 and 4, with a third relevant video never retrieved.
+        let ranked = ids(&["a", "b", "c", "d"]);
+        let relevant: HashSet<String> = ids(&["b", "d", "e"]).into_iter().collect();
+        
+        let mut totals = MetricTotals::default();
+        totals.add_ranking(&ranked, &relevant, 4);
+        
+        let dcg = 1.0 / 3f64.log2() + 1.0 / 5f64.log2();
+        let idcg = 1.0 + 1.0 / 3f64.log2() + 1.0 / 4f64.log2();
+        assert_eq!(totals.users, 1);
+        assert_close(totals.precision, 0.5);
+        assert_close(totals.recall, 2.0 / 3.0);
+        assert_close(totals.average_precision, (1.0 / 2.0 + 2.0 / 4.0) / 3.0);
+        assert_close(totals.ndcg, dcg / idcg);
+        assert_close(totals.reciprocal_rank, 0.5);
+        assert_close(totals.hits, 1.0);
+    }
+    
+    #[test]
+    fn ranking_metrics_ignore_videos_past_k() {
+        let ranked = ids(&["a", "b", "c"]);
+        let relevant: HashSet<String> = ids(&["c"]).into_iter().collect();
+        
+        let mut totals = MetricTotals::default();
+        totals.add_ranking(&ranked, &relevant, 2);
+        
+        assert_close(totals.precision, 0.0);
+        assert_close(totals.ndcg, 0.0);
+        assert_close(totals.reciprocal_rank, 0.0);
+        assert_close(totals.hits, 0.0);
+    }
+    
+    #[test]
+    fn diversity_is_mean_pairwise_jaccard_distance() {
+        let categories: HashMap<&str, HashSet<&str>> = HashMap::from([
+            ("a", HashSet::from(["music"])),
+            ("b", HashSet::from(["music", "comedy"])),
+            ("c", HashSet::from(["news"])),
+        ]);
+        
+        let diversity = intra_list_diversity(&ids(&["a", "b", "c", "unknown"]), &categories).unwrap();
+        assert_close(diversity, (0.5 + 1.0 + 1.0) / 3.0);
+        assert_eq!(intra_list_diversity(&ids(&["a", "unknown"]), &categories), None);
+    }
+}
@@ -0,0 +1,536 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use crate::{Interaction, User};
+use crate::experiments::VariantTag;
+use crate::negative::NegativeTarget;
+
+const SEGMENT_PREFIX: &str = "events-";
+const SEGMENT_SUFFIX: &str = ".log";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineEvent {
+    Watch {
+        user_id: String,
+        video_id: String,
+        watch_duration: Duration,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        interactions: Vec<Interaction>,
+        timestamp: DateTime<Utc>,
+    },
+    Like {
+        user_id: String,
+        video_id: String,
+        is_like: bool,
+        timestamp: DateTime<Utc>,
+    },
+    Comment {
+        comment_id: String,
+        user_id: String,
+        video_id: String,
+        text: String,
+        timestamp: DateTime<Utc>,
+    },
+    Share {
+        user_id: String,
+        video_id: String,
+        timestamp: DateTime<Utc>,
+    },
+    Subscribe {
+        user_id: String,
+        channel_id: String,
+        timestamp: DateTime<Utc>,
+    },
+    CreateUser {
+        user: Box<User>,
+        timestamp: DateTime<Utc>,
+    },
+    SetTimezone {
+        user_id: String,
+        timezone: Tz,
+        timestamp: DateTime<Utc>,
+    },
+    RecomputePatterns {
+        user_id: String,
+        timestamp: DateTime<Utc>,
+    },
+    NegativeFeedback {
+        user_id: String,
+        target: NegativeTarget,
+        timestamp: DateTime<Utc>,
+    },
+    UndoNegativeFeedback {
+        user_id: String,
+        target: NegativeTarget,
+        timestamp: DateTime<Utc>,
+    },
+}
+
+impl EngineEvent {
+    pub fn user_id(&self) -> &str {
+        match self {
+            EngineEvent::Watch { user_id, .. }
+            | EngineEvent::Like { user_id, .. }
+            | EngineEvent::Comment { user_id, .. }
+            | EngineEvent::Share { user_id, .. }
+            | EngineEvent::Subscribe { user_id, .. }
+            | EngineEvent::SetTimezone { user_id, .. }
+            | EngineEvent::RecomputePatterns { user_id, .. }
+            | EngineEvent::NegativeFeedback { user_id, .. }
+            | EngineEvent::UndoNegativeFeedback { user_id, .. } => user_id,
+            EngineEvent::CreateUser { user, .. } => &user.id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub seq: u64,
+    pub event: EngineEvent,
+    // The experiment variant ranking this user's recommendations when the
+    // event was recorded, kept for offline analysis.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<VariantTag>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    Always,
+    EveryN(u64),
+    Never,
+}
+
+impl FsyncPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "always" => Some(FsyncPolicy::Always),
+            "never" => Some(FsyncPolicy::Never),
+            other => other.strip_prefix("every:")
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .map(FsyncPolicy::EveryN),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EventLogConfig {
+    pub dir: PathBuf,
+    pub fsync: FsyncPolicy,
+    pub max_segment_bytes: u64,
+    pub snapshot_every: u64,
+}
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        EventLogConfig {
+            dir: PathBuf::from("data"),
+            fsync: FsyncPolicy::Always,
+            max_segment_bytes: 16 * 1024 * 1024,
+            snapshot_every: 10_000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<T> {
+    last_seq: u64,
+    state: T,
+}
+
+pub struct EventLog {
+    config: EventLogConfig,
+    writer: File,
+    segment_bytes: u64,
+    next_seq: u64,
+    unsynced: u64,
+    since_snapshot: u64,
+    // Length of the record the last `append` wrote, for `undo_append`.
+    last_record_bytes: u64,
+    // Set when a failed write could not be rolled back, so the segment may
+    // end in a torn record that later appends would be hidden behind.
+    poisoned: bool,
+}
+
+impl EventLog {
+    pub fn open(config: EventLogConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        
+        let snapshot_seq = read_snapshot_seq(&config.dir)?;
+        let segments = list_segments(&config.dir)?;
+        
+        let mut next_seq = snapshot_seq + 1;
+        let mut since_snapshot = 0;
+        if let Some((first_seq, last_path)) = segments.last() {
+            let (last_seq, valid_bytes) = scan_segment(last_path)?;
+            let file = OpenOptions::new().write(true).open(last_path)?;
+            file.set_len(valid_bytes)?;
+            file.sync_all()?;
+            
+            next_seq = next_seq.max(last_seq.map_or(*first_seq, |seq| seq + 1));
+        }
+        for (_, path) in &segments {
+            since_snapshot += read_records(path)?.iter().filter(|r| r.seq > snapshot_seq).count() as u64;
+        }
+        
+        let path = match segments.last() {
+            Some((_, path)) => path.clone(),
+            None => segment_path(&config.dir, next_seq),
+        };
+        let writer = OpenOptions::new().create(true).append(true).open(&path)?;
+        let segment_bytes = writer.metadata()?.len();
+        sync_dir(&config.dir)?;
+        
+        Ok(EventLog {
+            config,
+            writer,
+            segment_bytes,
+            next_seq,
+            unsynced: 0,
+            since_snapshot,
+            last_record_bytes: 0,
+            poisoned: false,
+        })
+    }
+    
+    pub fn append(&mut self, event: &EngineEvent, variant: Option<VariantTag>) -> io::Result<u64> {
+        if self.poisoned {
+            return Err(io::Error::other("event log is poisoned by an earlier failed write"));
+        }
+        if self.segment_bytes >= self.config.max_segment_bytes {
+            self.rotate()?;
+        }
+        
+        let record = LogRecord { seq: self.next_seq, event: event.clone(), variant };
+        let json = serde_json::to_string(&record)?;
+        let line = format!("{:08x} {}\n", checksum(json.as_bytes()), json);
+        
+        if let Err(e) = self.writer.write_all(line.as_bytes()) {
+            self.cut_back();
+            return Err(e);
+        }
+        self.unsynced += 1;
+        
+        let should_sync = match self.config.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::EveryN(n) => self.unsynced >= n,
+            FsyncPolicy::Never => false,
+        };
+        // The caller is told the event failed, so the record must not be left
+        // behind for recovery to replay under a sequence number reused next.
+        if should_sync && let Err(e) = self.sync() {
+            self.unsynced -= 1;
+            self.cut_back();
+            return Err(e);
+        }
+        
+        self.segment_bytes += line.len() as u64;
+        self.last_record_bytes = line.len() as u64;
+        self.next_seq += 1;
+        self.since_snapshot += 1;
+        Ok(record.seq)
+    }
+    
+    // Cuts off whatever part of a failed record made it to the file. A log
+    // that cannot be cut back refuses further appends.
+    fn cut_back(&mut self) {
+        if self.writer.set_len(self.segment_bytes).is_err() {
+            self.poisoned = true;
+        }
+    }
+    
+    // Removes the record the last `append` wrote, for an event that could not
+    // be applied. A log that cannot be cut back refuses further appends.
+    pub fn undo_append(&mut self) -> io::Result<()> {
+        if self.last_record_bytes == 0 {
+            return Err(io::Error::other("no record to undo"));
+        }
+        let start = self.segment_bytes - self.last_record_bytes;
+        if let Err(e) = self.writer.set_len(start).and_then(|()| self.writer.sync_data()) {
+            self.poisoned = true;
+            return Err(e);
+        }
+        self.segment_bytes = start;
+        self.last_record_bytes = 0;
+        self.next_seq -= 1;
+        self.since_snapshot = self.since_snapshot.saturating_sub(1);
+        Ok(())
+    }
+    
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+    
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+    
+    pub fn snapshot_due(&self) -> bool {
+        self.since_snapshot >= self.config.snapshot_every
+    }
+    
+    pub fn replay_after(&self, seq: u64) -> io::Result<Vec<LogRecord>> {
+        let mut records = Vec::new();
+        for (_, path) in list_segments(&self.config.dir)? {
+            records.extend(read_records(&path)?.into_iter().filter(|r| r.seq > seq));
+        }
+        Ok(records)
+    }
+    
+    pub fn load_snapshot<T: DeserializeOwned>(&self) -> io::Result<Option<(u64, T)>> {
+        let path = self.config.dir.join(SNAPSHOT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        
+        let snapshot: Snapshot<T> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        Ok(Some((snapshot.last_seq, snapshot.state)))
+    }
+    
+    pub fn write_snapshot<T: Serialize>(&mut self, state: &T) -> io::Result<()> {
+        self.sync()?;
+        let last_seq = self.last_seq();
+        
+        let tmp_path = self.config.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        {
+            let mut file = File::create(&tmp_path)?;
+            serde_json::to_writer(&mut file, &Snapshot { last_seq, state })?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, self.config.dir.join(SNAPSHOT_FILE))?;
+        sync_dir(&self.config.dir)?;
+        
+        self.compact()
+    }
+    
+    // Drops every segment holding only records at or before the current
+    // sequence number; callers must have persisted that state elsewhere.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let last_seq = self.last_seq();
+        self.rotate()?;
+        
+        for (first_seq, path) in list_segments(&self.config.dir)? {
+            if first_seq <= last_seq {
+                fs::remove_file(path)?;
+            }
+        }
+        sync_dir(&self.config.dir)?;
+        
+        self.since_snapshot = 0;
+        Ok(())
+    }
+    
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sync()?;
+        
+        let path = segment_path(&self.config.dir, self.next_seq);
+        self.writer = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.segment_bytes = self.writer.metadata()?.len();
+        self.last_record_bytes = 0;
+        sync_dir(&self.config.dir)
+    }
+}
+
+fn segment_path(dir: &Path, first_seq: u64) -> PathBuf {
+    dir.join(format!("{}{:020}{}", SEGMENT_PREFIX, first_seq, SEGMENT_SUFFIX))
+}
+
+fn list_segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let first_seq = path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(SEGMENT_PREFIX))
+            .and_then(|name| name.strip_suffix(SEGMENT_SUFFIX))
+            .and_then(|seq| seq.parse::<u64>().ok());
+        if let Some(first_seq) = first_seq {
+            segments.push((first_seq, path));
+        }
+    }
+    segments.sort_by_key(|(first_seq, _)| *first_seq);
+    Ok(segments)
+}
+
+fn read_snapshot_seq(dir: &Path) -> io::Result<u64> {
+    #[derive(Deserialize)]
+    struct SnapshotHeader {
+        last_seq: u64,
+    }
+    
+    let path = dir.join(SNAPSHOT_FILE);
+    if !path.exists() {
+        return Ok(0);
+    }
+    let header: SnapshotHeader = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    Ok(header.last_seq)
+}
+
+fn parse_line(line: &str) -> Option<LogRecord> {
+    let (sum, json) = line.split_once(' ')?;
+    let sum = u32::from_str_radix(sum, 16).ok()?;
+    if sum != checksum(json.as_bytes()) {
+        return None;
+    }
+    serde_json::from_str(json).ok()
+}
+
+// Stops at the first torn or corrupt line; everything after it is considered lost.
+fn read_records(path: &Path) -> io::Result<Vec<LogRecord>> {
+    let mut records = Vec::new();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    
+    while reader.read_line(&mut line)? > 0 {
+        if !line.ends_with('\n') {
+            break;
+        }
+        match parse_line(line.trim_end()) {
+            Some(record) => records.push(record),
+            None => break,
+        }
+        line.clear();
+    }
+    Ok(records)
+}
+
+fn scan_segment(path: &Path) -> io::Result<(Option<u64>, u64)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    let mut last_seq = None;
+    let mut valid_bytes = 0;
+    
+    while reader.read_line(&mut line)? > 0 {
+        if !line.ends_with('\n') {
+            break;
+        }
+        match parse_line(line.trim_end()) {
+            Some(record) => last_seq = Some(record.seq),
+            None => break,
+        }
+        valid_bytes += line.len() as u64;
+        line.clear();
+    }
+    Ok((last_seq, valid_bytes))
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("recommend-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+    
+    fn subscribe(channel_id: &str) -> EngineEvent {
+        EngineEvent::Subscribe {
+            user_id: "user1".to_string(),
+            channel_id: channel_id.to_string(),
+            timestamp: Utc::now(),
+        }
+    }
+    
+    fn channels(records: &[LogRecord]) -> Vec<(u64, String)> {
+        records.iter().map(|record| match &record.event {
+            EngineEvent::Subscribe { channel_id, .. } => (record.seq, channel_id.clone()),
+            other => panic!("unexpected event {:?}", other),
+        }).collect()
+    }
+    
+    #[test]
+    fn recovers_from_torn_tail() {
+        let dir = temp_dir("torn-tail");
+        let config = EventLogConfig { dir: dir.clone(), ..Default::default() };
+        
+        let mut log = EventLog::open(config.clone()).unwrap();
+        log.append(&subscribe("channel1"), None).unwrap();
+        log.append(&subscribe("channel2"), None).unwrap();
+        drop(log);
+        
+        let (_, path) = list_segments(&dir).unwrap().pop().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"0badf00d {\"seq\":3,\"event\":{\"ty").unwrap();
+        drop(file);
+        
+        let mut log = EventLog::open(config.clone()).unwrap();
+        assert_eq!(log.last_seq(), 2);
+        assert_eq!(log.append(&subscribe("channel3"), None).unwrap(), 3);
+        drop(log);
+        
+        let log = EventLog::open(config).unwrap();
+        let records = log.replay_after(0).unwrap();
+        assert_eq!(channels(&records), vec![
+            (1, "channel1".to_string()),
+            (2, "channel2".to_string()),
+            (3, "channel3".to_string()),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn undo_append_removes_last_record() {
+        let dir = temp_dir("undo");
+        let config = EventLogConfig { dir: dir.clone(), ..Default::default() };
+        
+        let mut log = EventLog::open(config.clone()).unwrap();
+        log.append(&subscribe("channel1"), None).unwrap();
+        log.append(&subscribe("channel2"), None).unwrap();
+        log.undo_append().unwrap();
+        assert!(log.undo_append().is_err());
+        assert_eq!(log.append(&subscribe("channel3"), None).unwrap(), 2);
+        drop(log);
+        
+        let log = EventLog::open(config).unwrap();
+        assert_eq!(channels(&log.replay_after(0).unwrap()), vec![
+            (1, "channel1".to_string()),
+            (2, "channel3".to_string()),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn replays_only_after_snapshot() {
+        let dir = temp_dir("snapshot");
+        let config = EventLogConfig { dir: dir.clone(), ..Default::default() };
+        
+        let mut log = EventLog::open(config.clone()).unwrap();
+        log.append(&subscribe("channel1"), None).unwrap();
+        log.write_snapshot(&"state").unwrap();
+        log.append(&subscribe("channel2"), None).unwrap();
+        drop(log);
+        
+        let log = EventLog::open(config).unwrap();
+        let (last_seq, state): (u64, String) = log.load_snapshot().unwrap().unwrap();
+        assert_eq!((last_seq, state.as_str()), (1, "state"));
+        assert_eq!(channels(&log.replay_after(last_seq).unwrap()), vec![(2, "channel2".to_string())]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
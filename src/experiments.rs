@@ -0,0 +1,515 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::BlendConfig;
+use crate::evaluation::Source;
+use crate::event_log::EngineEvent;
+use crate::impressions::{Outcome, OutcomeKind};
+use crate::interleaving::{InterleavingStats, Team};
+use crate::randomness::stable_hash;
+
+pub const EXPERIMENT_HEADER: &str = "X-Experiment-Variant";
+
+const BUCKETS: u64 = 10_000;
+const Z_95: f64 = 1.96;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendingMode {
+    #[default]
+    Weighted,
+    // Reciprocal rank fusion: each source adds weight / (60 + rank), so
+    // sources whose scores live on different scales mix on equal terms.
+    ReciprocalRank,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExperimentMode {
+    // Each enrolled user sees one variant.
+    #[default]
+    Split,
+    // Each enrolled user sees both variants merged by team-draft
+    // interleaving, which needs far less traffic to pick a winner.
+    Interleaved,
+}
+
+// How a variant ranks. Anything left unset falls back to the engine's own
+// configuration, so an empty variant is a control group.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VariantConfig {
+    pub blend: Option<BlendConfig>,
+    pub sources: Option<Vec<Source>>,
+    #[serde(default)]
+    pub blending: BlendingMode,
+    // `false` ranks with the blend even while a trained ranker is active.
+    pub ranker: Option<bool>,
+}
+
+impl VariantConfig {
+    pub fn uses(&self, source: Source) -> bool {
+        self.sources.as_ref().is_none_or(|sources| sources.contains(&source))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VariantDefinition {
+    pub name: String,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    #[serde(flatten)]
+    pub config: VariantConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExperimentDefinition {
+    pub id: String,
+    #[serde(default = "default_traffic")]
+    pub traffic: f64,
+    #[serde(default)]
+    pub mode: ExperimentMode,
+    pub variants: Vec<VariantDefinition>,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_traffic() -> f64 {
+    1.0
+}
+
+#[derive(Debug)]
+pub enum RegisterError {
+    Duplicate,
+    Invalid(&'static str),
+}
+
+impl ExperimentDefinition {
+    fn validate(&self) -> Result<(), RegisterError> {
+        if self.id.is_empty() {
+            return Err(RegisterError::Invalid("experiment id must not be empty"));
+        }
+        if !(self.traffic > 0.0 && self.traffic <= 1.0) {
+            return Err(RegisterError::Invalid("traffic must be in (0, 1]"));
+        }
+        if self.variants.is_empty() {
+            return Err(RegisterError::Invalid("an experiment needs at least one variant"));
+        }
+        if self.mode == ExperimentMode::Interleaved && self.variants.len() != 2 {
+            return Err(RegisterError::Invalid("an interleaved experiment needs exactly two variants"));
+        }
+        
+        let mut names = HashSet::new();
+        for variant in &self.variants {
+            if variant.name.is_empty() || !names.insert(variant.name.as_str()) {
+                return Err(RegisterError::Invalid("variant names must be unique and non-empty"));
+            }
+            if !(variant.weight.is_finite() && variant.weight > 0.0) {
+                return Err(RegisterError::Invalid("variant weights must be positive"));
+            }
+            if variant.config.sources.as_ref().is_some_and(|sources| sources.contains(&Source::Blend)) {
+                return Err(RegisterError::Invalid("variant sources cannot include blend"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VariantTag {
+    pub experiment_id: String,
+    pub variant: String,
+}
+
+#[derive(Default)]
+struct VariantStats {
+    users: usize,
+    requests: u64,
+    impressions: u64,
+    clicks: u64,
+    views: u64,
+    watch_seconds: f64,
+    watch_seconds_squared: f64,
+    likes: u64,
+    comments: u64,
+    shares: u64,
+}
+
+// Only users who were actually served a variant's recommendations count
+// towards its results; being hashed into a bucket alone is not exposure.
+struct ExperimentResults {
+    exposed: HashMap<String, usize>,
+    variants: Vec<VariantStats>,
+    interleaving: InterleavingStats,
+}
+
+pub struct Experiment {
+    definition: ExperimentDefinition,
+    created_at: DateTime<Utc>,
+    running: AtomicBool,
+    results: Mutex<ExperimentResults>,
+}
+
+impl Experiment {
+    // Enrollment and variant choice hash the user separately, so changing
+    // the traffic share never moves an enrolled user to another variant.
+    fn variant_for(&self, user_id: &str) -> Option<usize> {
+        let definition = &self.definition;
+        let enrolled = (definition.traffic * BUCKETS as f64).round() as u64;
+        if stable_hash(0, &[&definition.id, user_id]) % BUCKETS >= enrolled {
+            return None;
+        }
+        
+        let total: f64 = definition.variants.iter().map(|variant| variant.weight).sum();
+        let mut point = (stable_hash(1, &[&definition.id, user_id]) % BUCKETS) as f64 / BUCKETS as f64 * total;
+        for (index, variant) in definition.variants.iter().enumerate() {
+            if point < variant.weight {
+                return Some(index);
+            }
+            point -= variant.weight;
+        }
+        Some(definition.variants.len() - 1)
+    }
+    
+    fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.definition.id,
+            "traffic": self.definition.traffic,
+            "mode": self.definition.mode,
+            "running": self.running.load(Ordering::Relaxed),
+            "createdAt": self.created_at,
+            "variants": self.definition.variants,
+        })
+    }
+}
+
+pub struct Assignment {
+    experiment: Arc<Experiment>,
+    variant: usize,
+}
+
+pub enum Ranking<'a> {
+    Single(&'a VariantConfig),
+    Interleaved(&'a VariantConfig, &'a VariantConfig),
+}
+
+impl Assignment {
+    pub fn ranking(&self) -> Ranking<'_> {
+        let variants = &self.experiment.definition.variants;
+        match self.experiment.definition.mode {
+            ExperimentMode::Split => Ranking::Single(&variants[self.variant].config),
+            ExperimentMode::Interleaved => Ranking::Interleaved(&variants[0].config, &variants[1].config),
+        }
+    }
+    
+    pub fn tag(&self) -> VariantTag {
+        let variant = match self.experiment.definition.mode {
+            ExperimentMode::Split => self.experiment.definition.variants[self.variant].name.clone(),
+            ExperimentMode::Interleaved => "interleaved".to_string(),
+        };
+        VariantTag {
+            experiment_id: self.experiment.definition.id.clone(),
+            variant,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ExperimentRegistry {
+    experiments: RwLock<Vec<Arc<Experiment>>>,
+}
+
+impl ExperimentRegistry {
+    pub fn register(&self, definition: ExperimentDefinition) -> Result<(), RegisterError> {
+        definition.validate()?;
+        
+        let mut experiments = self.experiments.write().unwrap();
+        if experiments.iter().any(|experiment| experiment.definition.id == definition.id) {
+            return Err(RegisterError::Duplicate);
+        }
+        
+        experiments.push(Arc::new(Experiment {
+            results: Mutex::new(ExperimentResults {
+                exposed: HashMap::new(),
+                variants: definition.variants.iter().map(|_| VariantStats::default()).collect(),
+                interleaving: InterleavingStats::default(),
+            }),
+            definition,
+            created_at: Utc::now(),
+            running: AtomicBool::new(true),
+        }));
+        Ok(())
+    }
+    
+    pub fn stop(&self, experiment_id: &str) -> bool {
+        let experiments = self.experiments.read().unwrap();
+        match experiments.iter().find(|experiment| experiment.definition.id == experiment_id) {
+            Some(experiment) => {
+                experiment.running.store(false, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+    
+    // Experiments are checked in the order they were created and the first one
+    // that enrolls the user decides its ranking, so concurrent experiments
+    // never mix their variants in a single list.
+    pub fn assign(&self, user_id: &str) -> Option<Assignment> {
+        let experiments = self.experiments.read().unwrap();
+        experiments.iter()
+            .filter(|experiment| experiment.running.load(Ordering::Relaxed))
+            .find_map(|experiment| {
+                experiment.variant_for(user_id).map(|variant| Assignment {
+                    experiment: experiment.clone(),
+                    variant,
+                })
+            })
+    }
+    
+    // Interleaved experiments count exposures through
+    // record_interleaved_exposure instead.
+    pub fn record_exposure(&self, assignment: &Assignment, user_id: &str, impressions: usize) {
+        if assignment.experiment.definition.mode == ExperimentMode::Interleaved {
+            return;
+        }
+        
+        let mut results = assignment.experiment.results.lock().unwrap();
+        if results.exposed.insert(user_id.to_string(), assignment.variant).is_none() {
+            results.variants[assignment.variant].users += 1;
+        }
+        
+        let stats = &mut results.variants[assignment.variant];
+        stats.requests += 1;
+        stats.impressions += impressions as u64;
+    }
+    
+    pub fn record_interleaved_exposure(&self, assignment: &Assignment, user_id: &str, slots: &[(String, Team)]) {
+        let mut results = assignment.experiment.results.lock().unwrap();
+        results.interleaving.record_impression(user_id, slots);
+    }
+    
+    pub fn record_outcome(&self, assignment: &Assignment, event: &EngineEvent) {
+        let mut results = assignment.experiment.results.lock().unwrap();
+        if assignment.experiment.definition.mode == ExperimentMode::Interleaved {
+            if let EngineEvent::Watch { user_id, video_id, .. } = event {
+                results.interleaving.record_click(user_id, video_id);
+            }
+            return;
+        }
+        if results.exposed.get(event.user_id()) != Some(&assignment.variant) {
+            return;
+        }
+        
+        let stats = &mut results.variants[assignment.variant];
+        match event {
+            EngineEvent::Watch { watch_duration, .. } => {
+                let seconds = watch_duration.as_secs_f64();
+                stats.views += 1;
+                stats.watch_seconds += seconds;
+                stats.watch_seconds_squared += seconds * seconds;
+            }
+            EngineEvent::Like { is_like: true, .. } => stats.likes += 1,
+            EngineEvent::Comment { .. } => stats.comments += 1,
+            EngineEvent::Share { .. } => stats.shares += 1,
+            _ => {}
+        }
+    }
+    
+    // Watches credited to a slot of a list this experiment served. Unlike
+    // views, each counts against exactly one impression, so they give the
+    // click-through rate.
+    pub fn record_attributed(&self, outcome: &Outcome) {
+        let Some(tag) = &outcome.variant else {
+            return;
+        };
+        if outcome.kind != OutcomeKind::Watch {
+            return;
+        }
+        
+        let experiments = self.experiments.read().unwrap();
+        let Some(experiment) = experiments.iter().find(|experiment| experiment.definition.id == tag.experiment_id) else {
+            return;
+        };
+        if experiment.definition.mode == ExperimentMode::Interleaved {
+            return;
+        }
+        if let Some(variant) = experiment.definition.variants.iter().position(|variant| variant.name == tag.variant) {
+            experiment.results.lock().unwrap().variants[variant].clicks += 1;
+        }
+    }
+    
+    pub fn list(&self) -> Vec<serde_json::Value> {
+        self.experiments.read().unwrap().iter().map(|experiment| experiment.summary()).collect()
+    }
+    
+    pub fn results(&self, experiment_id: &str) -> Option<serde_json::Value> {
+        let experiment = self.experiments.read().unwrap().iter()
+            .find(|experiment| experiment.definition.id == experiment_id)
+            .cloned()?;
+        let results = experiment.results.lock().unwrap();
+        
+        let mut summary = experiment.summary();
+        summary["confidenceLevel"] = serde_json::json!(0.95);
+        if experiment.definition.mode == ExperimentMode::Interleaved {
+            let outcomes = results.interleaving.outcomes();
+            let names = &experiment.definition.variants;
+            summary["interleaving"] = serde_json::json!({
+                "rankerA": names[0].name,
+                "rankerB": names[1].name,
+                "outcomes": outcomes,
+                // Share of decided impressions won by ranker A; an interval
+                // clear of 0.5 means one ranker is preferred.
+                "preferenceA": proportion(outcomes.wins_a, outcomes.wins_a + outcomes.wins_b),
+            });
+            return Some(summary);
+        }
+        
+        let variants: Vec<_> = experiment.definition.variants.iter().zip(&results.variants)
+            .map(|(variant, stats)| {
+                let engagements = stats.likes + stats.comments + stats.shares;
+                serde_json::json!({
+                    "name": variant.name,
+                    "users": stats.users,
+                    "requests": stats.requests,
+                    "impressions": stats.impressions,
+                    "clicks": stats.clicks,
+                    "views": stats.views,
+                    "likes": stats.likes,
+                    "comments": stats.comments,
+                    "shares": stats.shares,
+                    "ctr": proportion(stats.clicks, stats.impressions),
+                    "watchTimeSeconds": mean(stats.watch_seconds, stats.watch_seconds_squared, stats.views),
+                    "engagementRate": proportion(engagements.min(stats.views), stats.views),
+                })
+            })
+            .collect();
+        
+        summary["variants"] = serde_json::Value::Array(variants);
+        Some(summary)
+    }
+}
+
+#[derive(Serialize)]
+struct Estimate {
+    value: f64,
+    low: f64,
+    high: f64,
+}
+
+// Wilson score interval, which stays inside [0, 1] and behaves for the small
+// counts a young experiment has.
+fn proportion(successes: u64, trials: u64) -> Estimate {
+    if trials == 0 {
+        return Estimate { value: 0.0, low: 0.0, high: 0.0 };
+    }
+    
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let z2 = Z_95 * Z_95;
+    let centre = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+    Estimate { value: p, low: (centre - half).max(0.0), high: (centre + half).min(1.0) }
+}
+
+// Normal approximation around the sample mean.
+fn mean(sum: f64, sum_squared: f64, count: u64) -> Estimate {
+    if count == 0 {
+        return Estimate { value: 0.0, low: 0.0, high: 0.0 };
+    }
+    
+    let n = count as f64;
+    let mean = sum / n;
+    let variance = if count > 1 { ((sum_squared - n * mean * mean) / (n - 1.0)).max(0.0) } else { 0.0 };
+    let half = Z_95 * (variance / n).sqrt();
+    Estimate { value: mean, low: (mean - half).max(0.0), high: mean + half }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+    
+    fn definition(traffic: f64) -> ExperimentDefinition {
+        serde_json::from_value(serde_json::json!({
+            "id": "exp1",
+            "traffic": traffic,
+            "variants": [{"name": "control"}, {"name": "treatment", "weight": 3.0}],
+        })).unwrap()
+    }
+    
+    fn watch(user_id: &str, seconds: u64) -> EngineEvent {
+        EngineEvent::Watch {
+            user_id: user_id.to_string(),
+            video_id: "video1".to_string(),
+            watch_duration: Duration::from_secs(seconds),
+            interactions: Vec::new(),
+            timestamp: Utc::now(),
+        }
+    }
+    
+    #[test]
+    fn assignment_is_sticky_and_follows_weights() {
+        let registry = ExperimentRegistry::default();
+        registry.register(definition(0.5)).unwrap();
+        assert!(matches!(registry.register(definition(0.5)), Err(RegisterError::Duplicate)));
+        
+        let mut enrolled = 0;
+        let mut treatment = 0;
+        for i in 0..4000 {
+            let user_id = format!("user{}", i);
+            let Some(assignment) = registry.assign(&user_id) else {
+                continue;
+            };
+            assert_eq!(registry.assign(&user_id).unwrap().variant, assignment.variant);
+            enrolled += 1;
+            treatment += assignment.variant;
+        }
+        
+        let enrolled_share = enrolled as f64 / 4000.0;
+        let treatment_share = treatment as f64 / enrolled as f64;
+        assert!((enrolled_share - 0.5).abs() < 0.05, "enrolled {}", enrolled_share);
+        assert!((treatment_share - 0.75).abs() < 0.05, "treatment {}", treatment_share);
+        
+        registry.stop("exp1");
+        assert!((0..100).all(|i| registry.assign(&format!("user{}", i)).is_none()));
+    }
+    
+    #[test]
+    fn only_exposed_users_count_towards_results() {
+        let registry = ExperimentRegistry::default();
+        registry.register(definition(1.0)).unwrap();
+        let assignment = registry.assign("user1").unwrap();
+        
+        // Enrolled but not yet served: the watch is not the variant's doing.
+        registry.record_outcome(&assignment, &watch("user1", 100));
+        registry.record_exposure(&assignment, "user1", 10);
+        registry.record_exposure(&assignment, "user1", 10);
+        registry.record_outcome(&assignment, &watch("user1", 30));
+        registry.record_outcome(&assignment, &watch("user1", 50));
+        
+        let results = registry.results("exp1").unwrap();
+        let stats = &results["variants"][assignment.variant];
+        assert_eq!(stats["users"], 1);
+        assert_eq!(stats["requests"], 2);
+        assert_eq!(stats["impressions"], 20);
+        assert_eq!(stats["views"], 2);
+        assert_eq!(stats["watchTimeSeconds"]["value"], 40.0);
+    }
+    
+    #[test]
+    fn wilson_interval_stays_within_bounds() {
+        let estimate = proportion(0, 10);
+        assert_eq!(estimate.value, 0.0);
+        assert_eq!(estimate.low, 0.0);
+        assert!(estimate.high > 0.0 && estimate.high < 0.35);
+        
+        let estimate = proportion(50, 100);
+        assert!((estimate.low - 0.4038).abs() < 1e-3 && (estimate.high - 0.5962).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+use crate::evaluation::Source;
+use crate::impressions::{Impression, LogEntry, Outcome, OutcomeKind};
+use crate::randomness::Jitter;
+use crate::sharded::ShardedMap;
+use crate::storage::Storage;
+
+const RECENT_PULLS: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExplorationPolicy {
+    Off,
+    EpsilonGreedy(f64),
+    Ucb(f64),
+    Thompson,
+}
+
+impl ExplorationPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => return Some(ExplorationPolicy::Off),
+            "thompson" => return Some(ExplorationPolicy::Thompson),
+            _ => {}
+        }
+        
+        let (kind, param) = value.split_once(':')?;
+        let param: f64 = param.parse().ok()?;
+        match kind {
+            "epsilon" if (0.0..=1.0).contains(&param) => Some(ExplorationPolicy::EpsilonGreedy(param)),
+            "ucb" if param >= 0.0 => Some(ExplorationPolicy::Ucb(param)),
+            _ => None,
+        }
+    }
+    
+    fn name(&self) -> String {
+        match self {
+            ExplorationPolicy::Off => "off".to_string(),
+            ExplorationPolicy::EpsilonGreedy(epsilon) => format!("epsilon:{}", epsilon),
+            ExplorationPolicy::Ucb(c) => format!("ucb:{}", c),
+            ExplorationPolicy::Thompson => "thompson".to_string(),
+        }
+    }
+}
+
+// A video is an arm while it is new or has few views. Up to `slots` places in
+// each served list go to arms, and no user is shown more than `daily_cap`
+// exploration slots per UTC day.
+#[derive(Clone, Debug)]
+pub struct ExplorationConfig {
+    pub policy: ExplorationPolicy,
+    pub slots: usize,
+    pub daily_cap: usize,
+    pub max_views: u64,
+    pub max_age: Duration,
+}
+
+impl Default for ExplorationConfig {
+    fn default() -> Self {
+        ExplorationConfig {
+            policy: ExplorationPolicy::Thompson,
+            slots: 1,
+            daily_cap: 10,
+            max_views: 100,
+            max_age: Duration::days(7),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct ArmStats {
+    pulls: u64,
+    rewards: u64,
+}
+
+impl ArmStats {
+    // Laplace smoothing keeps unseen arms at an even prior instead of zero.
+    fn mean(&self) -> f64 {
+        (self.rewards as f64 + 1.0) / (self.pulls as f64 + 2.0)
+    }
+}
+
+#[derive(Default)]
+struct Allowance {
+    day: Option<NaiveDate>,
+    used: usize,
+}
+
+// Arms are pulled when they fill an exploration slot of a served list and
+// rewarded when the impression tracker attributes a watch to that slot, so the
+// statistics can be rebuilt from the impression log on startup. The same video
+// ranked into a list by another source is neither.
+pub struct ExplorationLayer {
+    config: ExplorationConfig,
+    arms: ShardedMap<ArmStats>,
+    allowances: ShardedMap<Allowance>,
+    // Each user's latest exploration slots as (request id, video id).
+    pulls: ShardedMap<VecDeque<(String, String)>>,
+}
+
+impl ExplorationLayer {
+    pub fn new(config: ExplorationConfig) -> Self {
+        ExplorationLayer {
+            config,
+            arms: ShardedMap::default(),
+            allowances: ShardedMap::default(),
+            pulls: ShardedMap::default(),
+        }
+    }
+    
+    pub fn replay(&self, entries: &[LogEntry]) {
+        for entry in entries {
+            match entry {
+                LogEntry::Impression(impression) => self.record_impression(impression),
+                LogEntry::Outcome(outcome) => self.record_outcome(outcome),
+            }
+        }
+    }
+    
+    pub fn record_impression(&self, impression: &Impression) {
+        let explored: Vec<_> = impression.items.iter()
+            .filter(|item| item.source_scores.contains_key(&Source::Exploration))
+            .collect();
+        if explored.is_empty() {
+            return;
+        }
+        
+        self.pulls.upsert(&impression.user_id, |pulls| {
+            for item in &explored {
+                pulls.push_front((impression.request_id.clone(), item.video_id.clone()));
+            }
+            pulls.truncate(RECENT_PULLS);
+        });
+        for item in explored {
+            self.arms.upsert(&item.video_id, |arm| arm.pulls += 1);
+        }
+    }
+    
+    pub fn record_outcome(&self, outcome: &Outcome) {
+        if outcome.kind != OutcomeKind::Watch {
+            return;
+        }
+        let pulled = self.pulls.read(&outcome.user_id, |pulls| {
+            pulls.is_some_and(|pulls| {
+                pulls.iter().any(|(request_id, video_id)| *request_id == outcome.request_id && *video_id == outcome.video_id)
+            })
+        });
+        if pulled {
+            self.arms.upsert(&outcome.video_id, |arm| arm.rewards += 1);
+        }
+    }
+    
+    // Number of exploration slots the user may still be shown today.
+    pub fn allowance(&self, user_id: &str, now: DateTime<Utc>) -> usize {
+        if self.config.policy == ExplorationPolicy::Off {
+            return 0;
+        }
+        let today = now.date_naive();
+        let used = self.allowances.read(user_id, |allowance| {
+            allowance.filter(|allowance| allowance.day == Some(today)).map_or(0, |allowance| allowance.used)
+        });
+        self.config.slots.min(self.config.daily_cap.saturating_sub(used))
+    }
+    
+    pub fn consume(&self, user_id: &str, slots: usize, now: DateTime<Utc>) {
+        let today = now.date_naive();
+        self.allowances.upsert(user_id, |allowance| {
+            if allowance.day != Some(today) {
+                allowance.day = Some(today);
+                allowance.used = 0;
+            }
+            allowance.used += slots;
+        });
+    }
+    
+    // Picks up to `count` eligible videos the user has not watched and that
+    // are not already in `exclude`, scored under the configured policy.
+    pub fn candidates(
+        &self,
+        user_id: &str,
+        count: usize,
+        exclude: &HashSet<String>,
+        storage: &dyn Storage,
+        jitter: &mut Jitter,
+        now: DateTime<Utc>,
+    ) -> io::Result<Vec<(String, f64)>> {
+        if count == 0 || self.config.policy == ExplorationPolicy::Off {
+            return Ok(Vec::new());
+        }
+        
+        let watched: HashSet<String> = storage.get_user(user_id)?
+            .map(|user| user.watch_history.iter().map(|watch| watch.video_id.clone()).collect())
+            .unwrap_or_default();
+        
+        let mut eligible = Vec::new();
+        storage.for_each_video(&mut |video| {
+            if (video.metrics.views < self.config.max_views || now - video.upload_date < self.config.max_age)
+                && !watched.contains(&video.id)
+                && !exclude.contains(&video.id)
+            {
+                eligible.push(video.id.clone());
+            }
+        })?;
+        eligible.sort();
+        
+        let mut arms: Vec<(String, ArmStats)> = eligible.into_iter()
+            .map(|video_id| {
+                let arm = self.arms.read(&video_id, |arm| arm.copied().unwrap_or_default());
+                (video_id, arm)
+            })
+            .collect();
+        
+        let mut scored: Vec<(String, f64)> = match self.config.policy {
+            ExplorationPolicy::Off => Vec::new(),
+            ExplorationPolicy::EpsilonGreedy(epsilon) => {
+                let mut picks = Vec::new();
+                while picks.len() < count && !arms.is_empty() {
+                    let index = if jitter.unit() < epsilon {
+                        jitter.index(arms.len())
+                    } else {
+                        (0..arms.len())
+                            .max_by(|&a, &b| arms[a].1.mean().total_cmp(&arms[b].1.mean()).then_with(|| arms[b].0.cmp(&arms[a].0)))
+                            .unwrap_or(0)
+                    };
+                    let (video_id, arm) = arms.remove(index);
+                    picks.push((video_id, arm.mean()));
+                }
+                return Ok(picks);
+            }
+            ExplorationPolicy::Ucb(c) => {
+                let total_pulls: u64 = arms.iter().map(|(_, arm)| arm.pulls).sum();
+                let log_total = (total_pulls as f64 + 1.0).ln();
+                arms.into_iter()
+                    .map(|(video_id, arm)| (video_id, arm.mean() + c * (log_total / (arm.pulls as f64 + 1.0)).sqrt()))
+                    .collect()
+            }
+            ExplorationPolicy::Thompson => arms.into_iter()
+                .map(|(video_id, arm)| {
+                    let failures = arm.pulls.saturating_sub(arm.rewards);
+                    (video_id, jitter.beta(arm.rewards as f64 + 1.0, failures as f64 + 1.0))
+                })
+                .collect(),
+        };
+        
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(count);
+        Ok(scored)
+    }
+    
+    pub fn stats(&self) -> serde_json::Value {
+        let (mut pulls, mut rewards) = (0, 0);
+        self.arms.for_each(|_, arm| {
+            pulls += arm.pulls;
+            rewards += arm.rewards;
+        });
+        
+        serde_json::json!({
+            "policy": self.config.policy.name(),
+            "slots": self.config.slots,
+            "dailyCap": self.config.daily_cap,
+            "maxViews": self.config.max_views,
+            "maxAgeDays": self.config.max_age.num_days(),
+            "trackedVideos": self.arms.len(),
+            "pulls": pulls,
+            "rewards": rewards,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::impressions::ImpressionItem;
+    
+    fn item(video_id: &str, position: usize, source: Source) -> ImpressionItem {
+        ImpressionItem {
+            video_id: video_id.to_string(),
+            position,
+            score: 1.0,
+            source_scores: HashMap::from([(source, 1.0)]),
+            features: Vec::new(),
+            rank_features: Vec::new(),
+        }
+    }
+    
+    fn watch(video_id: &str, position: usize) -> Outcome {
+        Outcome {
+            request_id: "request1".to_string(),
+            user_id: "user1".to_string(),
+            video_id: video_id.to_string(),
+            position,
+            kind: OutcomeKind::Watch,
+            watch_seconds: Some(60.0),
+            timestamp: Utc::now(),
+            variant: None,
+        }
+    }
+    
+    #[test]
+    fn only_exploration_slots_pull_and_reward_arms() {
+        let layer = ExplorationLayer::new(ExplorationConfig::default());
+        let impression = Impression {
+            request_id: "request1".to_string(),
+            user_id: "user1".to_string(),
+            timestamp: Utc::now(),
+            variant: None,
+            context: None,
+            items: vec![item("ranked", 1, Source::Collaborative), item("explored", 2, Source::Exploration)],
+        };
+        
+        layer.replay(&[
+            LogEntry::Impression(impression),
+            LogEntry::Outcome(watch("ranked", 1)),
+            LogEntry::Outcome(watch("explored", 2)),
+        ]);
+        
+        let stats = layer.stats();
+        assert_eq!(stats["trackedVideos"], 1);
+        assert_eq!(stats["pulls"], 1);
+        assert_eq!(stats["rewards"], 1);
+    }
+    
+    #[test]
+    fn allowance_resets_each_day() {
+        let config = ExplorationConfig { slots: 2, daily_cap: 3, ..ExplorationConfig::default() };
+        let layer = ExplorationLayer::new(config);
+        let now = Utc::now();
+        
+        assert_eq!(layer.allowance("user1", now), 2);
+        layer.consume("user1", 2, now);
+        assert_eq!(layer.allowance("user1", now), 1);
+        layer.consume("user1", 1, now);
+        assert_eq!(layer.allowance("user1", now), 0);
+        assert_eq!(layer.allowance("user1", now + Duration::days(1)), 2);
+    }
+}
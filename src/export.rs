@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use actix_web::web::Bytes;
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::storage::{Rating, Storage};
+use crate::{User, Video};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const RETENTION_POINTS: [f32; 4] = [0.25, 0.5, 0.75, 1.0];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Jsonl,
+    Csv,
+    Columnar,
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "jsonl" => Some(ExportFormat::Jsonl),
+            "csv" => Some(ExportFormat::Csv),
+            "columnar" => Some(ExportFormat::Columnar),
+            _ => None,
+        }
+    }
+    
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Csv | ExportFormat::Columnar => "text/csv",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dataset {
+    Users,
+    Videos,
+    Ratings,
+    Recommendations,
+}
+
+impl Dataset {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "users" => Some(Dataset::Users),
+            "videos" => Some(Dataset::Videos),
+            "ratings" => Some(Dataset::Ratings),
+            "recommendations" => Some(Dataset::Recommendations),
+            _ => None,
+        }
+    }
+}
+
+// Rows are written as they are read so that exports never hold more than the
+// id lists in memory; `recommend` is called once per user.
+pub fn export_dataset(
+    dataset: Dataset,
+    format: ExportFormat,
+    count: usize,
+    storage: &dyn Storage,
+    recommend: &mut dyn FnMut(&str, usize) -> io::Result<Vec<Video>>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    match dataset {
+        Dataset::Users => export_users(storage, format, out),
+        Dataset::Videos => export_videos(storage, format, out),
+        Dataset::Ratings => export_ratings(storage, format, out),
+        Dataset::Recommendations => export_recommendations(storage, count, recommend, format, out),
+    }?;
+    out.flush()
+}
+
+fn export_users(storage: &dyn Storage, format: ExportFormat, out: &mut dyn Write) -> io::Result<()> {
+    let user_ids = sorted(storage.user_ids()?);
+    
+    match format {
+        ExportFormat::Jsonl => {
+            for_each_user(storage, &user_ids, |user| {
+                writeln!(out, "{}", json!({
+                    "user_id": user.id,
+                    "subscriptions": sorted(user.subscriptions.iter().cloned().collect()),
+                    "timezone": user.timezone,
+                    "watch_count": user.watch_history.len(),
+                    "content_preferences": user.content_preferences,
+                    "interaction_patterns": user.interaction_patterns,
+                }))
+            })
+        }
+        ExportFormat::Csv => {
+            write_row(out, &["user_id", "subscriptions", "watch_count", "content_preferences",
+                "avg_watch_percentage", "avg_comment_length", "time_of_day_preferences",
+                "like_to_view_ratio", "share_frequency"])?;
+            
+            for_each_user(storage, &user_ids, |user| {
+                let patterns = &user.interaction_patterns;
+                write_row(out, &[
+                    user.id.clone(),
+                    json!(sorted(user.subscriptions.iter().cloned().collect())).to_string(),
+                    user.watch_history.len().to_string(),
+                    json!(user.content_preferences).to_string(),
+                    patterns.avg_watch_percentage.to_string(),
+                    patterns.avg_comment_length.to_string(),
+                    json!(patterns.time_of_day_preferences).to_string(),
+                    patterns.like_to_view_ratio.to_string(),
+                    patterns.share_frequency.to_string(),
+                ])
+            })
+        }
+        ExportFormat::Columnar => {
+            let mut categories = BTreeSet::new();
+            for_each_user(storage, &user_ids, |user| {
+                categories.extend(user.content_preferences.keys().cloned());
+                Ok(())
+            })?;
+            
+            let mut header: Vec<String> = ["user_id", "subscription_count", "watch_count",
+                "avg_watch_percentage", "avg_comment_length", "like_to_view_ratio", "share_frequency"]
+                .iter().map(|column| column.to_string()).collect();
+            header.extend(categories.iter().map(|category| format!("pref_{}", category)));
+            header.extend((0..24).map(|hour| format!("hour_{:02}", hour)));
+            write_row(out, &header)?;
+            
+            for_each_user(storage, &user_ids, |user| {
+                let patterns = &user.interaction_patterns;
+                let mut row = vec![
+                    user.id.clone(),
+                    user.subscriptions.len().to_string(),
+                    user.watch_history.len().to_string(),
+                    patterns.avg_watch_percentage.to_string(),
+                    patterns.avg_comment_length.to_string(),
+                    patterns.like_to_view_ratio.to_string(),
+                    patterns.share_frequency.to_string(),
+                ];
+                row.extend(categories.iter().map(|category| {
+                    user.content_preferences.get(category).copied().unwrap_or(0.0).to_string()
+                }));
+                row.extend((0..24u8).map(|hour| {
+                    patterns.time_of_day_preferences.get(&hour).copied().unwrap_or(0.0).to_string()
+                }));
+                write_row(out, &row)
+            })
+        }
+    }
+}
+
+fn export_videos(storage: &dyn Storage, format: ExportFormat, out: &mut dyn Write) -> io::Result<()> {
+    let video_ids = sorted(storage.video_ids()?);
+    
+    let scalar_columns = ["views", "likes", "dislikes", "share_count", "comment_count",
+        "avg_watch_time_secs", "avg_watch_percentage", "completion_rate", "rewatch_rate"];
+    let scalars = |video: &Video| {
+        let metrics = &video.metrics;
+        vec![
+            metrics.views.to_string(),
+            metrics.likes.to_string(),
+            metrics.dislikes.to_string(),
+            metrics.share_count.to_string(),
+            metrics.comment_count.to_string(),
+            metrics.avg_watch_time.as_secs_f64().to_string(),
+            metrics.avg_watch_percentage.to_string(),
+            metrics.completion_rate.to_string(),
+            metrics.rewatch_rate.to_string(),
+        ]
+    };
+    
+    match format {
+        ExportFormat::Jsonl => {
+            for_each_video(storage, &video_ids, |video| {
+                let metrics = &video.metrics;
+                writeln!(out, "{}", json!({
+                    "video_id": video.id,
+                    "title": video.title,
+                    "channel_id": video.channel_id,
+                    "duration_secs": video.duration.as_secs_f64(),
+                    "categories": video.categories,
+                    "tags": video.tags,
+                    "upload_date": video.upload_date,
+                    "metrics": {
+                        "views": metrics.views,
+                        "likes": metrics.likes,
+                        "dislikes": metrics.dislikes,
+                        "share_count": metrics.share_count,
+                        "comment_count": metrics.comment_count,
+                        "avg_watch_time_secs": metrics.avg_watch_time.as_secs_f64(),
+                        "avg_watch_percentage": metrics.avg_watch_percentage,
+                        "completion_rate": metrics.completion_rate,
+                        "retention_curve": metrics.retention_curve,
+                        "rewatch_rate": metrics.rewatch_rate,
+                    },
+                }))
+            })
+        }
+        ExportFormat::Csv | ExportFormat::Columnar => {
+            let mut header: Vec<String> = ["video_id", "title", "channel_id", "duration_secs",
+                "categories", "tags", "upload_date"]
+                .iter().chain(scalar_columns.iter()).map(|column| column.to_string()).collect();
+            match format {
+                ExportFormat::Columnar => header.extend(RETENTION_POINTS.iter()
+                    .map(|point| format!("retention_{}", (point * 100.0) as u32))),
+                _ => header.push("retention_curve".to_string()),
+            }
+            write_row(out, &header)?;
+            
+            for_each_video(storage, &video_ids, |video| {
+                let (categories, tags) = match format {
+                    ExportFormat::Columnar => (video.categories.join("|"), video.tags.join("|")),
+                    _ => (json!(video.categories).to_string(), json!(video.tags).to_string()),
+                };
+                
+                let mut row = vec![
+                    video.id.clone(),
+                    video.title.clone(),
+                    video.channel_id.clone(),
+                    video.duration.as_secs_f64().to_string(),
+                    categories,
+                    tags,
+                    video.upload_date.to_rfc3339(),
+                ];
+                row.extend(scalars(video));
+                match format {
+                    ExportFormat::Columnar => row.extend(RETENTION_POINTS.iter().map(|point| {
+                        retention_at(&video.metrics.retention_curve, *point)
+                            .map_or(String::new(), |value| value.to_string())
+                    })),
+                    _ => row.push(json!(video.metrics.retention_curve).to_string()),
+                }
+                write_row(out, &row)
+            })
+        }
+    }
+}
+
+// Sparse COO layout: `row` and `col` index the sorted user and video ids, so
+// the file loads directly into a scipy/numpy sparse matrix. Ratings are as
+// stored at `updated_at`, before any time decay; `rating` is the confidence
+// weighted sum that `preference` and `confidence` come from.
+fn export_ratings(storage: &dyn Storage, format: ExportFormat, out: &mut dyn Write) -> io::Result<()> {
+    let user_ids = sorted(storage.user_ids()?);
+    
+    let mut video_ids: BTreeSet<String> = storage.video_ids()?.into_iter().collect();
+    for user_id in &user_ids {
+        video_ids.extend(storage.user_ratings(user_id)?.into_keys());
+    }
+    let video_index: BTreeMap<String, usize> = video_ids.into_iter()
+        .enumerate()
+        .map(|(index, video_id)| (video_id, index))
+        .collect();
+    
+    if format != ExportFormat::Jsonl {
+        write_row(out, &["row", "col", "user_id", "video_id", "rating", "preference", "confidence", "updated_at"])?;
+    }
+    
+    for (row, user_id) in user_ids.iter().enumerate() {
+        let ratings: BTreeMap<String, Rating> = storage.user_ratings(user_id)?.into_iter().collect();
+        for (video_id, rating) in ratings {
+            let col = video_index[&video_id];
+            match format {
+                ExportFormat::Jsonl => writeln!(out, "{}", json!({
+                    "row": row,
+                    "col": col,
+                    "user_id": user_id,
+                    "video_id": video_id,
+                    "rating": rating.value,
+                    "preference": rating.preference(),
+                    "confidence": rating.confidence,
+                    "updated_at": rating.updated_at,
+                }))?,
+                _ => write_row(out, &[
+                    row.to_string(),
+                    col.to_string(),
+                    user_id.clone(),
+                    video_id,
+                    rating.value.to_string(),
+                    rating.preference().to_string(),
+                    rating.confidence.to_string(),
+                    rating.updated_at.to_rfc3339(),
+                ])?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn export_recommendations(
+    storage: &dyn Storage,
+    count: usize,
+    recommend: &mut dyn FnMut(&str, usize) -> io::Result<Vec<Video>>,
+    format: ExportFormat,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let user_ids = sorted(storage.user_ids()?);
+    
+    match format {
+        ExportFormat::Jsonl => {}
+        ExportFormat::Csv => write_row(out, &["user_id", "rank", "video_id", "title", "channel_id"])?,
+        ExportFormat::Columnar => {
+            let mut header = vec!["user_id".to_string()];
+            header.extend((1..=count).map(|rank| format!("rank_{}", rank)));
+            write_row(out, &header)?;
+        }
+    }
+    
+    for user_id in &user_ids {
+        let recommendations = recommend(user_id, count)?;
+        
+        match format {
+            ExportFormat::Jsonl => {
+                let videos: Vec<_> = recommendations.iter().enumerate().map(|(index, video)| json!({
+                    "rank": index + 1,
+                    "video_id": video.id,
+                    "title": video.title,
+                    "channel_id": video.channel_id,
+                })).collect();
+                writeln!(out, "{}", json!({"user_id": user_id, "recommendations": videos}))?;
+            }
+            ExportFormat::Csv => {
+                for (index, video) in recommendations.iter().enumerate() {
+                    write_row(out, &[
+                        user_id.clone(),
+                        (index + 1).to_string(),
+                        video.id.clone(),
+                        video.title.clone(),
+                        video.channel_id.clone(),
+                    ])?;
+                }
+            }
+            ExportFormat::Columnar => {
+                let mut row = vec![user_id.clone()];
+                row.extend((0..count).map(|index| {
+                    recommendations.get(index).map_or(String::new(), |video| video.id.clone())
+                }));
+                write_row(out, &row)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn for_each_user(
+    storage: &dyn Storage,
+    user_ids: &[String],
+    mut f: impl FnMut(&User) -> io::Result<()>,
+) -> io::Result<()> {
+    for user_id in user_ids {
+        if let Some(user) = storage.get_user(user_id)? {
+            f(&user)?;
+        }
+    }
+    Ok(())
+}
+
+fn for_each_video(
+    storage: &dyn Storage,
+    video_ids: &[String],
+    mut f: impl FnMut(&Video) -> io::Result<()>,
+) -> io::Result<()> {
+    for video_id in video_ids {
+        if let Some(video) = storage.get_video(video_id)? {
+            f(&video)?;
+        }
+    }
+    Ok(())
+}
+
+fn sorted(mut ids: Vec<String>) -> Vec<String> {
+    ids.sort();
+    ids
+}
+
+fn retention_at(curve: &[(f32, f32)], point: f32) -> Option<f32> {
+    let after = curve.iter().position(|(position, _)| *position >= point)?;
+    if after == 0 {
+        return Some(curve[0].1);
+    }
+    
+    let (x0, y0) = curve[after - 1];
+    let (x1, y1) = curve[after];
+    Some(y0 + (y1 - y0) * (point - x0) / (x1 - x0))
+}
+
+fn write_row<S: AsRef<str>>(out: &mut dyn Write, fields: &[S]) -> io::Result<()> {
+    let line: Vec<String> = fields.iter().map(|field| escape_csv(field.as_ref())).collect();
+    writeln!(out, "{}", line.join(","))
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Buffers export output into chunks for a streaming HTTP response; writes fail
+// with `BrokenPipe` once the client has gone away.
+pub struct ChunkWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub fn new(sender: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        ChunkWriter { sender, buffer: Vec::with_capacity(CHUNK_SIZE) }
+    }
+    
+    // Ends the stream with an error instead of the unsent rest, so the client
+    // sees an aborted response rather than a complete-looking truncated one.
+    pub fn fail(self, error: io::Error) {
+        let _ = self.sender.blocking_send(Err(error));
+    }
+    
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        
+        let chunk = Bytes::from(std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE)));
+        self.sender.blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "export client disconnected"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
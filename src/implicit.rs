@@ -0,0 +1,98 @@
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::Interaction;
+use crate::telemetry;
+
+// What one event says about how much a user likes a video: a preference
+// between -1 and 1, and how much that preference can be trusted.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Feedback {
+    pub preference: f64,
+    pub confidence: f64,
+}
+
+// Turns events into feedback. A watch is judged against how much of the
+// video people usually watch: watching `neutral_dwell` times the video's
+// average watch percentage says nothing either way, more leans towards liking
+// it and less towards not. Rewinds, skips and the other player interactions
+// move the preference by their interest signal. Explicit feedback has a fixed
+// preference and is trusted more than a watch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImplicitConfig {
+    // Used for videos without an average watch percentage yet.
+    typical_watch_percentage: f64,
+    neutral_dwell: f64,
+    dwell_slope: f64,
+    interaction_weight: f64,
+    watch_confidence: f64,
+    like_confidence: f64,
+    dislike_confidence: f64,
+    comment_preference: f64,
+    comment_confidence: f64,
+    share_confidence: f64,
+    negative_confidence: f64,
+}
+
+impl Default for ImplicitConfig {
+    fn default() -> Self {
+        ImplicitConfig {
+            typical_watch_percentage: 0.5,
+            neutral_dwell: 0.5,
+            dwell_slope: 2.0,
+            interaction_weight: 1.0,
+            watch_confidence: 1.0,
+            like_confidence: 3.0,
+            dislike_confidence: 3.0,
+            comment_preference: 0.5,
+            comment_confidence: 1.5,
+            share_confidence: 4.0,
+            negative_confidence: 5.0,
+        }
+    }
+}
+
+impl ImplicitConfig {
+    // A watch of most of the video is stronger evidence than a short one, so
+    // confidence grows from half to all of `watch_confidence` with the share
+    // of the video watched.
+    pub fn watch(
+        &self,
+        watch_percentage: f64,
+        typical_watch_percentage: f64,
+        interactions: &[Interaction],
+        video_duration: Duration,
+    ) -> Feedback {
+        let typical = if typical_watch_percentage > 0.0 { typical_watch_percentage } else { self.typical_watch_percentage };
+        let dwell = watch_percentage / typical.max(0.01);
+        let preference = (self.dwell_slope * (dwell - self.neutral_dwell)).tanh()
+            + self.interaction_weight * telemetry::interest_signal(interactions, video_duration);
+        
+        Feedback {
+            preference: preference.clamp(-1.0, 1.0),
+            confidence: self.watch_confidence * (0.5 + 0.5 * watch_percentage.clamp(0.0, 1.0)),
+        }
+    }
+    
+    pub fn like(&self, is_like: bool) -> Feedback {
+        if is_like {
+            Feedback { preference: 1.0, confidence: self.like_confidence }
+        } else {
+            Feedback { preference: -1.0, confidence: self.dislike_confidence }
+        }
+    }
+    
+    pub fn comment(&self) -> Feedback {
+        Feedback { preference: self.comment_preference, confidence: self.comment_confidence }
+    }
+    
+    pub fn share(&self) -> Feedback {
+        Feedback { preference: 1.0, confidence: self.share_confidence }
+    }
+    
+    // "Not interested" in a video or its whole channel.
+    pub fn negative(&self) -> Feedback {
+        Feedback { preference: -1.0, confidence: self.negative_confidence }
+    }
+}
@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::contextual::RequestContext;
+use crate::evaluation::Source;
+use crate::event_log::EngineEvent;
+use crate::experiments::VariantTag;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+const RECENT_PER_USER: usize = 20;
+const MIN_POSITION_SUPPORT: u64 = 30;
+const MIN_PROPENSITY: f64 = 0.05;
+const REPORTED_VIDEOS: usize = 20;
+const MAX_LOG_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImpressionItem {
+    pub video_id: String,
+    pub position: usize,
+    pub score: f64,
+    pub source_scores: HashMap<Source, f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rank_features: Vec<f64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Impression {
+    pub request_id: String,
+    pub user_id: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<VariantTag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<RequestContext>,
+    pub items: Vec<ImpressionItem>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutcomeKind {
+    Watch,
+    Like,
+    Share,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Outcome {
+    pub request_id: String,
+    pub user_id: String,
+    pub video_id: String,
+    pub position: usize,
+    pub kind: OutcomeKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watch_seconds: Option<f64>,
+    pub timestamp: DateTime<Utc>,
+    // The experiment variant that served the credited list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<VariantTag>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogEntry {
+    Impression(Impression),
+    Outcome(Outcome),
+}
+
+pub fn read_entries(path: &Path) -> io::Result<Vec<LogEntry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    
+    // A torn final line from a crash is skipped rather than failing startup,
+    // but a bad line anywhere else means the log is corrupt.
+    let mut entries = Vec::new();
+    let mut lines = BufReader::new(file).lines().enumerate().peekable();
+    while let Some((index, line)) = lines.next() {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(_) if lines.peek().is_none() => {}
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} line {}: {}", path.display(), index + 1, e),
+                ));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// Cuts off a line torn by a crash, so later appends do not follow it.
+fn trim_torn_line(file: &mut File) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    let mut end = len;
+    let mut byte = [0; 1];
+    while end > 0 {
+        file.seek(SeekFrom::Start(end - 1))?;
+        file.read_exact(&mut byte)?;
+        if byte[0] == b'\n' {
+            break;
+        }
+        end -= 1;
+    }
+    if end < len {
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Default)]
+struct SlotStats {
+    impressions: u64,
+    watches: u64,
+    likes: u64,
+    shares: u64,
+}
+
+impl SlotStats {
+    fn add(&mut self, kind: OutcomeKind) {
+        match kind {
+            OutcomeKind::Watch => self.watches += 1,
+            OutcomeKind::Like => self.likes += 1,
+            OutcomeKind::Share => self.shares += 1,
+        }
+    }
+}
+
+struct RecentImpression {
+    request_id: String,
+    timestamp: DateTime<Utc>,
+    variant: Option<VariantTag>,
+    positions: HashMap<String, usize>,
+    credited: HashSet<(String, OutcomeKind)>,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    writer: Option<File>,
+    log_bytes: u64,
+    lists: u64,
+    recent: HashMap<String, VecDeque<RecentImpression>>,
+    positions: Vec<SlotStats>,
+    videos: HashMap<String, Vec<SlotStats>>,
+}
+
+impl TrackerState {
+    fn slot(slots: &mut Vec<SlotStats>, position: usize) -> &mut SlotStats {
+        if slots.len() < position {
+            slots.resize(position, SlotStats::default());
+        }
+        &mut slots[position - 1]
+    }
+    
+    fn add_impression(&mut self, impression: &Impression) {
+        self.lists += 1;
+        for item in &impression.items {
+            Self::slot(&mut self.positions, item.position).impressions += 1;
+            Self::slot(self.videos.entry(item.video_id.clone()).or_default(), item.position).impressions += 1;
+        }
+        
+        let recent = self.recent.entry(impression.user_id.clone()).or_default();
+        recent.push_front(RecentImpression {
+            request_id: impression.request_id.clone(),
+            timestamp: impression.timestamp,
+            variant: impression.variant.clone(),
+            positions: impression.items.iter().map(|item| (item.video_id.clone(), item.position)).collect(),
+            credited: HashSet::new(),
+        });
+        recent.truncate(RECENT_PER_USER);
+    }
+    
+    fn add_outcome(&mut self, outcome: &Outcome) {
+        Self::slot(&mut self.positions, outcome.position).add(outcome.kind);
+        Self::slot(self.videos.entry(outcome.video_id.clone()).or_default(), outcome.position).add(outcome.kind);
+        
+        if let Some(impression) = self.recent.get_mut(&outcome.user_id)
+            .and_then(|recent| recent.iter_mut().find(|impression| impression.request_id == outcome.request_id))
+        {
+            impression.credited.insert((outcome.video_id.clone(), outcome.kind));
+        }
+    }
+    
+    fn add(&mut self, entry: &LogEntry) {
+        match entry {
+            LogEntry::Impression(impression) => self.add_impression(impression),
+            LogEntry::Outcome(outcome) => self.add_outcome(outcome),
+        }
+    }
+    
+    fn append(&mut self, entry: &LogEntry) -> io::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            let mut line = serde_json::to_string(entry)?;
+            line.push('\n');
+            writer.write_all(line.as_bytes())?;
+            self.log_bytes += line.len() as u64;
+        }
+        Ok(())
+    }
+    
+    // Rewrites the log with only the newest entries that fit in `keep_bytes`,
+    // dropping outcomes whose impression did not make it, and rebuilds the
+    // counts from what is left so they always describe the log.
+    fn compact(&mut self, path: &Path, keep_bytes: u64) -> io::Result<()> {
+        let entries = read_entries(path)?;
+        let mut lines = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            let mut line = serde_json::to_string(entry)?;
+            line.push('\n');
+            lines.push(line);
+        }
+        
+        let mut start = lines.len();
+        let mut bytes = 0;
+        while start > 0 && bytes + lines[start - 1].len() as u64 <= keep_bytes {
+            start -= 1;
+            bytes += lines[start].len() as u64;
+        }
+        let kept: HashSet<&str> = entries[start..].iter()
+            .filter_map(|entry| match entry {
+                LogEntry::Impression(impression) => Some(impression.request_id.as_str()),
+                LogEntry::Outcome(_) => None,
+            })
+            .collect();
+        let keep = |entry: &LogEntry| match entry {
+            LogEntry::Impression(_) => true,
+            LogEntry::Outcome(outcome) => kept.contains(outcome.request_id.as_str()),
+        };
+        
+        let temp = path.with_extension("log.tmp");
+        let mut file = File::create(&temp)?;
+        let mut state = TrackerState::default();
+        for (entry, line) in entries[start..].iter().zip(&lines[start..]).filter(|(entry, _)| keep(entry)) {
+            file.write_all(line.as_bytes())?;
+            state.log_bytes += line.len() as u64;
+            state.add(entry);
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        
+        state.writer = Some(OpenOptions::new().append(true).open(path)?);
+        *self = state;
+        Ok(())
+    }
+}
+
+// Every served list is appended to the impression log, followed by an outcome
+// line whenever a later watch, like or share can be traced back to it.
+pub struct ImpressionTracker {
+    path: Option<PathBuf>,
+    // Past this size the oldest half of the log is dropped.
+    max_log_bytes: u64,
+    attribution_window: Duration,
+    next_request: AtomicU64,
+    state: Mutex<TrackerState>,
+}
+
+impl Default for ImpressionTracker {
+    fn default() -> Self {
+        ImpressionTracker {
+            path: None,
+            max_log_bytes: MAX_LOG_BYTES,
+            attribution_window: Duration::hours(24),
+            next_request: AtomicU64::new(0),
+            state: Mutex::new(TrackerState::default()),
+        }
+    }
+}
+
+impl ImpressionTracker {
+    pub fn open(path: &Path, entries: &[LogEntry]) -> io::Result<Self> {
+        let mut state = TrackerState::default();
+        for entry in entries {
+            state.add(entry);
+        }
+        let mut writer = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        trim_torn_line(&mut writer)?;
+        state.log_bytes = writer.metadata()?.len();
+        state.writer = Some(writer);
+        
+        Ok(ImpressionTracker {
+            path: Some(path.to_path_buf()),
+            state: Mutex::new(state),
+            ..ImpressionTracker::default()
+        })
+    }
+    
+    // Everything logged so far, for training on. Trackers without a file
+    // have nothing to return.
+    pub fn read_log(&self) -> io::Result<Vec<LogEntry>> {
+        match &self.path {
+            Some(path) => read_entries(path),
+            None => Ok(Vec::new()),
+        }
+    }
+    
+    pub fn next_request_id(&self, now: DateTime<Utc>) -> String {
+        format!("{}-{}", now.timestamp_millis(), self.next_request.fetch_add(1, Ordering::Relaxed))
+    }
+    
+    pub fn log_impression(&self, impression: Impression) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.add_impression(&impression);
+        self.append(&mut state, &LogEntry::Impression(impression))
+    }
+    
+    // A failed compaction leaves the log as it was and is retried on the next
+    // append, so it does not fail the entry that triggered it.
+    fn append(&self, state: &mut TrackerState, entry: &LogEntry) -> io::Result<()> {
+        state.append(entry)?;
+        if state.log_bytes > self.max_log_bytes
+            && let Some(path) = &self.path
+            && let Err(e) = state.compact(path, self.max_log_bytes / 2)
+        {
+            eprintln!("Failed to compact impression log: {}", e);
+        }
+        Ok(())
+    }
+    
+    // Credits the event to the newest recent impression that showed the video
+    // to this user. Each slot is credited at most once per kind of outcome.
+    pub fn attribute(&self, event: &EngineEvent) -> io::Result<Option<Outcome>> {
+        let (user_id, video_id, kind, watch_seconds, timestamp) = match event {
+            EngineEvent::Watch { user_id, video_id, watch_duration, timestamp, .. } => {
+                (user_id, video_id, OutcomeKind::Watch, Some(watch_duration.as_secs_f64()), *timestamp)
+            }
+            EngineEvent::Like { user_id, video_id, is_like: true, timestamp } => {
+                (user_id, video_id, OutcomeKind::Like, None, *timestamp)
+            }
+            EngineEvent::Share { user_id, video_id, timestamp } => {
+                (user_id, video_id, OutcomeKind::Share, None, *timestamp)
+            }
+            _ => return Ok(None),
+        };
+        
+        let mut state = self.state.lock().unwrap();
+        let Some(impression) = state.recent.get(user_id).and_then(|recent| recent.iter().find(|impression| {
+            timestamp >= impression.timestamp
+                && timestamp - impression.timestamp <= self.attribution_window
+                && impression.positions.contains_key(video_id)
+                && !impression.credited.contains(&(video_id.clone(), kind))
+        })) else {
+            return Ok(None);
+        };
+        
+        let outcome = Outcome {
+            request_id: impression.request_id.clone(),
+            user_id: user_id.clone(),
+            video_id: video_id.clone(),
+            position: impression.positions[video_id],
+            kind,
+            watch_seconds,
+            timestamp,
+            variant: impression.variant.clone(),
+        };
+        state.add_outcome(&outcome);
+        self.append(&mut state, &LogEntry::Outcome(outcome.clone()))?;
+        Ok(Some(outcome))
+    }
+    
+    // Lower slots are seen less often, so raw CTR understates videos that were
+    // mostly shown there. The chance that a slot is examined is estimated from
+    // its watch rate relative to the top slot, falling back to 1 / position
+    // until the slot has enough impressions. Better videos also rank higher,
+    // so the estimate overstates position bias somewhat.
+    fn propensities(positions: &[SlotStats]) -> Vec<f64> {
+        let top_ctr = positions.first()
+            .filter(|top| top.impressions >= MIN_POSITION_SUPPORT && top.watches > 0)
+            .map(|top| top.watches as f64 / top.impressions as f64);
+        
+        positions.iter().enumerate().map(|(index, slot)| match top_ctr {
+            Some(top_ctr) if slot.impressions >= MIN_POSITION_SUPPORT => {
+                (slot.watches as f64 / slot.impressions as f64 / top_ctr).clamp(MIN_PROPENSITY, 1.0)
+            }
+            _ => 1.0 / (index + 1) as f64,
+        }).collect()
+    }
+    
+    pub fn report(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let propensities = Self::propensities(&state.positions);
+        let ratio = |numerator: f64, denominator: u64| {
+            if denominator > 0 { numerator / denominator as f64 } else { 0.0 }
+        };
+        
+        let mut totals = SlotStats::default();
+        let by_position: Vec<_> = state.positions.iter().zip(&propensities).enumerate()
+            .map(|(index, (slot, propensity))| {
+                totals.impressions += slot.impressions;
+                totals.watches += slot.watches;
+                totals.likes += slot.likes;
+                totals.shares += slot.shares;
+                serde_json::json!({
+                    "position": index + 1,
+                    "impressions": slot.impressions,
+                    "watches": slot.watches,
+                    "likes": slot.likes,
+                    "shares": slot.shares,
+                    "ctr": ratio(slot.watches as f64, slot.impressions),
+                    "propensity": propensity,
+                })
+            })
+            .collect();
+        
+        let mut videos: Vec<_> = state.videos.iter().map(|(video_id, slots)| {
+            let impressions: u64 = slots.iter().map(|slot| slot.impressions).sum();
+            let watches: u64 = slots.iter().map(|slot| slot.watches).sum();
+            let weighted_watches: f64 = slots.iter().zip(&propensities)
+                .map(|(slot, propensity)| slot.watches as f64 / propensity)
+                .sum();
+            (video_id, impressions, watches, weighted_watches)
+        }).collect();
+        videos.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        
+        let videos: Vec<_> = videos.into_iter().take(REPORTED_VIDEOS)
+            .map(|(video_id, impressions, watches, weighted_watches)| serde_json::json!({
+                "videoId": video_id,
+                "impressions": impressions,
+                "watches": watches,
+                "ctr": ratio(watches as f64, impressions),
+                "debiasedCtr": ratio(weighted_watches, impressions),
+            }))
+            .collect();
+        
+        serde_json::json!({
+            "lists": state.lists,
+            "impressions": totals.impressions,
+            "watches": totals.watches,
+            "likes": totals.likes,
+            "shares": totals.shares,
+            "ctr": ratio(totals.watches as f64, totals.impressions),
+            "attributionWindowHours": self.attribution_window.num_hours(),
+            "byPosition": by_position,
+            "videos": videos,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("recommend-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("impressions.log")
+    }
+    
+    fn impression(timestamp: DateTime<Utc>) -> Impression {
+        Impression {
+            request_id: format!("request{}", timestamp.timestamp_millis()),
+            user_id: "user1".to_string(),
+            timestamp,
+            variant: None,
+            context: None,
+            items: vec![ImpressionItem {
+                video_id: "video1".to_string(),
+                position: 1,
+                score: 1.0,
+                source_scores: HashMap::new(),
+                features: Vec::new(),
+                rank_features: Vec::new(),
+            }],
+        }
+    }
+    
+    fn share(timestamp: DateTime<Utc>) -> EngineEvent {
+        EngineEvent::Share {
+            user_id: "user1".to_string(),
+            video_id: "video1".to_string(),
+            timestamp,
+        }
+    }
+    
+    #[test]
+    fn attributes_only_inside_the_window() {
+        let served = Utc::now();
+        let tracker = ImpressionTracker::default();
+        tracker.log_impression(impression(served)).unwrap();
+        
+        assert!(tracker.attribute(&share(served - Duration::minutes(5))).unwrap().is_none());
+        assert!(tracker.attribute(&share(served + Duration::hours(25))).unwrap().is_none());
+        
+        let outcome = tracker.attribute(&share(served + Duration::minutes(5))).unwrap().unwrap();
+        assert_eq!(outcome.request_id, format!("request{}", served.timestamp_millis()));
+        assert_eq!(outcome.position, 1);
+        assert!(tracker.attribute(&share(served + Duration::minutes(6))).unwrap().is_none());
+    }
+    
+    #[test]
+    fn only_a_torn_final_line_is_skipped() {
+        let path = temp_path("impressions-torn");
+        let line = serde_json::to_string(&LogEntry::Impression(impression(Utc::now()))).unwrap();
+        
+        fs::write(&path, format!("{}\n{}\n{{\"type\":\"impr", line, line)).unwrap();
+        assert_eq!(read_entries(&path).unwrap().len(), 2);
+        
+        // Appending after a torn line would bury it mid-file, so it is cut off.
+        let entries = read_entries(&path).unwrap();
+        let tracker = ImpressionTracker::open(&path, &entries).unwrap();
+        tracker.log_impression(impression(Utc::now())).unwrap();
+        assert_eq!(read_entries(&path).unwrap().len(), 3);
+        
+        fs::write(&path, format!("{}\nnot json\n{}\n", line, line)).unwrap();
+        assert_eq!(read_entries(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+    
+    #[test]
+    fn compaction_keeps_the_newest_entries() {
+        let path = temp_path("impressions-compact");
+        let mut tracker = ImpressionTracker::open(&path, &[]).unwrap();
+        let line = serde_json::to_string(&LogEntry::Impression(impression(Utc::now()))).unwrap();
+        tracker.max_log_bytes = 10 * (line.len() as u64 + 1);
+        
+        let start = Utc::now();
+        for i in 0..10 {
+            tracker.log_impression(impression(start + Duration::seconds(i))).unwrap();
+        }
+        tracker.attribute(&share(start + Duration::seconds(10))).unwrap().unwrap();
+        
+        // The outcome pushed the log over its limit. It belongs to the newest
+        // impression, so it stays along with as many others as fit in half.
+        let entries = read_entries(&path).unwrap();
+        let impressions = entries.iter().filter(|entry| matches!(entry, LogEntry::Impression(_))).count();
+        assert!((1..5).contains(&impressions), "{} impressions kept", impressions);
+        assert!(matches!(entries.last(), Some(LogEntry::Outcome(_))));
+        assert_eq!(tracker.report()["lists"], impressions);
+        assert!(fs::metadata(&path).unwrap().len() <= tracker.max_log_bytes / 2);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,187 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+
+use crate::randomness::Jitter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Team {
+    A,
+    B,
+}
+
+// Team-draft interleaving: the ranker with fewer picks so far adds its best
+// video not yet in the list, with a coin flip deciding who goes first when
+// they are level. Each slot remembers the team that picked it.
+pub fn team_draft(a: &[String], b: &[String], count: usize, jitter: &mut Jitter) -> Vec<(String, Team)> {
+    let mut merged = Vec::new();
+    let mut seen = HashSet::new();
+    let (mut next_a, mut next_b) = (0, 0);
+    let (mut picks_a, mut picks_b) = (0, 0);
+    
+    while merged.len() < count {
+        while next_a < a.len() && seen.contains(&a[next_a]) {
+            next_a += 1;
+        }
+        while next_b < b.len() && seen.contains(&b[next_b]) {
+            next_b += 1;
+        }
+        
+        let team = match (next_a < a.len(), next_b < b.len()) {
+            (false, false) => break,
+            (true, false) => Team::A,
+            (false, true) => Team::B,
+            (true, true) if picks_a < picks_b || (picks_a == picks_b && jitter.unit() < 0.5) => Team::A,
+            (true, true) => Team::B,
+        };
+        
+        let video_id = match team {
+            Team::A => {
+                picks_a += 1;
+                &a[next_a]
+            }
+            Team::B => {
+                picks_b += 1;
+                &b[next_b]
+            }
+        };
+        seen.insert(video_id.clone());
+        merged.push((video_id.clone(), team));
+    }
+    
+    merged
+}
+
+struct PendingImpression {
+    teams: HashMap<String, Team>,
+    clicked: HashSet<String>,
+    clicks_a: u64,
+    clicks_b: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Outcomes {
+    pub impressions: u64,
+    pub wins_a: u64,
+    pub wins_b: u64,
+    pub ties: u64,
+    pub no_clicks: u64,
+    pub clicks_a: u64,
+    pub clicks_b: u64,
+}
+
+impl Outcomes {
+    fn settle(&mut self, impression: &PendingImpression) {
+        match impression.clicks_a.cmp(&impression.clicks_b) {
+            Ordering::Greater => self.wins_a += 1,
+            Ordering::Less => self.wins_b += 1,
+            Ordering::Equal if impression.clicks_a > 0 => self.ties += 1,
+            Ordering::Equal => self.no_clicks += 1,
+        }
+    }
+}
+
+// An impression stays open until the user is served their next interleaved
+// list, so watches in between are credited to the team that placed the video.
+#[derive(Default)]
+pub struct InterleavingStats {
+    settled: Outcomes,
+    pending: HashMap<String, PendingImpression>,
+}
+
+impl InterleavingStats {
+    pub fn record_impression(&mut self, user_id: &str, slots: &[(String, Team)]) {
+        let impression = PendingImpression {
+            teams: slots.iter().cloned().collect(),
+            clicked: HashSet::new(),
+            clicks_a: 0,
+            clicks_b: 0,
+        };
+        if let Some(previous) = self.pending.insert(user_id.to_string(), impression) {
+            self.settled.settle(&previous);
+        }
+        self.settled.impressions += 1;
+    }
+    
+    // Only the first watch of each slot counts.
+    pub fn record_click(&mut self, user_id: &str, video_id: &str) {
+        let Some(impression) = self.pending.get_mut(user_id) else {
+            return;
+        };
+        let Some(&team) = impression.teams.get(video_id) else {
+            return;
+        };
+        if !impression.clicked.insert(video_id.to_string()) {
+            return;
+        }
+        
+        match team {
+            Team::A => {
+                impression.clicks_a += 1;
+                self.settled.clicks_a += 1;
+            }
+            Team::B => {
+                impression.clicks_b += 1;
+                self.settled.clicks_b += 1;
+            }
+        }
+    }
+    
+    // Open impressions are scored as they stand.
+    pub fn outcomes(&self) -> Outcomes {
+        let mut outcomes = self.settled;
+        for impression in self.pending.values() {
+            outcomes.settle(impression);
+        }
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::*;
+    use crate::randomness::RngPolicy;
+    
+    fn ids(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+    
+    #[test]
+    fn team_draft_alternates_and_skips_duplicates() {
+        // Without jitter the coin flip always goes to B.
+        let mut jitter = RngPolicy::Off.jitter("interleaving", "user1", Utc::now());
+        let merged = team_draft(&ids(&["x", "y", "z"]), &ids(&["y", "w", "x"]), 4, &mut jitter);
+        assert_eq!(merged, vec![
+            ("y".to_string(), Team::B),
+            ("x".to_string(), Team::A),
+            ("w".to_string(), Team::B),
+            ("z".to_string(), Team::A),
+        ]);
+    }
+    
+    #[test]
+    fn clicks_credit_the_team_that_placed_the_video() {
+        let mut stats = InterleavingStats::default();
+        let slots = vec![("x".to_string(), Team::A), ("y".to_string(), Team::B), ("w".to_string(), Team::B)];
+        
+        stats.record_impression("user1", &slots);
+        stats.record_click("user1", "y");
+        stats.record_click("user1", "y");
+        stats.record_click("user1", "x");
+        stats.record_click("user1", "w");
+        stats.record_click("user1", "unserved");
+        stats.record_click("user2", "x");
+        
+        // The next list settles the first one; it is scored while still open.
+        stats.record_impression("user1", &slots);
+        stats.record_click("user1", "x");
+        stats.record_impression("user2", &slots);
+        
+        let outcomes = stats.outcomes();
+        assert_eq!(outcomes.impressions, 3);
+        assert_eq!((outcomes.wins_a, outcomes.wins_b, outcomes.ties, outcomes.no_clicks), (1, 1, 0, 1));
+        assert_eq!((outcomes.clicks_a, outcomes.clicks_b), (2, 2));
+    }
+}
@@ -1,23 +1,75 @@
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
 use actix_cors::Cors;
-use actix_files;
 use serde::{Deserialize, Serialize};
+use aggregates::Aggregation;
 use collaborative_filtering::collaborative_filtering_recommendations;
 use content_based::content_based_recommendations;
 use popularity_based::popularity_based_recommendations;
-use temporal::get_temporal_recommendations;
+use temporal::{TemporalProfile, get_temporal_recommendations, learn_watch_time, rebuild_temporal_profile};
 use engagement::get_engagement_recommendations;
+use event_log::{EngineEvent, EventLog, EventLogConfig, FsyncPolicy};
+use contextual::{ContextualBandit, ContextualConfig, RequestContext};
+use decay::DecayConfig;
+use evaluation::{EvaluationConfig, EvaluationReport, Source};
+use exploration::{ExplorationConfig, ExplorationLayer, ExplorationPolicy};
+use experiments::{Assignment, BlendingMode, EXPERIMENT_HEADER, ExperimentDefinition, ExperimentRegistry, Ranking, RegisterError, VariantConfig, VariantTag};
+use implicit::{Feedback, ImplicitConfig};
+use impressions::{Impression, ImpressionItem, ImpressionTracker, REQUEST_ID_HEADER};
+use interleaving::Team;
+use tuning::{Metric, Strategy, TuningConfig};
+use export::{ChunkWriter, Dataset, ExportFormat};
+use randomness::{Jitter, RngPolicy};
+use ranker::{ModelStore, RankerChoice, RankerModel, TrainError, TrainingOptions};
+use recommendation_cache::{CacheConfig, RecommendationCache};
+use negative::{NegativeFeedback, NegativeTarget};
+use patterns::EventCounts;
+use retention::{RetentionConfig, RetentionCounts};
+use sequence::{SequenceConfig, SequenceModel};
+use sessions::{SESSION_HEADER, SessionConfig, SessionModel};
+use simulation::{Intensity, SimulationConfig, Simulator, StartError};
+use sqlite_storage::SqliteStorage;
+use storage::{MemoryStorage, Rating, Storage};
+use telemetry::{WatchSessionRequest, watch_duration};
 
+pub mod aggregates;
 pub mod analytics;
+pub mod bench;
+pub mod event_log;
+pub mod export;
+pub mod randomness;
+pub mod ranker;
+pub mod recommendation_cache;
+pub mod negative;
+pub mod patterns;
+pub mod retention;
+pub mod sequence;
+pub mod sessions;
+pub mod sharded;
+pub mod simulation;
+pub mod sqlite_storage;
+pub mod storage;
+pub mod telemetry;
 pub mod web_server;
 pub mod temporal;
+pub mod tuning;
 pub mod engagement;
+pub mod evaluation;
+pub mod experiments;
+pub mod exploration;
+pub mod implicit;
+pub mod impressions;
+pub mod interleaving;
 pub mod collaborative_filtering;
+pub mod decay;
 pub mod content_based;
+pub mod contextual;
 pub mod popularity_based;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -27,15 +79,31 @@ pub struct User {
     watch_history: Vec<WatchEvent>,
     content_preferences: HashMap<String, f64>,
     interaction_patterns: InteractionPatterns,
+    #[serde(default)]
+    timezone: Tz,
+    #[serde(default)]
+    preferences_updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    short_term_preferences: HashMap<String, f64>,
+    #[serde(default)]
+    short_term_updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    negative_feedback: NegativeFeedback,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct InteractionPatterns {
     avg_watch_percentage: f64,
     avg_comment_length: usize,
     time_of_day_preferences: HashMap<u8, f64>,
+    #[serde(default)]
+    day_of_week_preferences: HashMap<u8, f64>,
     like_to_view_ratio: f64,
     share_frequency: f64,
+    #[serde(default)]
+    temporal_profile: TemporalProfile,
+    #[serde(default)]
+    counts: EventCounts,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -63,6 +131,8 @@ pub struct VideoMetrics {
     completion_rate: f64,
     retention_curve: Vec<(f32, f32)>,
     rewatch_rate: f64,
+    #[serde(default)]
+    retention: RetentionCounts,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -101,27 +171,317 @@ pub enum Interaction {
 }
 
 pub struct RecommendationEngine {
-    users: Arc<Mutex<HashMap<String, User>>>,
-    videos: Arc<Mutex<HashMap<String, Video>>>,
-    comments: Arc<Mutex<HashMap<String, Comment>>>,
-    
-    user_video_matrix: Arc<Mutex<HashMap<String, HashMap<String, f64>>>>,
+    storage: Arc<dyn Storage>,
     _video_similarity_matrix: Arc<Mutex<HashMap<String, HashMap<String, f64>>>>,
+    
+    event_log: Mutex<Option<EventLog>>,
+    recommendation_cache: Arc<RecommendationCache>,
+    config: EngineConfig,
+    latest_evaluation: RwLock<Option<EvaluationReport>>,
+    experiments: ExperimentRegistry,
+    impressions: ImpressionTracker,
+    exploration: ExplorationLayer,
+    contextual: ContextualBandit,
+    models: Option<ModelStore>,
+    ranker: RwLock<Option<Arc<RankerModel>>>,
+    sessions: SessionModel,
+    sequence: SequenceModel,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EngineConfig {
+    cache: CacheConfig,
+    rng: RngPolicy,
+    blend: BlendConfig,
+    exploration: ExplorationConfig,
+    contextual: ContextualConfig,
+    ranker: RankerChoice,
+    sessions: SessionConfig,
+    sequence: SequenceConfig,
+    decay: DecayConfig,
+    retention: RetentionConfig,
+    aggregation: Aggregation,
+    implicit: ImplicitConfig,
+}
+
+// How much each source contributes to the final ranking, and how many
+// candidates the collaborative and content-based sources put forward per
+// requested recommendation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlendConfig {
+    collaborative: f64,
+    content_based: f64,
+    popularity: f64,
+    temporal: f64,
+    engagement: f64,
+    session: f64,
+    sequence: f64,
+    candidate_multiplier: usize,
+}
+
+// A ranked video with the raw score each source gave it, kept so a served list
+// can be logged together with the evidence behind it.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    video_id: String,
+    score: f64,
+    source_scores: HashMap<Source, f64>,
+}
+
+struct ServedRecommendations {
+    request_id: String,
+    videos: Vec<Video>,
+    variant: Option<VariantTag>,
+    session_id: Option<String>,
+}
+
+impl Default for BlendConfig {
+    fn default() -> Self {
+        BlendConfig {
+            collaborative: 0.35,
+            content_based: 0.25,
+            popularity: 0.15,
+            temporal: 0.10,
+            engagement: 0.15,
+            session: 0.25,
+            sequence: 0.20,
+            candidate_multiplier: 2,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EngineState {
+    users: HashMap<String, User>,
+    videos: HashMap<String, Video>,
+    comments: HashMap<String, Comment>,
+    user_video_matrix: HashMap<String, HashMap<String, Rating>>,
 }
 
 impl RecommendationEngine {
-    fn new() -> Self {
+    fn with_storage(storage: Arc<dyn Storage>, config: EngineConfig) -> Self {
         RecommendationEngine {
-            users: Arc::new(Mutex::new(HashMap::new())),
-            videos: Arc::new(Mutex::new(HashMap::new())),
-            comments: Arc::new(Mutex::new(HashMap::new())),
-            user_video_matrix: Arc::new(Mutex::new(HashMap::new())),
+            storage,
             _video_similarity_matrix: Arc::new(Mutex::new(HashMap::new())),
+            event_log: Mutex::new(None),
+            recommendation_cache: Arc::new(RecommendationCache::new(config.cache.clone())),
+            latest_evaluation: RwLock::new(None),
+            experiments: ExperimentRegistry::default(),
+            impressions: ImpressionTracker::default(),
+            exploration: ExplorationLayer::new(config.exploration.clone()),
+            contextual: ContextualBandit::new(config.contextual.clone()),
+            models: None,
+            ranker: RwLock::new(None),
+            sessions: SessionModel::new(config.sessions.clone()),
+            sequence: SequenceModel::new(config.sequence.clone()),
+            config,
+        }
+    }
+    
+    fn recover(storage: Arc<dyn Storage>, log_config: EventLogConfig, config: EngineConfig) -> io::Result<Self> {
+        let impressions_path = log_config.dir.join("impressions.log");
+        let models = ModelStore::new(log_config.dir.join("models"));
+        let log = EventLog::open(log_config)?;
+        let mut engine = RecommendationEngine::with_storage(storage, config);
+        let entries = impressions::read_entries(&impressions_path)?;
+        engine.impressions = ImpressionTracker::open(&impressions_path, &entries)?;
+        engine.exploration.replay(&entries);
+        engine.contextual.replay(&entries);
+        *engine.ranker.get_mut().unwrap() = models.load_choice(engine.config.ranker)?.map(Arc::new);
+        engine.models = Some(models);
+        
+        let replay_from = match engine.storage.committed_seq()? {
+            Some(seq) => seq,
+            None => {
+                let seq = match log.load_snapshot::<EngineState>()? {
+                    Some((seq, state)) => {
+                        engine.storage.begin_event()?;
+                        engine.restore_state(state)?;
+                        seq
+                    }
+                    None => {
+                        engine.storage.begin_event()?;
+                        engine.add_dummy_data()?;
+                        0
+                    }
+                };
+                engine.storage.commit_event(seq)?;
+                seq
+            }
+        };
+        
+        engine.storage.begin_event()?;
+        engine.backfill_temporal_profiles()?;
+        engine.backfill_retention()?;
+        engine.storage.commit_event(replay_from)?;
+        engine.sessions.load_history(&*engine.storage)?;
+        engine.sequence.load_history(&*engine.storage)?;
+        let records = log.replay_after(replay_from)?;
+        eprintln!("Replaying {} logged events after seq {}...", records.len(), replay_from);
+        for record in &records {
+            engine.apply_logged(&record.event, record.seq)?;
+        }
+        
+        *engine.event_log.lock().unwrap() = Some(log);
+        Ok(engine)
+    }
+    
+    // The event log lock is always taken first and is held while the event is
+    // applied, so writers reach storage in log order and a snapshot never sees
+    // a half-applied event. Storage shard and cache locks are only ever taken
+    // inside it, one at a time, and readers never take it at all.
+    fn record(&self, event: EngineEvent) -> io::Result<()> {
+        if let EngineEvent::Watch { video_id, .. } = &event
+            && self.storage.get_video(video_id)?.is_none()
+        {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown video: {}", video_id)));
+        }
+        let assignment = self.assignment(event.user_id());
+        
+        let mut event_log = self.event_log.lock().unwrap();
+        let seq = match event_log.as_mut() {
+            Some(log) => log.append(&event, assignment.as_ref().map(|assignment| assignment.tag()))?,
+            None => 0,
+        };
+        
+        // An event that cannot be applied is taken back out of the log, so a
+        // retry is not recorded twice.
+        if let Err(e) = self.apply_logged(&event, seq) {
+            if let Some(log) = event_log.as_mut()
+                && let Err(undo) = log.undo_append()
+            {
+                eprintln!("Failed to remove unapplied event {} from the log: {}", seq, undo);
+            }
+            return Err(e);
+        }
+        
+        if let Some(assignment) = &assignment {
+            self.experiments.record_outcome(assignment, &event);
+        }
+        match self.impressions.attribute(&event) {
+            Ok(Some(outcome)) => {
+                self.exploration.record_outcome(&outcome);
+                self.contextual.record_outcome(&outcome);
+                self.experiments.record_attributed(&outcome);
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to log impression outcome: {}", e),
         }
+        
+        // The event is already logged and applied, so a failed checkpoint must
+        // not be reported as a failed event; it is retried on the next one.
+        if let Some(log) = event_log.as_mut()
+            && log.snapshot_due()
+            && let Err(e) = self.write_checkpoint(log)
+        {
+            eprintln!("Failed to write checkpoint: {}", e);
+        }
+        Ok(())
+    }
+    
+    // Applies a logged event as one storage transaction, rolled back if any
+    // part of it fails.
+    fn apply_logged(&self, event: &EngineEvent, seq: u64) -> io::Result<()> {
+        self.storage.begin_event()?;
+        let result = self.apply_event(event).and_then(|()| self.storage.commit_event(seq));
+        if result.is_err() {
+            self.storage.rollback_event();
+        }
+        result
     }
     
-    fn add_dummy_data(&mut self) {
-        let mut users = self.users.lock().unwrap();
+    fn apply_event(&self, event: &EngineEvent) -> io::Result<()> {
+        match event {
+            EngineEvent::Watch { user_id, video_id, watch_duration, interactions, timestamp } => {
+                self.process_watch(user_id, video_id, *watch_duration, interactions, *timestamp)?;
+            }
+            EngineEvent::Like { user_id, video_id, is_like, timestamp } => {
+                self.process_like(user_id, video_id, *is_like, *timestamp)?;
+            }
+            EngineEvent::Comment { comment_id, user_id, video_id, text, timestamp } => {
+                self.process_comment(comment_id, user_id, video_id, text, *timestamp)?;
+            }
+            EngineEvent::Share { user_id, video_id, timestamp } => {
+                self.process_share(user_id, video_id, *timestamp)?;
+            }
+            EngineEvent::Subscribe { user_id, channel_id, .. } => {
+                self.process_subscribe(user_id, channel_id)?;
+            }
+            EngineEvent::CreateUser { user, .. } => {
+                self.storage.put_user(User::clone(user))?;
+            }
+            EngineEvent::SetTimezone { user_id, timezone, .. } => {
+                self.process_set_timezone(user_id, *timezone)?;
+            }
+            EngineEvent::RecomputePatterns { user_id, .. } => {
+                self.recompute_patterns(user_id)?;
+            }
+            EngineEvent::NegativeFeedback { user_id, target, timestamp } => {
+                self.storage.update_user(user_id, &mut |user| user.negative_feedback.add(target, *timestamp))?;
+            }
+            EngineEvent::UndoNegativeFeedback { user_id, target, .. } => {
+                self.storage.update_user(user_id, &mut |user| user.negative_feedback.remove(target))?;
+            }
+        }
+        
+        self.recommendation_cache.invalidate(event.user_id());
+        Ok(())
+    }
+    
+    fn checkpoint(&self) -> io::Result<()> {
+        match self.event_log.lock().unwrap().as_mut() {
+            Some(log) => self.write_checkpoint(log),
+            None => Ok(()),
+        }
+    }
+    
+    fn write_checkpoint(&self, log: &mut EventLog) -> io::Result<()> {
+        if self.storage.committed_seq()?.is_some() {
+            return log.compact();
+        }
+        
+        let mut state = EngineState {
+            users: HashMap::new(),
+            videos: HashMap::new(),
+            comments: HashMap::new(),
+            user_video_matrix: HashMap::new(),
+        };
+        self.storage.for_each_user(&mut |user| {
+            state.users.insert(user.id.clone(), user.clone());
+        })?;
+        self.storage.for_each_video(&mut |video| {
+            state.videos.insert(video.id.clone(), video.clone());
+        })?;
+        self.storage.for_each_comment(&mut |comment| {
+            state.comments.insert(comment.id.clone(), comment.clone());
+        })?;
+        self.storage.for_each_user_ratings(&mut |user_id, ratings| {
+            state.user_video_matrix.insert(user_id.to_string(), ratings.clone());
+        })?;
+        
+        log.write_snapshot(&state)
+    }
+    
+    fn restore_state(&self, state: EngineState) -> io::Result<()> {
+        for user in state.users.into_values() {
+            self.storage.put_user(user)?;
+        }
+        for video in state.videos.into_values() {
+            self.storage.put_video(video)?;
+        }
+        for comment in state.comments.into_values() {
+            self.storage.put_comment(comment)?;
+        }
+        for (user_id, ratings) in state.user_video_matrix {
+            for (video_id, rating) in ratings {
+                self.storage.put_rating(&user_id, &video_id, rating)?;
+            }
+        }
+        Ok(())
+    }
+    
+    fn add_dummy_data(&self) -> io::Result<()> {
         
         let user1 = User {
             id: "user1".to_string(),
@@ -134,9 +494,17 @@ impl RecommendationEngine {
                 avg_comment_length: 15,
                 time_of_day_preferences: [(20, 0.9), (21, 0.8)].iter()
                     .map(|(h, v)| (*h, *v)).collect(),
+                day_of_week_preferences: HashMap::new(),
                 like_to_view_ratio: 0.3,
                 share_frequency: 0.05,
-            }
+                temporal_profile: TemporalProfile::default(),
+                counts: EventCounts::default(),
+            },
+            timezone: Tz::UTC,
+            preferences_updated_at: None,
+            short_term_preferences: HashMap::new(),
+            short_term_updated_at: None,
+            negative_feedback: NegativeFeedback::default(),
         };
         
         let user2 = User {
@@ -150,15 +518,21 @@ impl RecommendationEngine {
                 avg_comment_length: 5,
                 time_of_day_preferences: [(12, 0.6), (18, 0.8)].iter()
                     .map(|(h, v)| (*h, *v)).collect(),
+                day_of_week_preferences: HashMap::new(),
                 like_to_view_ratio: 0.2,
                 share_frequency: 0.02,
-            }
+                temporal_profile: TemporalProfile::default(),
+                counts: EventCounts::default(),
+            },
+            timezone: Tz::UTC,
+            preferences_updated_at: None,
+            short_term_preferences: HashMap::new(),
+            short_term_updated_at: None,
+            negative_feedback: NegativeFeedback::default(),
         };
         
-        users.insert("user1".to_string(), user1);
-        users.insert("user2".to_string(), user2);
-        
-        let mut videos = self.videos.lock().unwrap();
+        self.storage.put_user(user1)?;
+        self.storage.put_user(user2)?;
         
         let video1 = Video {
             id: "video1".to_string(),
@@ -177,8 +551,9 @@ impl RecommendationEngine {
                 avg_watch_time: Duration::from_secs(450),
                 avg_watch_percentage: 0.75,
                 completion_rate: 0.65,
-                retention_curve: vec![(0.0, 1.0), (0.5, 0.8), (1.0, 0.6)],
-                rewatch_rate: 0.1,
+                retention_curve: Vec::new(),
+                rewatch_rate: 0.0,
+                retention: RetentionCounts::default(),
             },
             embedding: vec![0.1, 0.2, 0.3, 0.4, 0.5],
         };
@@ -200,8 +575,9 @@ impl RecommendationEngine {
                 avg_watch_time: Duration::from_secs(840),
                 avg_watch_percentage: 0.7,
                 completion_rate: 0.55,
-                retention_curve: vec![(0.0, 1.0), (0.5, 0.75), (1.0, 0.5)],
-                rewatch_rate: 0.15,
+                retention_curve: Vec::new(),
+                rewatch_rate: 0.0,
+                retention: RetentionCounts::default(),
             },
             embedding: vec![0.5, 0.4, 0.3, 0.2, 0.1],
         };
@@ -223,193 +599,726 @@ impl RecommendationEngine {
                 avg_watch_time: Duration::from_secs(270),
                 avg_watch_percentage: 0.9,
                 completion_rate: 0.85,
-                retention_curve: vec![(0.0, 1.0), (0.5, 0.95), (1.0, 0.85)],
-                rewatch_rate: 0.4,
+                retention_curve: Vec::new(),
+                rewatch_rate: 0.0,
+                retention: RetentionCounts::default(),
             },
             embedding: vec![0.2, 0.3, 0.5, 0.3, 0.2],
         };
         
-        videos.insert("video1".to_string(), video1);
-        videos.insert("video2".to_string(), video2);
-        videos.insert("video3".to_string(), video3);
+        self.storage.put_video(video1)?;
+        self.storage.put_video(video2)?;
+        self.storage.put_video(video3)?;
+        Ok(())
     }
     
-    fn recommend_videos(&self, user_id: &str, count: usize) -> Vec<Video> {
-        let mut recommendations = Vec::new();
+    fn cached_recommendations(&self, user_id: &str, count: usize) -> io::Result<Vec<Candidate>> {
+        if let Some(candidates) = self.recommendation_cache.get(user_id, count) {
+            return Ok(candidates.iter().take(count).cloned().collect());
+        }
         
-        let collaborative_recs = self.collaborative_filtering_recommendations(user_id, count * 2);
-        let content_based_recs = self.content_based_recommendations(user_id, count * 2);
-        let popularity_recs = self.popularity_based_recommendations(count);
-        let temporal_recs = self.temporal_recommendations(user_id, count);
-        let engagement_recs = self.engagement_based_recommendations(user_id, count);
+        let generation = self.recommendation_cache.generation(user_id);
+        let mut candidates = self.recommend_candidates(
+            user_id, count.max(self.recommendation_cache.config().precompute_count), self.config.rng, None, Utc::now())?;
+        self.recommendation_cache.insert(user_id, generation, candidates.clone());
         
-        let mut scored_videos: HashMap<String, f64> = HashMap::new();
+        candidates.truncate(count);
+        Ok(candidates)
+    }
+    
+    fn precompute_recommendations(&self, user_id: &str) -> io::Result<()> {
+        let generation = self.recommendation_cache.generation(user_id);
+        let candidates = self.recommend_candidates(
+            user_id, self.recommendation_cache.config().precompute_count, self.config.rng, None, Utc::now())?;
+        self.recommendation_cache.insert(user_id, generation, candidates);
+        self.recommendation_cache.record_precomputed();
+        Ok(())
+    }
+    
+    // The serving path: the only place exposures are counted towards
+    // experiment results and the only place impressions are logged. An
+    // explicit seed pins the result and a named session changes it, so either
+    // bypasses the shared cache.
+    fn serve_recommendations(
+        &self,
+        user_id: &str,
+        count: usize,
+        seed: Option<u64>,
+        context: Option<&RequestContext>,
+        session_id: Option<&str>,
+    ) -> io::Result<ServedRecommendations> {
+        let now = Utc::now();
+        let assignment = self.assignment(user_id);
+        let rng_policy = seed.map_or(self.config.rng, RngPolicy::Fixed);
         
-        let cf_weight = 0.35;
-        let cb_weight = 0.25;
-        let pop_weight = 0.15;
-        let temp_weight = 0.10;
-        let eng_weight = 0.15;
+        // Interleaved lists are credited slot by slot, so they are built fresh
+        // for every request to know exactly which ranker placed each video,
+        // and are not re-ranked by the contextual bandit.
+        let mut features = HashMap::new();
+        let mut teams = HashMap::new();
+        let candidates = if let Some(assignment) = &assignment
+            && let Ranking::Interleaved(a, b) = assignment.ranking()
+        {
+            let slots = self.interleave_candidates(user_id, count, rng_policy, session_id, (a, b), now)?;
+            slots.into_iter()
+                .map(|(candidate, team)| {
+                    teams.insert(candidate.video_id.clone(), team);
+                    candidate
+                })
+                .collect()
+        } else {
+            let pool = self.contextual.pool_size(count, context);
+            let candidates = match (seed, session_id) {
+                (None, None) => self.cached_recommendations(user_id, pool)?,
+                _ => self.recommend_candidates(user_id, pool, rng_policy, session_id, now)?,
+            };
+            match context {
+                Some(context) => self.contextual.rerank(user_id, context, candidates, count, &*self.storage)?
+                    .into_iter()
+                    .map(|(candidate, item)| {
+                        features.insert(candidate.video_id.clone(), item);
+                        candidate
+                    })
+                    .collect(),
+                None => candidates,
+            }
+        };
         
-        for (video_id, score) in collaborative_recs {
-            *scored_videos.entry(video_id).or_default() += score * cf_weight;
+        let candidates = self.explore(user_id, count, candidates, rng_policy, now)?;
+        let (candidates, videos): (Vec<_>, Vec<_>) = self.with_videos(candidates)?.into_iter().unzip();
+        if let Some(assignment) = &assignment {
+            // Only interleaved slots that survived exploration and the catalog
+            // check were shown, so only they can earn a ranker credit.
+            let slots: Vec<_> = candidates.iter()
+                .filter_map(|candidate| teams.get(&candidate.video_id).map(|team| (candidate.video_id.clone(), *team)))
+                .collect();
+            match assignment.ranking() {
+                Ranking::Interleaved(..) => self.experiments.record_interleaved_exposure(assignment, user_id, &slots),
+                Ranking::Single(_) => self.experiments.record_exposure(assignment, user_id, videos.len()),
+            }
+        }
+        
+        let user = self.current_user(user_id, now)?;
+        let impression = Impression {
+            request_id: self.impressions.next_request_id(now),
+            user_id: user_id.to_string(),
+            timestamp: now,
+            variant: assignment.as_ref().map(Assignment::tag),
+            context: context.cloned(),
+            items: candidates.into_iter().zip(&videos).enumerate().map(|(index, (candidate, video))| ImpressionItem {
+                features: features.remove(&candidate.video_id).unwrap_or_default(),
+                rank_features: ranker::features(&candidate, video, user.as_ref(), now),
+                video_id: candidate.video_id,
+                position: index + 1,
+                score: candidate.score,
+                source_scores: candidate.source_scores,
+            }).collect(),
+        };
+        
+        let served = ServedRecommendations {
+            request_id: impression.request_id.clone(),
+            variant: impression.variant.clone(),
+            session_id: user.as_ref()
+                .and_then(|user| self.sessions.session_for(user, session_id, now))
+                .map(|session| session.id),
+            videos,
+        };
+        self.exploration.record_impression(&impression);
+        self.contextual.record_impression(&impression);
+        if let Err(e) = self.impressions.log_impression(impression) {
+            eprintln!("Failed to log impression: {}", e);
+        }
+        Ok(served)
+    }
+    
+    // Serving for the simulator: ranked at the simulated time and kept out of
+    // the impression log and the bandits, so simulated sessions never train or
+    // skew what real users are shown.
+    fn simulated_recommendations(
+        &self,
+        user_id: &str,
+        count: usize,
+        context: &RequestContext,
+        now: DateTime<Utc>,
+    ) -> io::Result<Vec<Video>> {
+        let pool = self.contextual.pool_size(count, Some(context));
+        let candidates = self.recommend_candidates(user_id, pool, self.config.rng, None, now)?;
+        let candidates = self.contextual.rerank(user_id, context, candidates, count, &*self.storage)?
+            .into_iter()
+            .map(|(candidate, _)| candidate)
+            .collect();
+        let candidates = self.explore(user_id, count, candidates, self.config.rng, now)?;
+        Ok(self.with_videos(candidates)?.into_iter().map(|(_, video)| video).collect())
+    }
+    
+    // Simulated users never join experiments, so their traffic cannot reach
+    // experiment results.
+    fn assignment(&self, user_id: &str) -> Option<Assignment> {
+        if simulation::is_simulated(user_id) {
+            return None;
+        }
+        self.experiments.assign(user_id)
+    }
+    
+    // Pairs each candidate with its video, dropping those not in the catalog.
+    fn with_videos(&self, candidates: Vec<Candidate>) -> io::Result<Vec<(Candidate, Video)>> {
+        candidates.into_iter()
+            .filter_map(|candidate| {
+                self.storage.get_video(&candidate.video_id).transpose().map(|video| video.map(|video| (candidate, video)))
+            })
+            .collect()
+    }
+    
+    // Exploration picks are added at serve time so cached and interleaved lists
+    // get them too. They replace the tail of the list and are spread evenly
+    // through it rather than stacked at the bottom.
+    fn explore(&self, user_id: &str, count: usize, mut candidates: Vec<Candidate>, rng_policy: RngPolicy, now: DateTime<Utc>) -> io::Result<Vec<Candidate>> {
+        let slots = self.exploration.allowance(user_id, now).min(count);
+        if slots == 0 {
+            return Ok(candidates);
+        }
+        
+        let listed: HashSet<String> = candidates.iter().map(|candidate| candidate.video_id.clone()).collect();
+        let mut picks = self.exploration.candidates(
+            user_id, slots, &listed, &*self.storage, &mut rng_policy.jitter("exploration", user_id, now), now)?;
+        if let Some(user) = self.storage.get_user(user_id)? {
+            let mut allowed = Vec::with_capacity(picks.len());
+            for (video_id, score) in picks {
+                if self.storage.get_video(&video_id)?.is_none_or(|video| !user.negative_feedback.blocks(&video)) {
+                    allowed.push((video_id, score));
+                }
+            }
+            picks = allowed;
+        }
+        if picks.is_empty() {
+            return Ok(candidates);
+        }
+        self.exploration.consume(user_id, picks.len(), now);
+        
+        let spacing = picks.len() + 1;
+        for (index, (video_id, score)) in picks.into_iter().enumerate() {
+            let position = ((index + 1) * count / spacing).min(candidates.len());
+            candidates.insert(position, Candidate {
+                video_id,
+                score,
+                source_scores: HashMap::from([(Source::Exploration, score)]),
+            });
+        }
+        candidates.truncate(count);
+        Ok(candidates)
+    }
+    
+    fn active_ranker(&self) -> Option<Arc<RankerModel>> {
+        self.ranker.read().unwrap().clone()
+    }
+    
+    // Trains on every impression logged so far, saves the result as a new
+    // version and serves with it straight away.
+    fn train_ranker(&self, options: &TrainingOptions) -> Result<Arc<RankerModel>, TrainError> {
+        let models = self.models.as_ref().ok_or(TrainError::Unavailable)?;
+        let mut model = ranker::train(&self.impressions.read_log()?, options)?;
+        models.save(&mut model)?;
+        
+        let model = Arc::new(model);
+        *self.ranker.write().unwrap() = Some(model.clone());
+        self.recommendation_cache.invalidate_all();
+        Ok(model)
+    }
+    
+    // Swaps the serving model without a restart; `None` goes back to the
+    // blend.
+    fn activate_ranker(&self, version: Option<u32>) -> io::Result<()> {
+        let model = match (version, &self.models) {
+            (Some(version), Some(models)) => Some(Arc::new(models.load(version)?)),
+            (Some(_), None) => return Err(io::Error::new(io::ErrorKind::NotFound, "no model directory")),
+            (None, _) => None,
+        };
+        *self.ranker.write().unwrap() = model;
+        self.recommendation_cache.invalidate_all();
+        Ok(())
+    }
+    
+    fn ranker_status(&self) -> io::Result<serde_json::Value> {
+        let versions = match &self.models {
+            Some(models) => models.versions()?,
+            None => Vec::new(),
+        };
+        Ok(serde_json::json!({
+            "active": self.active_ranker().map(|model| model.summary()),
+            "versions": versions,
+        }))
+    }
+    
+    // Cached lists were ranked under the old assignments, so any change to the
+    // running experiments drops them.
+    fn register_experiment(&self, definition: ExperimentDefinition) -> Result<(), RegisterError> {
+        self.experiments.register(definition)?;
+        self.recommendation_cache.invalidate_all();
+        Ok(())
+    }
+    
+    fn stop_experiment(&self, experiment_id: &str) -> bool {
+        let stopped = self.experiments.stop(experiment_id);
+        if stopped {
+            self.recommendation_cache.invalidate_all();
         }
+        stopped
+    }
+    
+    fn recommend_videos(&self, user_id: &str, count: usize) -> io::Result<Vec<Video>> {
+        let candidates = self.recommend_candidates(user_id, count, self.config.rng, None, Utc::now())?;
+        Ok(self.with_videos(candidates)?.into_iter().map(|(_, video)| video).collect())
+    }
+    
+    fn recommend_candidates(
+        &self,
+        user_id: &str,
+        count: usize,
+        rng_policy: RngPolicy,
+        session_id: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> io::Result<Vec<Candidate>> {
+        let Some(assignment) = self.assignment(user_id) else {
+            return self.rank_candidates(user_id, count, rng_policy, session_id, &VariantConfig::default(), now);
+        };
         
-        for (video_id, score) in content_based_recs {
-            *scored_videos.entry(video_id).or_default() += score * cb_weight;
+        match assignment.ranking() {
+            Ranking::Single(variant) => self.rank_candidates(user_id, count, rng_policy, session_id, variant, now),
+            Ranking::Interleaved(a, b) => Ok(self.interleave_candidates(user_id, count, rng_policy, session_id, (a, b), now)?.into_iter()
+                .map(|(candidate, _)| candidate)
+                .collect()),
         }
+    }
+    
+    // Each slot keeps the scores of the ranker that picked it.
+    fn interleave_candidates(
+        &self,
+        user_id: &str,
+        count: usize,
+        rng_policy: RngPolicy,
+        session_id: Option<&str>,
+        (a, b): (&VariantConfig, &VariantConfig),
+        now: DateTime<Utc>,
+    ) -> io::Result<Vec<(Candidate, Team)>> {
+        let ranked_a = self.rank_candidates(user_id, count, rng_policy, session_id, a, now)?;
+        let ranked_b = self.rank_candidates(user_id, count, rng_policy, session_id, b, now)?;
+        let ids = |ranked: &[Candidate]| -> Vec<String> {
+            ranked.iter().map(|candidate| candidate.video_id.clone()).collect()
+        };
+        let mut jitter = rng_policy.jitter("interleaving", user_id, now);
+        let slots = interleaving::team_draft(&ids(&ranked_a), &ids(&ranked_b), count, &mut jitter);
+        
+        let mut pool_a: HashMap<String, Candidate> = ranked_a.into_iter().map(|c| (c.video_id.clone(), c)).collect();
+        let mut pool_b: HashMap<String, Candidate> = ranked_b.into_iter().map(|c| (c.video_id.clone(), c)).collect();
+        Ok(slots.into_iter().filter_map(|(video_id, team)| {
+            let pool = match team {
+                Team::A => &mut pool_a,
+                Team::B => &mut pool_b,
+            };
+            pool.remove(&video_id).map(|candidate| (candidate, team))
+        }).collect())
+    }
+    
+    fn rank_candidates(
+        &self,
+        user_id: &str,
+        count: usize,
+        rng_policy: RngPolicy,
+        session_id: Option<&str>,
+        variant: &VariantConfig,
+        now: DateTime<Utc>,
+    ) -> io::Result<Vec<Candidate>> {
+        let blend = variant.blend.as_ref().unwrap_or(&self.config.blend);
+        let candidates = count * blend.candidate_multiplier;
         
-        for (video_id, score) in popularity_recs {
-            *scored_videos.entry(video_id).or_default() += score * pop_weight;
+        let mut sources = Vec::new();
+        if variant.uses(Source::Collaborative) {
+            sources.push((Source::Collaborative, blend.collaborative, self.collaborative_filtering_recommendations(
+                user_id, candidates, &mut rng_policy.jitter("collaborative", user_id, now), now)?));
+        }
+        if variant.uses(Source::ContentBased) {
+            sources.push((Source::ContentBased, blend.content_based, self.content_based_recommendations(
+                user_id, candidates, &mut rng_policy.jitter("content_based", user_id, now), now)?));
+        }
+        if variant.uses(Source::Popularity) {
+            sources.push((Source::Popularity, blend.popularity, self.popularity_based_recommendations(
+                count, &mut rng_policy.jitter("popularity", user_id, now), now)?));
+        }
+        if variant.uses(Source::Temporal) {
+            sources.push((Source::Temporal, blend.temporal, self.temporal_recommendations(user_id, count, now)?));
+        }
+        if variant.uses(Source::Engagement) {
+            sources.push((Source::Engagement, blend.engagement, self.engagement_based_recommendations(user_id, count)?));
+        }
+        if variant.uses(Source::Session) {
+            sources.push((Source::Session, blend.session, self.session_recommendations(user_id, session_id, candidates, now)?));
+        }
+        if variant.uses(Source::Sequence) {
+            sources.push((Source::Sequence, blend.sequence, self.sequence_recommendations(user_id, candidates)?));
         }
         
-        for (video_id, score) in temporal_recs {
-            *scored_videos.entry(video_id).or_default() += score * temp_weight;
+        let mut scored_videos: HashMap<String, Candidate> = HashMap::new();
+        
+        for (source, weight, recs) in sources {
+            for (rank, (video_id, score)) in recs.into_iter().enumerate() {
+                let contribution = match variant.blending {
+                    BlendingMode::Weighted => score * weight,
+                    BlendingMode::ReciprocalRank => weight / (60.0 + rank as f64 + 1.0),
+                };
+                let candidate = scored_videos.entry(video_id.clone()).or_insert_with(|| Candidate {
+                    video_id,
+                    score: 0.0,
+                    source_scores: HashMap::new(),
+                });
+                candidate.score += contribution;
+                candidate.source_scores.insert(source, score);
+            }
         }
         
-        for (video_id, score) in engagement_recs {
-            *scored_videos.entry(video_id).or_default() += score * eng_weight;
+        let mut scored_list: Vec<Candidate> = scored_videos.into_values().collect();
+        
+        // With a trained ranker the sources only nominate candidates, and the
+        // model's watch probability replaces the blended score.
+        if variant.ranker != Some(false)
+            && let Some(ranker) = self.active_ranker()
+        {
+            let user = self.current_user(user_id, now)?;
+            for candidate in &mut scored_list {
+                candidate.score = match self.storage.get_video(&candidate.video_id)? {
+                    Some(video) => ranker.predict(&ranker::features(candidate, &video, user.as_ref(), now)),
+                    None => 0.0,
+                };
+            }
         }
+        if let Some(user) = self.storage.get_user(user_id)? {
+            user.negative_feedback.apply(&mut scored_list, &*self.storage)?;
+        }
+        scored_list.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.video_id.cmp(&b.video_id)));
+        scored_list.truncate(count);
+        Ok(scored_list)
+    }
+    
+    fn source_recommendations(&self, source: Source, user_id: &str, count: usize) -> io::Result<Vec<String>> {
+        let now = Utc::now();
+        let rng = self.config.rng;
         
-        let mut scored_list: Vec<(String, f64)> = scored_videos.into_iter().collect();
-        scored_list.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let scored = match source {
+            Source::Blend => {
+                return Ok(self.recommend_videos(user_id, count)?.into_iter().map(|video| video.id).collect());
+            }
+            Source::Collaborative => self.collaborative_filtering_recommendations(
+                user_id, count, &mut rng.jitter("collaborative", user_id, now), now),
+            Source::ContentBased => self.content_based_recommendations(
+                user_id, count, &mut rng.jitter("content_based", user_id, now), now),
+            Source::Popularity => self.popularity_based_recommendations(
+                count, &mut rng.jitter("popularity", user_id, now), now),
+            Source::Temporal => self.temporal_recommendations(user_id, count, now),
+            Source::Engagement => self.engagement_based_recommendations(user_id, count),
+            Source::Session => self.session_recommendations(user_id, None, count, now),
+            Source::Sequence => self.sequence_recommendations(user_id, count),
+            Source::Exploration => self.exploration.candidates(
+                user_id, count, &HashSet::new(), &*self.storage, &mut rng.jitter("exploration", user_id, now), now),
+        }?;
+        
+        Ok(scored.into_iter().take(count).map(|(video_id, _)| video_id).collect())
+    }
+    
+    fn session_recommendations(&self, user_id: &str, session_id: Option<&str>, count: usize, now: DateTime<Utc>) -> io::Result<Vec<(String, f64)>> {
+        let Some(user) = self.storage.get_user(user_id)? else {
+            return Ok(Vec::new());
+        };
+        Ok(match self.sessions.session_for(&user, session_id, now) {
+            Some(session) => self.sessions.recommend(&session, count),
+            None => Vec::new(),
+        })
+    }
+    
+    fn sequence_recommendations(&self, user_id: &str, count: usize) -> io::Result<Vec<(String, f64)>> {
+        let Some(user) = self.storage.get_user(user_id)? else {
+            return Ok(Vec::new());
+        };
+        let watched: HashSet<String> = user.watch_history.iter().map(|watch| watch.video_id.clone()).collect();
+        let context: Vec<&str> = user.watch_history.iter().rev().take(2).rev().map(|watch| watch.video_id.as_str()).collect();
+        Ok(self.sequence.predict(&context, count, &watched))
+    }
+    
+    // What to play after `video_id`. When the user is known, the video they
+    // watched just before their latest watch of it sharpens the prediction
+    // and anything they have already seen is skipped.
+    fn up_next(&self, video_id: &str, user_id: Option<&str>, count: usize) -> io::Result<Option<Vec<Video>>> {
+        if self.storage.get_video(video_id)?.is_none() {
+            return Ok(None);
+        }
         
-        let videos_lock = self.videos.lock().unwrap();
-        for (video_id, _) in scored_list.iter().take(count) {
-            if let Some(video) = videos_lock.get(video_id) {
-                recommendations.push(video.clone());
+        let user = match user_id {
+            Some(user_id) => self.storage.get_user(user_id)?,
+            None => None,
+        };
+        let mut context = vec![video_id];
+        let mut watched = HashSet::new();
+        if let Some(user) = &user {
+            if let Some(index) = user.watch_history.iter().rposition(|watch| watch.video_id == video_id)
+                && index > 0
+            {
+                context.insert(0, user.watch_history[index - 1].video_id.as_str());
             }
+            watched = user.watch_history.iter().map(|watch| watch.video_id.clone()).collect();
         }
         
-        recommendations
+        let mut videos = Vec::new();
+        for (video_id, _) in self.sequence.predict(&context, count, &watched) {
+            if let Some(video) = self.storage.get_video(&video_id)?
+                && user.as_ref().is_none_or(|user| !user.negative_feedback.blocks(&video))
+            {
+                videos.push(video);
+            }
+        }
+        Ok(Some(videos))
     }
     
-    fn collaborative_filtering_recommendations(&self, user_id: &str, count: usize) -> Vec<(String, f64)> {
-        collaborative_filtering_recommendations(user_id, count, &self.user_video_matrix, &self.users)
+    fn collaborative_filtering_recommendations(
+        &self,
+        user_id: &str,
+        count: usize,
+        jitter: &mut Jitter,
+        now: DateTime<Utc>,
+    ) -> io::Result<Vec<(String, f64)>> {
+        let mut ratings = self.config.decay.ratings(self.storage.user_ratings(user_id)?, now);
+        if let Some(user) = self.storage.get_user(user_id)? {
+            user.negative_feedback.add_negatives(&mut ratings, self.config.implicit.negative(), &*self.storage)?;
+        }
+        collaborative_filtering_recommendations(user_id, &ratings, count, &*self.storage, &self.config.decay, now, jitter)
     }
     
-    fn content_based_recommendations(&self, user_id: &str, count: usize) -> Vec<(String, f64)> {
-        content_based_recommendations(user_id, count, &self.users, &self.videos)
+    fn content_based_recommendations(
+        &self,
+        user_id: &str,
+        count: usize,
+        jitter: &mut Jitter,
+        now: DateTime<Utc>,
+    ) -> io::Result<Vec<(String, f64)>> {
+        let user = self.current_user(user_id, now)?;
+        content_based_recommendations(user.as_ref(), count, &*self.storage, jitter, now)
     }
     
-    fn popularity_based_recommendations(&self, count: usize) -> Vec<(String, f64)> {
-        popularity_based_recommendations(count, &self.videos)
+    fn popularity_based_recommendations(
+        &self,
+        count: usize,
+        jitter: &mut Jitter,
+        now: DateTime<Utc>,
+    ) -> io::Result<Vec<(String, f64)>> {
+        popularity_based_recommendations(count, &*self.storage, jitter, now)
     }
     
-    fn temporal_recommendations(&self, user_id: &str, count: usize) -> Vec<(String, f64)> {
-        get_temporal_recommendations(user_id, count, &self.users, &self.videos)
+    fn temporal_recommendations(&self, user_id: &str, count: usize, now: DateTime<Utc>) -> io::Result<Vec<(String, f64)>> {
+        get_temporal_recommendations(user_id, count, &*self.storage, now)
     }
     
-    fn engagement_based_recommendations(&self, user_id: &str, count: usize) -> Vec<(String, f64)> {
-        get_engagement_recommendations(user_id, count, &self.users, &self.videos)
+    fn engagement_based_recommendations(&self, user_id: &str, count: usize) -> io::Result<Vec<(String, f64)>> {
+        get_engagement_recommendations(user_id, count, &*self.storage)
     }
     
-    fn process_like(&mut self, user_id: &str, video_id: &str, is_like: bool) {
-        if let Ok(mut videos) = self.videos.lock() {
-            if let Some(video) = videos.get_mut(video_id) {
-                if is_like {
-                    video.metrics.likes += 1;
-                } else {
-                    video.metrics.dislikes += 1;
-                }
+    fn process_like(&self, user_id: &str, video_id: &str, is_like: bool, timestamp: DateTime<Utc>) -> io::Result<()> {
+        self.storage.update_video(video_id, &mut |video| {
+            if is_like {
+                video.metrics.likes += 1;
+            } else {
+                video.metrics.dislikes += 1;
             }
-        }
+        })?;
         
-        self.update_user_preferences(user_id, video_id, if is_like { 1.0 } else { -0.5 });
+        self.storage.update_user(user_id, &mut |user| {
+            let first_for_view = user.watch_history.iter().rev()
+                .find(|watch| watch.video_id == video_id)
+                .is_some_and(|watch| !watch.interactions.iter().any(|interaction| matches!(interaction, Interaction::Like)));
+            patterns::record_like(&mut user.interaction_patterns, is_like, first_for_view);
+            attach_interaction(user, video_id, if is_like { Interaction::Like } else { Interaction::Dislike });
+        })?;
+        
+        self.update_user_preferences(user_id, video_id, if is_like { 1.0 } else { -0.5 }, timestamp)?;
+        self.add_feedback(user_id, video_id, self.config.implicit.like(is_like), timestamp)
     }
     
-    fn process_comment(&mut self, user_id: &str, video_id: &str, comment_text: &str) -> String {
-        let comment_id = format!("c-{}-{}", video_id, chrono::Utc::now().timestamp());
-        
+    fn process_comment(
+        &self,
+        comment_id: &str,
+        user_id: &str,
+        video_id: &str,
+        comment_text: &str,
+        timestamp: DateTime<Utc>,
+    ) -> io::Result<()> {
         let sentiment_score = self.analyze_sentiment(comment_text);
         
         let comment = Comment {
-            id: comment_id.clone(),
+            id: comment_id.to_string(),
             video_id: video_id.to_string(),
             user_id: user_id.to_string(),
             text: comment_text.to_string(),
-            timestamp: Utc::now(),
+            timestamp,
             sentiment_score,
             likes: 0,
             replies: Vec::new(),
         };
         
-        if let Ok(mut comments) = self.comments.lock() {
-            comments.insert(comment_id.clone(), comment);
-        }
+        self.storage.put_comment(comment)?;
         
-        if let Ok(mut videos) = self.videos.lock() {
-            if let Some(video) = videos.get_mut(video_id) {
-                video.metrics.comment_count += 1;
-            }
-        }
+        self.storage.update_video(video_id, &mut |video| {
+            video.metrics.comment_count += 1;
+        })?;
         
-        self.update_user_preferences(user_id, video_id, 0.3);
+        self.storage.update_user(user_id, &mut |user| {
+            patterns::record_comment(&mut user.interaction_patterns, comment_text);
+            attach_interaction(user, video_id, Interaction::Comment(comment_text.to_string()));
+        })?;
         
-        comment_id
+        self.update_user_preferences(user_id, video_id, 0.3, timestamp)?;
+        self.add_feedback(user_id, video_id, self.config.implicit.comment(), timestamp)
     }
     
-    fn process_watch(&mut self, user_id: &str, video_id: &str, watch_duration: Duration) {
-        let video_duration = {
-            let videos = self.videos.lock().unwrap();
-            videos.get(video_id).map_or(Duration::from_secs(0), |v| v.duration)
+    fn process_watch(
+        &self,
+        user_id: &str,
+        video_id: &str,
+        watch_duration: Duration,
+        interactions: &[Interaction],
+        timestamp: DateTime<Utc>,
+    ) -> io::Result<()> {
+        // `record` rejects watches of unknown videos; ones logged before it
+        // did are dropped on replay.
+        let Some(video) = self.storage.get_video(video_id)? else {
+            return Ok(());
+        };
+        let video_duration = video.duration;
+        let watch_percentage = if video_duration.is_zero() {
+            0.0
+        } else {
+            watch_duration.as_secs_f64() / video_duration.as_secs_f64()
         };
         
         let watch_event = WatchEvent {
             video_id: video_id.to_string(),
-            timestamp: Utc::now(),
+            timestamp,
             watch_duration,
             video_duration,
-            interactions: Vec::new(),
+            interactions: interactions.to_vec(),
         };
         
-        if let Ok(mut users) = self.users.lock() {
-            if let Some(user) = users.get_mut(user_id) {
-                user.watch_history.push(watch_event);
+        let mut previous = Vec::new();
+        let mut earlier_watches = 0;
+        self.storage.update_user(user_id, &mut |user| {
+            previous = user.watch_history[user.watch_history.len().saturating_sub(2)..].to_vec();
+            earlier_watches = user.watch_history.iter().filter(|watch| watch.video_id == video_id).count();
+            user.watch_history.push(watch_event.clone());
+            learn_watch_time(user, &video, timestamp);
+            if video_duration > Duration::ZERO {
+                patterns::record_watch(&mut user.interaction_patterns, watch_percentage);
             }
+        })?;
+        if let Some(last) = previous.last() {
+            self.sessions.record_watch(last, &watch_event);
         }
+        self.sequence.record_watch(&previous, &watch_event);
         
-        if let Ok(mut videos) = self.videos.lock() {
-            if let Some(video) = videos.get_mut(video_id) {
-                video.metrics.views += 1;
-                
-                let alpha = 0.1;
-                let old_avg = video.metrics.avg_watch_time.as_secs_f64();
-                let new_avg = old_avg * (1.0 - alpha) + watch_duration.as_secs_f64() * alpha;
-                video.metrics.avg_watch_time = Duration::from_secs_f64(new_avg);
-                
-                let watch_percentage = watch_duration.as_secs_f64() / video_duration.as_secs_f64();
-                video.metrics.avg_watch_percentage = 
-                    video.metrics.avg_watch_percentage * (1.0 - alpha) + watch_percentage * alpha;
-                
-                if watch_percentage > 0.9 {
-                    let old_completion = video.metrics.completion_rate;
-                    video.metrics.completion_rate = old_completion * (1.0 - alpha) + alpha;
-                }
-            }
-        }
+        self.storage.update_video(video_id, &mut |video| {
+            aggregates::record_watch(&mut video.metrics, watch_duration, video_duration, self.config.aggregation);
+            retention::record_watch(&mut video.metrics, &watch_event, earlier_watches, &self.config.retention);
+        })?;
         
-        let watch_percentage = watch_duration.as_secs_f64() / video_duration.as_secs_f64();
-        let preference_update = if watch_percentage > 0.7 { 0.5 } else { 0.2 * watch_percentage };
-        self.update_user_preferences(user_id, video_id, preference_update);
+        let preference_update = if watch_percentage > 0.7 { 0.5 } else { 0.2 * watch_percentage }
+            + telemetry::interest_signal(interactions, video_duration);
+        self.update_user_preferences(user_id, video_id, preference_update, timestamp)?;
+        let feedback = self.config.implicit.watch(
+            watch_percentage, video.metrics.avg_watch_percentage, interactions, video_duration);
+        self.add_feedback(user_id, video_id, feedback, timestamp)
     }
     
-    fn process_share(&mut self, user_id: &str, video_id: &str) {
-        if let Ok(mut videos) = self.videos.lock() {
-            if let Some(video) = videos.get_mut(video_id) {
-                video.metrics.share_count += 1;
+    fn process_share(&self, user_id: &str, video_id: &str, timestamp: DateTime<Utc>) -> io::Result<()> {
+        self.storage.update_video(video_id, &mut |video| {
+            video.metrics.share_count += 1;
+        })?;
+        
+        self.storage.update_user(user_id, &mut |user| {
+            patterns::record_share(&mut user.interaction_patterns);
+            attach_interaction(user, video_id, Interaction::Share);
+        })?;
+        
+        self.update_user_preferences(user_id, video_id, 0.7, timestamp)?;
+        self.add_feedback(user_id, video_id, self.config.implicit.share(), timestamp)
+    }
+    
+    // Histograms are kept in local time, so they are relearned from the whole
+    // history whenever the timezone changes.
+    fn process_set_timezone(&self, user_id: &str, timezone: Tz) -> io::Result<()> {
+        self.storage.update_user(user_id, &mut |user| {
+            user.timezone = timezone;
+        })?;
+        self.rebuild_temporal_profile(user_id)
+    }
+    
+    fn rebuild_temporal_profile(&self, user_id: &str) -> io::Result<()> {
+        let Some(user) = self.storage.get_user(user_id)? else {
+            return Ok(());
+        };
+        let mut videos: HashMap<String, Video> = HashMap::new();
+        for watch in &user.watch_history {
+            if let Some(video) = self.storage.get_video(&watch.video_id)? {
+                videos.insert(video.id.clone(), video);
             }
         }
-        
-        self.update_user_preferences(user_id, video_id, 0.7);
+        self.storage.update_user(user_id, &mut |user| {
+            rebuild_temporal_profile(user, &videos);
+        })?;
+        Ok(())
     }
     
-    fn process_subscribe(&mut self, user_id: &str, channel_id: &str) {
-        if let Ok(mut users) = self.users.lock() {
-            if let Some(user) = users.get_mut(user_id) {
-                user.subscriptions.insert(channel_id.to_string());
+    // Users stored before time-of-day histograms were learned have a watch
+    // history but no profile.
+    fn backfill_temporal_profiles(&self) -> io::Result<()> {
+        let mut user_ids = Vec::new();
+        self.storage.for_each_user(&mut |user| {
+            if user.interaction_patterns.temporal_profile.is_empty() && !user.watch_history.is_empty() {
+                user_ids.push(user.id.clone());
             }
+        })?;
+        for user_id in &user_ids {
+            self.rebuild_temporal_profile(user_id)?;
         }
+        Ok(())
+    }
+    
+    // Counts kept at another resolution, or not kept yet, are recounted from
+    // the watch histories.
+    fn backfill_retention(&self) -> io::Result<()> {
+        let mut stale = false;
+        self.storage.for_each_video(&mut |video| {
+            stale |= retention::needs_rebuild(&video.metrics, &self.config.retention);
+        })?;
+        if stale {
+            retention::rebuild(&*self.storage, &self.config.retention)?;
+        }
+        Ok(())
+    }
+    
+    fn recompute_patterns(&self, user_id: &str) -> io::Result<()> {
+        let mut comments = Vec::new();
+        self.storage.for_each_comment(&mut |comment| {
+            if comment.user_id == user_id {
+                comments.push(comment.clone());
+            }
+        })?;
+        comments.sort_by_key(|comment| comment.timestamp);
+        
+        self.storage.update_user(user_id, &mut |user| {
+            patterns::recompute(user, &comments);
+        })?;
+        self.rebuild_temporal_profile(user_id)
+    }
+    
+    fn process_subscribe(&self, user_id: &str, channel_id: &str) -> io::Result<()> {
+        self.storage.update_user(user_id, &mut |user| {
+            user.subscriptions.insert(channel_id.to_string());
+        })?;
+        Ok(())
     }
     
     fn analyze_sentiment(&self, text: &str) -> f32 {
@@ -427,63 +1336,74 @@ impl RecommendationEngine {
         (pos_count as f32 - neg_count as f32) / (pos_count + neg_count) as f32
     }
     
-    fn update_user_preferences(&mut self, user_id: &str, video_id: &str, score_adjustment: f64) {
-        let video_categories = {
-            let videos = self.videos.lock().unwrap();
-            videos.get(video_id).map_or(Vec::new(), |v| v.categories.clone())
-        };
-        
-        if let Ok(mut users) = self.users.lock() {
-            if let Some(user) = users.get_mut(user_id) {
-                for category in video_categories {
-                    let entry = user.content_preferences.entry(category).or_insert(0.0);
-                    *entry = (*entry * 0.95) + score_adjustment * 0.05;
-                }
-            }
-        }
+    fn update_user_preferences(&self, user_id: &str, video_id: &str, score_adjustment: f64, timestamp: DateTime<Utc>) -> io::Result<()> {
+        let video_categories = self.storage.get_video(video_id)?
+            .map_or(Vec::new(), |v| v.categories);
         
-        if let Ok(mut matrix) = self.user_video_matrix.lock() {
-            let user_ratings = matrix.entry(user_id.to_string()).or_insert_with(HashMap::new);
-            let current_rating = user_ratings.entry(video_id.to_string()).or_insert(0.0);
-            *current_rating += score_adjustment;
-        }
+        self.storage.update_user(user_id, &mut |user| {
+            self.config.decay.update_preferences(user, &video_categories, score_adjustment, timestamp);
+        })?;
+        Ok(())
     }
     
-    fn get_stats(&self) -> serde_json::Value {
-        let user_count = self.users.lock().unwrap().len();
-        let video_count = self.videos.lock().unwrap().len();
+    fn add_feedback(&self, user_id: &str, video_id: &str, feedback: Feedback, timestamp: DateTime<Utc>) -> io::Result<()> {
+        let rating = self.config.decay.add_feedback(self.storage.get_rating(user_id, video_id)?, feedback, timestamp);
+        self.storage.put_rating(user_id, video_id, rating)
+    }
+    
+    // The user as the ranking sources see them at `now`: preferences decayed
+    // to that time, with the short-term profile added on top.
+    fn current_user(&self, user_id: &str, now: DateTime<Utc>) -> io::Result<Option<User>> {
+        let Some(mut user) = self.storage.get_user(user_id)? else {
+            return Ok(None);
+        };
+        user.content_preferences = self.config.decay.interests(&user, now);
+        Ok(Some(user))
+    }
+    
+    fn get_stats(&self) -> io::Result<serde_json::Value> {
+        let user_count = self.storage.user_count()?;
+        let video_count = self.storage.video_count()?;
         
         let mut total_views = 0;
         let mut total_likes = 0;
         let mut total_comments = 0;
         
-        for video in self.videos.lock().unwrap().values() {
+        self.storage.for_each_video(&mut |video| {
             total_views += video.metrics.views;
             total_likes += video.metrics.likes;
             total_comments += video.metrics.comment_count;
-        }
+        })?;
+        
+        let evaluation = self.latest_evaluation.read().unwrap().clone();
         
-        serde_json::json!({
+        Ok(serde_json::json!({
             "userCount": user_count,
             "videoCount": video_count,
             "interactionsToday": total_views + total_likes + total_comments,
-            "recommendationQuality": 87.5,
-            "users": self.users.lock().unwrap().keys().collect::<Vec<_>>()
-        })
+            "recommendationQuality": evaluation.as_ref().map(|report| report.ndcg * 100.0),
+            "evaluation": evaluation,
+            "recommendationCache": self.recommendation_cache.stats(),
+            "exploration": self.exploration.stats(),
+            "contextual": self.contextual.stats(),
+            "sessions": self.sessions.stats(),
+            "sequence": self.sequence.stats(),
+            "users": self.storage.user_ids()?
+        }))
     }
     
-    fn get_chart_data(&self) -> serde_json::Value {
+    fn get_chart_data(&self) -> io::Result<serde_json::Value> {
         let mut likes = 0;
         let mut dislikes = 0;
         let mut comments = 0;
         let mut shares = 0;
         
-        for video in self.videos.lock().unwrap().values() {
+        self.storage.for_each_video(&mut |video| {
             likes += video.metrics.likes;
             dislikes += video.metrics.dislikes;
             comments += video.metrics.comment_count;
             shares += video.metrics.share_count;
-        }
+        })?;
         
         let watch_time_dist = vec![25, 35, 25, 15];
         
@@ -494,17 +1414,17 @@ impl RecommendationEngine {
         ];
         
         let mut categories = HashMap::new();
-        for video in self.videos.lock().unwrap().values() {
+        self.storage.for_each_video(&mut |video| {
             for cat in &video.categories {
                 *categories.entry(cat.clone()).or_insert(0) += 1;
             }
-        }
+        })?;
         
         let mut cat_vec: Vec<(String, i32)> = categories.into_iter().collect();
-        cat_vec.sort_by(|a, b| b.1.cmp(&a.1));
+        cat_vec.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
         let cat_vec = cat_vec.into_iter().take(5).collect::<Vec<_>>();
         
-        serde_json::json!({
+        Ok(serde_json::json!({
             "interactions": {
                 "likes": likes,
                 "dislikes": dislikes,
@@ -519,7 +1439,7 @@ impl RecommendationEngine {
                 "labels": cat_vec.iter().map(|(name, _)| name).collect::<Vec<_>>(),
                 "values": cat_vec.iter().map(|(_, count)| count).collect::<Vec<_>>()
             }
-        })
+        }))
     }
 }
 
@@ -527,6 +1447,9 @@ impl RecommendationEngine {
 struct RecommendationRequest {
     user_id: String,
     count: usize,
+    seed: Option<u64>,
+    context: Option<RequestContext>,
+    session_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -562,6 +1485,40 @@ struct SubscribeRequest {
     channel_id: String,
 }
 
+// Likes, shares and comments are kept on the user's latest watch of the
+// video, if there is one.
+fn attach_interaction(user: &mut User, video_id: &str, interaction: Interaction) {
+    if let Some(watch) = user.watch_history.iter_mut().rev().find(|watch| watch.video_id == video_id) {
+        watch.interactions.push(interaction);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TimezoneRequest {
+    timezone: Tz,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NotInterestedRequest {
+    video_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChannelFeedbackRequest {
+    channel_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CategoryFeedbackRequest {
+    category: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportQuery {
+    format: Option<String>,
+    count: Option<usize>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct SimulationRequest {
     user_count: usize,
@@ -570,133 +1527,1112 @@ struct SimulationRequest {
 }
 
 async fn get_recommendations(
-    data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+    data: web::Data<Arc<RecommendationEngine>>,
     req: web::Json<RecommendationRequest>,
 ) -> impl Responder {
-    let engine = data.lock().unwrap();
-    let recommendations = engine.recommend_videos(&req.user_id, req.count);
+    if let Some(context) = &req.context
+        && !context.is_valid()
+    {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "local_hour must be between 0 and 23"
+        }));
+    }
+    let served = match data.serve_recommendations(
+        &req.user_id, req.count, req.seed, req.context.as_ref(), req.session_id.as_deref())
+    {
+        Ok(served) => served,
+        Err(e) => return storage_error(e),
+    };
     
-    HttpResponse::Ok().json(recommendations)
+    let mut response = HttpResponse::Ok();
+    response.insert_header((REQUEST_ID_HEADER, served.request_id));
+    if let Some(variant) = served.variant {
+        response.insert_header((EXPERIMENT_HEADER, format!("{}={}", variant.experiment_id, variant.variant)));
+    }
+    if let Some(session_id) = served.session_id {
+        response.insert_header((SESSION_HEADER, session_id));
+    }
+    response.json(served.videos)
+}
+
+fn storage_error(error: io::Error) -> HttpResponse {
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "status": "error",
+        "message": format!("failed to read storage: {}", error)
+    }))
+}
+
+fn record_error(error: io::Error) -> HttpResponse {
+    if error.kind() == io::ErrorKind::NotFound {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": error.to_string()
+        }));
+    }
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "status": "error",
+        "message": format!("failed to record event: {}", error)
+    }))
 }
 
 async fn record_watch(
-    data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+    data: web::Data<Arc<RecommendationEngine>>,
     req: web::Json<WatchRequest>,
 ) -> impl Responder {
-    let mut engine = data.lock().unwrap();
-    let duration = Duration::from_secs_f64(req.watch_seconds);
+    let watch_duration = match watch_duration(req.watch_seconds) {
+        Ok(duration) => duration,
+        Err(message) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        })),
+    };
+    let event = EngineEvent::Watch {
+        user_id: req.user_id.clone(),
+        video_id: req.video_id.clone(),
+        watch_duration,
+        interactions: Vec::new(),
+        timestamp: Utc::now(),
+    };
+    
+    match data.record(event) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => record_error(e),
+    }
+}
+
+async fn record_watch_session(
+    data: web::Data<Arc<RecommendationEngine>>,
+    req: web::Json<WatchSessionRequest>,
+) -> impl Responder {
+    let video = match data.storage.get_video(&req.video_id) {
+        Ok(Some(video)) => video,
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown video: {}", req.video_id)
+        })),
+        Err(e) => return storage_error(e),
+    };
+    let interactions = match req.validate(video.duration) {
+        Ok(interactions) => interactions,
+        Err(message) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        })),
+    };
     
-    engine.process_watch(&req.user_id, &req.video_id, duration);
+    let event = EngineEvent::Watch {
+        user_id: req.user_id.clone(),
+        video_id: req.video_id.clone(),
+        watch_duration: Duration::from_secs_f64(req.watch_seconds),
+        interactions,
+        timestamp: Utc::now(),
+    };
     
-    HttpResponse::Ok().json(serde_json::json!({"status": "success"}))
+    match data.record(event) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => record_error(e),
+    }
 }
 
 async fn record_like(
-    data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+    data: web::Data<Arc<RecommendationEngine>>,
     req: web::Json<LikeRequest>,
 ) -> impl Responder {
-    let mut engine = data.lock().unwrap();
-    
-    engine.process_like(&req.user_id, &req.video_id, req.is_like);
+    let event = EngineEvent::Like {
+        user_id: req.user_id.clone(),
+        video_id: req.video_id.clone(),
+        is_like: req.is_like,
+        timestamp: Utc::now(),
+    };
     
-    HttpResponse::Ok().json(serde_json::json!({"status": "success"}))
+    match data.record(event) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => record_error(e),
+    }
 }
 
 async fn record_comment(
-    data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+    data: web::Data<Arc<RecommendationEngine>>,
     req: web::Json<CommentRequest>,
 ) -> impl Responder {
-    let mut engine = data.lock().unwrap();
+    let timestamp = Utc::now();
+    let comment_id = format!("c-{}-{}", req.video_id, timestamp.timestamp());
     
-    let comment_id = engine.process_comment(&req.user_id, &req.video_id, &req.text);
+    let event = EngineEvent::Comment {
+        comment_id: comment_id.clone(),
+        user_id: req.user_id.clone(),
+        video_id: req.video_id.clone(),
+        text: req.text.clone(),
+        timestamp,
+    };
     
-    HttpResponse::Ok().json(serde_json::json!({"status": "success", "comment_id": comment_id}))
+    match data.record(event) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success", "comment_id": comment_id})),
+        Err(e) => record_error(e),
+    }
 }
 
 async fn record_share(
-    data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+    data: web::Data<Arc<RecommendationEngine>>,
     req: web::Json<ShareRequest>,
 ) -> impl Responder {
-    let mut engine = data.lock().unwrap();
+    let event = EngineEvent::Share {
+        user_id: req.user_id.clone(),
+        video_id: req.video_id.clone(),
+        timestamp: Utc::now(),
+    };
     
-    engine.process_share(&req.user_id, &req.video_id);
-    
-    HttpResponse::Ok().json(serde_json::json!({"status": "success"}))
+    match data.record(event) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => record_error(e),
+    }
 }
 
 async fn record_subscribe(
-    data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+    data: web::Data<Arc<RecommendationEngine>>,
     req: web::Json<SubscribeRequest>,
 ) -> impl Responder {
-    let mut engine = data.lock().unwrap();
+    let event = EngineEvent::Subscribe {
+        user_id: req.user_id.clone(),
+        channel_id: req.channel_id.clone(),
+        timestamp: Utc::now(),
+    };
     
-    engine.process_subscribe(&req.user_id, &req.channel_id);
+    match data.record(event) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => record_error(e),
+    }
+}
+
+async fn set_timezone(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+    req: web::Json<TimezoneRequest>,
+) -> impl Responder {
+    match data.storage.get_user(&path) {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown user: {}", path)
+        })),
+        Err(e) => return storage_error(e),
+    }
+    let event = EngineEvent::SetTimezone {
+        user_id: path.into_inner(),
+        timezone: req.timezone,
+        timestamp: Utc::now(),
+    };
     
-    HttpResponse::Ok().json(serde_json::json!({"status": "success"}))
+    match data.record(event) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => record_error(e),
+    }
 }
 
-async fn get_stats(
-    data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+async fn get_patterns(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match data.storage.get_user(&path) {
+        Ok(Some(user)) => HttpResponse::Ok().json(user.interaction_patterns),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown user: {}", path)
+        })),
+        Err(e) => storage_error(e),
+    }
+}
+
+async fn get_interests(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let now = Utc::now();
+    match data.storage.get_user(&path) {
+        Ok(Some(user)) => HttpResponse::Ok().json(serde_json::json!({
+            "as_of": now,
+            "long_term": data.config.decay.long_term(&user, now),
+            "short_term": data.config.decay.short_term(&user, now),
+            "combined": data.config.decay.interests(&user, now),
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown user: {}", path)
+        })),
+        Err(e) => storage_error(e),
+    }
+}
+
+// Videos and channels must be in the catalog; any category can be turned down.
+fn add_negative_feedback(data: &RecommendationEngine, user_id: String, target: NegativeTarget) -> HttpResponse {
+    match data.storage.get_user(&user_id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown user: {}", user_id)
+        })),
+        Err(e) => return storage_error(e),
+    }
+    let unknown = match &target {
+        NegativeTarget::Video(video_id) => match data.storage.get_video(video_id) {
+            Ok(video) => video.is_none().then(|| format!("unknown video: {}", video_id)),
+            Err(e) => return storage_error(e),
+        },
+        NegativeTarget::Channel(channel_id) => match data.storage.channel_video_ids(channel_id) {
+            Ok(video_ids) => video_ids.is_empty().then(|| format!("unknown channel: {}", channel_id)),
+            Err(e) => return storage_error(e),
+        },
+        NegativeTarget::Category(category) => {
+            if category.is_empty() {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "status": "error",
+                    "message": "category must not be empty"
+                }));
+            }
+            None
+        }
+    };
+    if let Some(message) = unknown {
+        return HttpResponse::NotFound().json(serde_json::json!({"status": "error", "message": message}));
+    }
+    
+    match data.record(EngineEvent::NegativeFeedback { user_id, target, timestamp: Utc::now() }) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => record_error(e),
+    }
+}
+
+fn undo_negative_feedback(data: &RecommendationEngine, user_id: String, target: NegativeTarget) -> HttpResponse {
+    let user = match data.storage.get_user(&user_id) {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown user: {}", user_id)
+        })),
+        Err(e) => return storage_error(e),
+    };
+    if !user.negative_feedback.contains(&target) {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": "no such negative feedback"
+        }));
+    }
+    
+    match data.record(EngineEvent::UndoNegativeFeedback { user_id, target, timestamp: Utc::now() }) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success"})),
+        Err(e) => record_error(e),
+    }
+}
+
+async fn mark_not_interested(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+    req: web::Json<NotInterestedRequest>,
+) -> impl Responder {
+    add_negative_feedback(&data, path.into_inner(), NegativeTarget::Video(req.into_inner().video_id))
+}
+
+async fn unmark_not_interested(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (user_id, video_id) = path.into_inner();
+    undo_negative_feedback(&data, user_id, NegativeTarget::Video(video_id))
+}
+
+async fn block_channel(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+    req: web::Json<ChannelFeedbackRequest>,
+) -> impl Responder {
+    add_negative_feedback(&data, path.into_inner(), NegativeTarget::Channel(req.into_inner().channel_id))
+}
+
+async fn unblock_channel(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<(String, String)>,
 ) -> impl Responder {
-    let engine = data.lock().unwrap();
-    let stats = engine.get_stats();
+    let (user_id, channel_id) = path.into_inner();
+    undo_negative_feedback(&data, user_id, NegativeTarget::Channel(channel_id))
+}
+
+async fn less_of_category(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+    req: web::Json<CategoryFeedbackRequest>,
+) -> impl Responder {
+    add_negative_feedback(&data, path.into_inner(), NegativeTarget::Category(req.into_inner().category))
+}
+
+async fn undo_less_of_category(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (user_id, category) = path.into_inner();
+    undo_negative_feedback(&data, user_id, NegativeTarget::Category(category))
+}
+
+async fn get_negative_feedback(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match data.storage.get_user(&path) {
+        Ok(Some(user)) => HttpResponse::Ok().json(user.negative_feedback.list()),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown user: {}", path)
+        })),
+        Err(e) => storage_error(e),
+    }
+}
+
+async fn recompute_patterns(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match data.storage.get_user(&path) {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown user: {}", path)
+        })),
+        Err(e) => return storage_error(e),
+    }
+    let event = EngineEvent::RecomputePatterns {
+        user_id: path.to_string(),
+        timestamp: Utc::now(),
+    };
     
-    HttpResponse::Ok().json(stats)
+    if let Err(e) = data.record(event) {
+        return record_error(e);
+    }
+    match data.storage.get_user(&path) {
+        Ok(user) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "patterns": user.map(|user| user.interaction_patterns)
+        })),
+        Err(e) => storage_error(e),
+    }
+}
+
+async fn get_stats(
+    data: web::Data<Arc<RecommendationEngine>>,
+) -> impl Responder {
+    match data.get_stats() {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => storage_error(e),
+    }
 }
 
 async fn get_chart_data(
-    data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+    data: web::Data<Arc<RecommendationEngine>>,
+) -> impl Responder {
+    match data.get_chart_data() {
+        Ok(chart_data) => HttpResponse::Ok().json(chart_data),
+        Err(e) => storage_error(e),
+    }
+}
+
+async fn export_data(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+    query: web::Query<ExportQuery>,
 ) -> impl Responder {
-    let engine = data.lock().unwrap();
-    let chart_data = engine.get_chart_data();
+    let Some(dataset) = Dataset::parse(&path) else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown dataset: {}", path)
+        }));
+    };
+    let format = match query.format.as_deref().map(ExportFormat::parse) {
+        None => ExportFormat::Jsonl,
+        Some(Some(format)) => format,
+        Some(None) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "format must be one of jsonl, csv, columnar"
+        })),
+    };
+    let count = query.count.unwrap_or(10);
+    
+    let engine = data.get_ref().clone();
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+    
+    actix_web::rt::task::spawn_blocking(move || {
+        let storage = engine.storage.clone();
+        let mut writer = ChunkWriter::new(sender);
+        let mut recommend = |user_id: &str, count: usize| engine.recommend_videos(user_id, count);
+        
+        if let Err(e) = export::export_dataset(dataset, format, count, &*storage, &mut recommend, &mut writer) {
+            eprintln!("Export of {:?} failed: {}", dataset, e);
+            writer.fail(e);
+        }
+    });
     
-    HttpResponse::Ok().json(chart_data)
+    let body = futures_util::stream::poll_fn(move |cx| {
+        receiver.poll_recv(cx).map(|chunk| chunk.map(|chunk| chunk.map_err(actix_web::Error::from)))
+    });
+    
+    HttpResponse::Ok().content_type(format.content_type()).streaming(body)
 }
 
 async fn run_simulation(
-    _data: web::Data<Arc<Mutex<RecommendationEngine>>>,
+    data: web::Data<Arc<RecommendationEngine>>,
+    simulator: web::Data<Arc<Simulator>>,
     req: web::Json<SimulationRequest>,
 ) -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "started",
-        "message": format!("Started simulation with {} users over {} days at {} intensity", 
-                          req.user_count, req.days, req.intensity)
+    let Some(intensity) = Intensity::parse(&req.intensity) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "intensity must be one of low, medium, high"
+        }));
+    };
+    
+    let config = SimulationConfig {
+        user_count: req.user_count,
+        days: req.days,
+        intensity,
+    };
+    
+    match simulator.start(data.get_ref().clone(), config) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "started",
+            "message": format!("Started simulation with {} users over {} days at {} intensity",
+                              req.user_count, req.days, req.intensity)
+        })),
+        Err(StartError::AlreadyRunning) => HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": "a simulation is already running"
+        })),
+        Err(StartError::EmptyCatalog) => HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "there are no videos to recommend"
+        })),
+        Err(StartError::InvalidConfig(message)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        })),
+        Err(StartError::Io(e)) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("failed to start simulation: {}", e)
+        })),
+    }
+}
+
+async fn list_experiments(
+    data: web::Data<Arc<RecommendationEngine>>,
+) -> impl Responder {
+    HttpResponse::Ok().json(data.experiments.list())
+}
+
+async fn create_experiment(
+    data: web::Data<Arc<RecommendationEngine>>,
+    req: web::Json<ExperimentDefinition>,
+) -> impl Responder {
+    let experiment_id = req.id.clone();
+    
+    match data.register_experiment(req.into_inner()) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success", "id": experiment_id})),
+        Err(RegisterError::Duplicate) => HttpResponse::Conflict().json(serde_json::json!({
+            "status": "error",
+            "message": format!("experiment {} already exists", experiment_id)
+        })),
+        Err(RegisterError::Invalid(message)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": message
+        })),
+    }
+}
+
+async fn stop_experiment(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if data.stop_experiment(&path) {
+        HttpResponse::Ok().json(serde_json::json!({"status": "success"}))
+    } else {
+        experiment_not_found(&path)
+    }
+}
+
+async fn get_experiment_results(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match data.experiments.results(&path) {
+        Some(results) => HttpResponse::Ok().json(results),
+        None => experiment_not_found(&path),
+    }
+}
+
+fn experiment_not_found(experiment_id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "status": "error",
+        "message": format!("unknown experiment: {}", experiment_id)
     }))
 }
 
-async fn get_simulation_status() -> impl Responder {
-    use rand::Rng;
-    let progress = rand::rng().random_range(0..=100);
+#[derive(Debug, Deserialize)]
+struct UpNextQuery {
+    user_id: Option<String>,
+    count: Option<usize>,
+}
+
+async fn get_up_next(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+    query: web::Query<UpNextQuery>,
+) -> impl Responder {
+    match data.up_next(&path, query.user_id.as_deref(), query.count.unwrap_or(10)) {
+        Ok(Some(videos)) => HttpResponse::Ok().json(videos),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown video: {}", path)
+        })),
+        Err(e) => storage_error(e),
+    }
+}
+
+async fn get_retention(
+    data: web::Data<Arc<RecommendationEngine>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match data.storage.get_video(&path) {
+        Ok(Some(video)) => HttpResponse::Ok().json(retention::report(&video.id, &video.metrics, video.duration)),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown video: {}", path)
+        })),
+        Err(e) => storage_error(e),
+    }
+}
+
+async fn get_ranker(
+    data: web::Data<Arc<RecommendationEngine>>,
+) -> impl Responder {
+    match data.ranker_status() {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("failed to list ranker models: {}", e)
+        })),
+    }
+}
+
+async fn train_ranker(
+    data: web::Data<Arc<RecommendationEngine>>,
+    req: web::Json<TrainingOptions>,
+) -> impl Responder {
+    // Training reads the whole impression log, so it runs off the worker
+    // threads that serve requests.
+    let engine = data.get_ref().clone();
+    let options = req.into_inner();
+    let Ok(result) = web::block(move || engine.train_ranker(&options)).await else {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": "ranker training was interrupted"
+        }));
+    };
+    
+    match result {
+        Ok(model) => HttpResponse::Ok().json(serde_json::json!({"status": "success", "model": model.summary()})),
+        Err(TrainError::NotEnoughData) => HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": "not enough logged impressions with both watched and unwatched slots"
+        })),
+        Err(TrainError::Unavailable) => HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "error",
+            "message": "this engine has no model directory"
+        })),
+        Err(TrainError::Io(e)) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("failed to train ranker: {}", e)
+        })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ActivateRankerRequest {
+    version: Option<u32>,
+}
+
+async fn activate_ranker(
+    data: web::Data<Arc<RecommendationEngine>>,
+    req: web::Json<ActivateRankerRequest>,
+) -> impl Responder {
+    match data.activate_ranker(req.version) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "success", "version": req.version})),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("unknown ranker version: {}", req.version.unwrap_or_default())
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("failed to load ranker: {}", e)
+        })),
+    }
+}
+
+async fn get_impression_report(
+    data: web::Data<Arc<RecommendationEngine>>,
+) -> impl Responder {
+    HttpResponse::Ok().json(data.impressions.report())
+}
+
+async fn get_simulation_status(
+    simulator: web::Data<Arc<Simulator>>,
+) -> impl Responder {
+    HttpResponse::Ok().json(simulator.status())
+}
+
+async fn get_simulation_results(
+    simulator: web::Data<Arc<Simulator>>,
+) -> impl Responder {
+    match simulator.results() {
+        Some(results) => HttpResponse::Ok().json(results),
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": "no simulation has been run"
+        })),
+    }
+}
+
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(|value| value.as_str())
+}
+
+// A number of `unit`s, or `off` for no decay.
+fn half_life(value: &str, unit: chrono::Duration) -> Option<Option<chrono::Duration>> {
+    if value == "off" {
+        return Some(None);
+    }
+    let units: f64 = value.parse().ok().filter(|units: &f64| *units > 0.0)?;
+    Some(Some(chrono::Duration::milliseconds((units * unit.num_milliseconds() as f64) as i64)))
+}
+
+fn invalid_arg(flag: &str, value: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid value for {}: {}", flag, value))
+}
+
+fn event_log_config(args: &[String]) -> io::Result<EventLogConfig> {
+    let mut config = EventLogConfig::default();
+    
+    if let Some(dir) = arg_value(args, "--data-dir") {
+        config.dir = dir.into();
+    }
+    if let Some(value) = arg_value(args, "--fsync") {
+        config.fsync = FsyncPolicy::parse(value).ok_or_else(|| invalid_arg("--fsync", value))?;
+    }
+    if let Some(value) = arg_value(args, "--segment-bytes") {
+        config.max_segment_bytes = value.parse().map_err(|_| invalid_arg("--segment-bytes", value))?;
+    }
+    if let Some(value) = arg_value(args, "--snapshot-every") {
+        config.snapshot_every = value.parse().map_err(|_| invalid_arg("--snapshot-every", value))?;
+    }
+    
+    Ok(config)
+}
+
+fn run_export(engine: &RecommendationEngine, args: &[String]) -> io::Result<()> {
+    let dataset_arg = args.get(1).map(String::as_str).unwrap_or("");
+    let dataset = Dataset::parse(dataset_arg)
+        .ok_or_else(|| invalid_arg("export", dataset_arg))?;
+    let format = match arg_value(args, "--format") {
+        Some(value) => ExportFormat::parse(value).ok_or_else(|| invalid_arg("--format", value))?,
+        None => ExportFormat::Jsonl,
+    };
+    let count = match arg_value(args, "--count") {
+        Some(value) => value.parse().map_err(|_| invalid_arg("--count", value))?,
+        None => 10,
+    };
+    
+    let mut out: Box<dyn io::Write> = match arg_value(args, "--output") {
+        Some(path) => Box::new(io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    let mut recommend = |user_id: &str, count: usize| engine.recommend_videos(user_id, count);
+    
+    export::export_dataset(dataset, format, count, &*engine.storage, &mut recommend, &mut out)
+}
+
+fn evaluation_config(args: &[String]) -> io::Result<EvaluationConfig> {
+    let mut config = EvaluationConfig::default();
+    
+    if let Some(value) = arg_value(args, "--source") {
+        config.source = Source::parse(value).ok_or_else(|| invalid_arg("--source", value))?;
+    }
+    if let Some(value) = arg_value(args, "--k") {
+        config.k = value.parse().ok().filter(|k| *k > 0).ok_or_else(|| invalid_arg("--k", value))?;
+    }
+    if let Some(value) = arg_value(args, "--test-fraction") {
+        config.test_fraction = value.parse().ok()
+            .filter(|fraction| (0.0..1.0).contains(fraction))
+            .ok_or_else(|| invalid_arg("--test-fraction", value))?;
+    }
+    
+    Ok(config)
+}
+
+fn run_evaluate(engine: &RecommendationEngine, args: &[String]) -> io::Result<()> {
+    let config = evaluation_config(args)?;
+    let report = evaluation::evaluate(&*engine.storage, &engine.config, &config)?;
+    
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn tuning_config(args: &[String]) -> io::Result<TuningConfig> {
+    let evaluation = evaluation_config(args)?;
+    let metric = match arg_value(args, "--metric") {
+        Some(value) => Metric::parse(value).ok_or_else(|| invalid_arg("--metric", value))?,
+        None => Metric::Ndcg,
+    };
+    let threads = match arg_value(args, "--threads") {
+        Some(value) => value.parse().ok().filter(|threads| *threads > 0).ok_or_else(|| invalid_arg("--threads", value))?,
+        None => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+    };
+    let number = |flag: &str, default: usize| match arg_value(args, flag) {
+        Some(value) => value.parse().map_err(|_| invalid_arg(flag, value)),
+        None => Ok(default),
+    };
     
-    HttpResponse::Ok().json(serde_json::json!({"progress": progress}))
+    let strategy = match arg_value(args, "--strategy").unwrap_or("random") {
+        "grid" => Strategy::Grid { steps: number("--grid-steps", 3)? },
+        "random" => Strategy::Random {
+            trials: number("--trials", 50)?,
+            seed: number("--seed", 0)? as u64,
+        },
+        "coordinate" => Strategy::CoordinateAscent {
+            rounds: number("--rounds", 10)?,
+            step: 0.1,
+        },
+        other => return Err(invalid_arg("--strategy", other)),
+    };
+    
+    Ok(TuningConfig {
+        strategy,
+        metric,
+        k: evaluation.k,
+        test_fraction: evaluation.test_fraction,
+        threads,
+    })
 }
 
-async fn get_simulation_results() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({
-        "userCount": 1000,
-        "totalInteractions": 25000,
-        "metrics": {
-            "views": 15000,
-            "likes": 5000,
-            "comments": 2000,
-            "shares": 800,
-            "subscribes": 300,
-            "ctr": 0.24,
-            "avgWatchTime": 185,
-            "engagementRate": 0.18
+fn run_tune(engine: &RecommendationEngine, args: &[String]) -> io::Result<()> {
+    let config = tuning_config(args)?;
+    let trials = tuning::tune(&*engine.storage, &engine.config, &config)?;
+    let Some(best) = trials.first() else {
+        return Err(io::Error::other("no configurations were evaluated"));
+    };
+    
+    eprintln!(
+        "{:>4}  {:>8}  {:>7}  {:>7}  {:>7}  {:>7}  {:>6}  {:>7}  {:>6}  {:>6}  {:>6}  {:>6}  {:>6}  {:>4}",
+        "rank", "score", "ndcg", "map", "recall", "prec", "collab", "content", "pop", "temp", "engage", "sess", "seq", "mult"
+    );
+    let top = match arg_value(args, "--top") {
+        Some(value) => value.parse().map_err(|_| invalid_arg("--top", value))?,
+        None => 10,
+    };
+    
+    for (rank, trial) in trials.iter().enumerate().take(top) {
+        let blend = &trial.blend;
+        eprintln!(
+            "{:>4}  {:>8.4}  {:>7.4}  {:>7.4}  {:>7.4}  {:>7.4}  {:>6.3}  {:>7.3}  {:>6.3}  {:>6.3}  {:>6.3}  {:>6.3}  {:>6.3}  {:>4}",
+            rank + 1, trial.score, trial.report.ndcg, trial.report.map, trial.report.recall, trial.report.precision,
+            blend.collaborative, blend.content_based, blend.popularity, blend.temporal, blend.engagement, blend.session, blend.sequence,
+            blend.candidate_multiplier
+        );
+    }
+    eprintln!("Evaluated {} configurations on {} users", trials.len(), best.report.users_evaluated);
+    
+    let json = serde_json::to_string_pretty(&best.blend)?;
+    match arg_value(args, "--output") {
+        Some(path) => std::fs::write(path, json + "\n"),
+        None => {
+            println!("{}", json);
+            Ok(())
         }
-    }))
+    }
+}
+
+fn run_train_ranker(engine: &RecommendationEngine, args: &[String]) -> io::Result<()> {
+    let mut options = TrainingOptions::default();
+    if let Some(value) = arg_value(args, "--epochs") {
+        options.epochs = value.parse().map_err(|_| invalid_arg("--epochs", value))?;
+    }
+    if let Some(value) = arg_value(args, "--learning-rate") {
+        options.learning_rate = value.parse().map_err(|_| invalid_arg("--learning-rate", value))?;
+    }
+    if let Some(value) = arg_value(args, "--l2") {
+        options.l2 = value.parse().map_err(|_| invalid_arg("--l2", value))?;
+    }
+    if let Some(value) = arg_value(args, "--holdout-fraction") {
+        options.holdout_fraction = value.parse().map_err(|_| invalid_arg("--holdout-fraction", value))?;
+    }
+    
+    let model = match engine.train_ranker(&options) {
+        Ok(model) => model,
+        Err(TrainError::NotEnoughData) => {
+            return Err(io::Error::other("not enough logged impressions with both watched and unwatched slots"));
+        }
+        Err(TrainError::Unavailable) => return Err(io::Error::other("no model directory")),
+        Err(TrainError::Io(e)) => return Err(e),
+    };
+    eprintln!(
+        "Trained ranker v{} on {} examples ({} watched), holdout log loss {:.4}, AUC {}",
+        model.version, model.training.examples, model.training.positives, model.training.holdout_log_loss,
+        model.training.holdout_auc.map_or("n/a".to_string(), |auc| format!("{:.4}", auc))
+    );
+    println!("{}", serde_json::to_string_pretty(&model.summary())?);
+    Ok(())
+}
+
+// Re-scores the live engine on its own history so /api/stats reports measured
+// quality rather than a fixed number.
+fn spawn_evaluation_job(engine: Arc<RecommendationEngine>, config: EvaluationConfig, interval: Duration) {
+    std::thread::spawn(move || loop {
+        match evaluation::evaluate(&*engine.storage, &engine.config, &config) {
+            Ok(report) => *engine.latest_evaluation.write().unwrap() = Some(report),
+            Err(e) => eprintln!("Failed to evaluate recommendations: {}", e),
+        }
+        
+        std::thread::sleep(interval);
+    });
+}
+
+fn parse_list<T: std::str::FromStr>(flag: &str, value: &str) -> io::Result<Vec<T>> {
+    value.split(',')
+        .map(|item| item.trim().parse().map_err(|_| invalid_arg(flag, value)))
+        .collect()
+}
+
+fn run_bench(args: &[String]) -> io::Result<()> {
+    let mut config = bench::BenchConfig::default();
+    
+    if let Some(value) = arg_value(args, "--threads") {
+        config.threads = parse_list("--threads", value)?;
+    }
+    if let Some(value) = arg_value(args, "--write-ratios") {
+        config.write_ratios = parse_list("--write-ratios", value)?;
+        if config.write_ratios.iter().any(|ratio| !(0.0..=1.0).contains(ratio)) {
+            return Err(invalid_arg("--write-ratios", value));
+        }
+    }
+    if let Some(value) = arg_value(args, "--seconds") {
+        let secs = value.parse().map_err(|_| invalid_arg("--seconds", value))?;
+        config.duration = Duration::from_secs_f64(secs);
+    }
+    if let Some(value) = arg_value(args, "--users") {
+        config.users = value.parse().map_err(|_| invalid_arg("--users", value))?;
+    }
+    if let Some(value) = arg_value(args, "--videos") {
+        config.videos = value.parse().map_err(|_| invalid_arg("--videos", value))?;
+    }
+    
+    bench::run(&config)
+}
+
+fn engine_config(args: &[String]) -> io::Result<EngineConfig> {
+    let mut config = EngineConfig::default();
+    
+    if let Some(value) = arg_value(args, "--cache-ttl-secs") {
+        let secs: i64 = value.parse().map_err(|_| invalid_arg("--cache-ttl-secs", value))?;
+        config.cache.ttl = chrono::Duration::seconds(secs);
+    }
+    if let Some(value) = arg_value(args, "--precompute-count") {
+        config.cache.precompute_count = value.parse().map_err(|_| invalid_arg("--precompute-count", value))?;
+    }
+    if let Some(value) = arg_value(args, "--precompute-interval-secs") {
+        let secs = value.parse().map_err(|_| invalid_arg("--precompute-interval-secs", value))?;
+        config.cache.precompute_interval = Duration::from_secs(secs);
+    }
+    if let Some(value) = arg_value(args, "--rng") {
+        config.rng = RngPolicy::parse(value).ok_or_else(|| invalid_arg("--rng", value))?;
+    }
+    if let Some(value) = arg_value(args, "--exploration") {
+        config.exploration.policy = ExplorationPolicy::parse(value).ok_or_else(|| invalid_arg("--exploration", value))?;
+    }
+    if let Some(value) = arg_value(args, "--exploration-slots") {
+        config.exploration.slots = value.parse().map_err(|_| invalid_arg("--exploration-slots", value))?;
+    }
+    if let Some(value) = arg_value(args, "--exploration-cap") {
+        config.exploration.daily_cap = value.parse().map_err(|_| invalid_arg("--exploration-cap", value))?;
+    }
+    if let Some(value) = arg_value(args, "--exploration-max-views") {
+        config.exploration.max_views = value.parse().map_err(|_| invalid_arg("--exploration-max-views", value))?;
+    }
+    if let Some(value) = arg_value(args, "--exploration-max-age-days") {
+        let days: i64 = value.parse().map_err(|_| invalid_arg("--exploration-max-age-days", value))?;
+        config.exploration.max_age = chrono::Duration::days(days);
+    }
+    if let Some(value) = arg_value(args, "--contextual-alpha") {
+        config.contextual.alpha = value.parse().map_err(|_| invalid_arg("--contextual-alpha", value))?;
+    }
+    if let Some(value) = arg_value(args, "--contextual-pool") {
+        config.contextual.pool = value.parse().map_err(|_| invalid_arg("--contextual-pool", value))?;
+    }
+    if let Some(value) = arg_value(args, "--contextual") {
+        config.contextual.enabled = match value {
+            "on" => true,
+            "off" => false,
+            _ => return Err(invalid_arg("--contextual", value)),
+        };
+    }
+    if let Some(value) = arg_value(args, "--session-gap-mins") {
+        let minutes: i64 = value.parse().map_err(|_| invalid_arg("--session-gap-mins", value))?;
+        config.sessions.gap = chrono::Duration::minutes(minutes);
+    }
+    if let Some(value) = arg_value(args, "--preference-half-life-days") {
+        config.decay.preference_half_life = half_life(value, chrono::Duration::days(1))
+            .ok_or_else(|| invalid_arg("--preference-half-life-days", value))?;
+    }
+    if let Some(value) = arg_value(args, "--rating-half-life-days") {
+        config.decay.rating_half_life = half_life(value, chrono::Duration::days(1))
+            .ok_or_else(|| invalid_arg("--rating-half-life-days", value))?;
+    }
+    if let Some(value) = arg_value(args, "--short-term-half-life-hours") {
+        config.decay.short_term_half_life = half_life(value, chrono::Duration::hours(1))
+            .ok_or_else(|| invalid_arg("--short-term-half-life-hours", value))?;
+    }
+    if let Some(value) = arg_value(args, "--short-term-weight") {
+        config.decay.short_term_weight = value.parse().map_err(|_| invalid_arg("--short-term-weight", value))?;
+    }
+    if let Some(value) = arg_value(args, "--sequence-min-support") {
+        config.sequence.min_support = value.parse().map_err(|_| invalid_arg("--sequence-min-support", value))?;
+    }
+    if let Some(value) = arg_value(args, "--retention-resolution") {
+        config.retention.resolution = value.parse().ok()
+            .filter(|resolution| *resolution > 0)
+            .ok_or_else(|| invalid_arg("--retention-resolution", value))?;
+    }
+    if let Some(value) = arg_value(args, "--metrics-ema-alpha") {
+        config.aggregation = Aggregation::parse(value).ok_or_else(|| invalid_arg("--metrics-ema-alpha", value))?;
+    }
+    if let Some(value) = arg_value(args, "--ranker") {
+        config.ranker = RankerChoice::parse(value).ok_or_else(|| invalid_arg("--ranker", value))?;
+    }
+    if let Some(path) = arg_value(args, "--implicit-config") {
+        let file = std::fs::File::open(path)?;
+        config.implicit = serde_json::from_reader(io::BufReader::new(file))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid implicit feedback config {}: {}", path, e)))?;
+    }
+    if let Some(path) = arg_value(args, "--blend-config") {
+        let file = std::fs::File::open(path)?;
+        config.blend = serde_json::from_reader(io::BufReader::new(file))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid blend config {}: {}", path, e)))?;
+    }
+    
+    Ok(config)
+}
+
+// Refreshes every user's cached top-N in the background. It only reads from
+// storage, so requests and interaction writes proceed while it runs.
+fn spawn_precompute_job(engine: Arc<RecommendationEngine>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        engine.recommendation_cache.purge_expired();
+        let result = engine.storage.user_ids().and_then(|user_ids| {
+            user_ids.iter().try_for_each(|user_id| engine.precompute_recommendations(user_id))
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to precompute recommendations: {}", e);
+        }
+        
+        std::thread::sleep(interval);
+    });
+}
+
+fn load_experiments(engine: &RecommendationEngine, path: &str) -> io::Result<()> {
+    let file = std::fs::File::open(path)?;
+    let definitions: Vec<ExperimentDefinition> = serde_json::from_reader(io::BufReader::new(file))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid experiments file {}: {}", path, e)))?;
+    
+    for definition in definitions {
+        let experiment_id = definition.id.clone();
+        engine.register_experiment(definition).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid experiment {}: {:?}", experiment_id, e),
+        ))?;
+    }
+    Ok(())
+}
+
+fn open_storage(args: &[String], log_config: &EventLogConfig) -> io::Result<Arc<dyn Storage>> {
+    match arg_value(args, "--storage").unwrap_or("memory") {
+        "memory" => Ok(Arc::new(MemoryStorage::new())),
+        "sqlite" => {
+            let path = arg_value(args, "--sqlite-path")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| log_config.dir.join("recommend.db"));
+            std::fs::create_dir_all(&log_config.dir)?;
+            
+            eprintln!("Opening SQLite storage at {}...", path.display());
+            let storage = SqliteStorage::open(&path).map_err(io::Error::other)?;
+            Ok(Arc::new(storage))
+        }
+        other => Err(invalid_arg("--storage", other)),
+    }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bench") {
+        return run_bench(&args);
+    }
+    
+    let log_config = event_log_config(&args)?;
+    let engine_config = engine_config(&args)?;
+    let storage = open_storage(&args, &log_config)?;
+    
+    match args.first().map(String::as_str) {
+        Some("export") => {
+            let engine = RecommendationEngine::recover(storage, log_config, engine_config)?;
+            return run_export(&engine, &args);
+        }
+        Some("evaluate") => {
+            let engine = RecommendationEngine::recover(storage, log_config, engine_config)?;
+            return run_evaluate(&engine, &args);
+        }
+        Some("tune") => {
+            let engine = RecommendationEngine::recover(storage, log_config, engine_config)?;
+            return run_tune(&engine, &args);
+        }
+        Some("train-ranker") => {
+            let engine = RecommendationEngine::recover(storage, log_config, engine_config)?;
+            return run_train_ranker(&engine, &args);
+        }
+        _ => {}
+    }
+    
+    let evaluation_config = evaluation_config(&args)?;
+    let evaluate_interval = match arg_value(&args, "--evaluate-interval-secs") {
+        Some(value) => Duration::from_secs(value.parse().map_err(|_| invalid_arg("--evaluate-interval-secs", value))?),
+        None => Duration::from_secs(3600),
+    };
+    
     println!("Starting video recommendation system...");
     
-    let mut recommendation_engine = RecommendationEngine::new();
+    println!("Recovering engine state from {}...", log_config.dir.display());
+    let precompute_interval = engine_config.cache.precompute_interval;
+    let recommendation_engine = RecommendationEngine::recover(storage, log_config, engine_config)?;
     
-    recommendation_engine.add_dummy_data();
+    if let Some(path) = arg_value(&args, "--experiments") {
+        load_experiments(&recommendation_engine, path)?;
+    }
     
-    let engine_data = Arc::new(Mutex::new(recommendation_engine));
+    let engine_data = Arc::new(recommendation_engine);
+    let shutdown_engine = engine_data.clone();
+    let simulator = Arc::new(Simulator::default());
+    
+    if !precompute_interval.is_zero() {
+        spawn_precompute_job(engine_data.clone(), precompute_interval);
+    }
+    if !evaluate_interval.is_zero() {
+        spawn_evaluation_job(engine_data.clone(), evaluation_config, evaluate_interval);
+    }
     
     println!("Starting web server on port 8080...");
     
@@ -709,23 +2645,50 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .wrap(cors)
             .app_data(web::Data::new(engine_data.clone()))
+            .app_data(web::Data::new(simulator.clone()))
             .service(
                 web::scope("/api")
                     .route("/recommendations", web::post().to(get_recommendations))
                     .route("/watch", web::post().to(record_watch))
+                    .route("/interaction", web::post().to(record_watch_session))
                     .route("/like", web::post().to(record_like))
                     .route("/comment", web::post().to(record_comment))
                     .route("/share", web::post().to(record_share))
                     .route("/subscribe", web::post().to(record_subscribe))
+                    .route("/users/{id}/timezone", web::put().to(set_timezone))
+                    .route("/users/{id}/patterns", web::get().to(get_patterns))
+                    .route("/users/{id}/interests", web::get().to(get_interests))
+                    .route("/users/{id}/patterns/recompute", web::post().to(recompute_patterns))
+                    .route("/users/{id}/negative-feedback", web::get().to(get_negative_feedback))
+                    .route("/users/{id}/not-interested", web::post().to(mark_not_interested))
+                    .route("/users/{id}/not-interested/{video_id}", web::delete().to(unmark_not_interested))
+                    .route("/users/{id}/dont-recommend-channel", web::post().to(block_channel))
+                    .route("/users/{id}/dont-recommend-channel/{channel_id}", web::delete().to(unblock_channel))
+                    .route("/users/{id}/less-of-category", web::post().to(less_of_category))
+                    .route("/users/{id}/less-of-category/{category}", web::delete().to(undo_less_of_category))
                     .route("/stats", web::get().to(get_stats))
                     .route("/chart-data", web::get().to(get_chart_data))
+                    .route("/export/{dataset}", web::get().to(export_data))
                     .route("/simulate", web::post().to(run_simulation))
                     .route("/simulation-status", web::get().to(get_simulation_status))
                     .route("/simulation-results", web::get().to(get_simulation_results))
+                    .route("/impressions/report", web::get().to(get_impression_report))
+                    .route("/videos/{id}/up-next", web::get().to(get_up_next))
+                    .route("/videos/{id}/retention", web::get().to(get_retention))
+                    .route("/ranker", web::get().to(get_ranker))
+                    .route("/ranker/train", web::post().to(train_ranker))
+                    .route("/ranker/activate", web::post().to(activate_ranker))
+                    .route("/experiments", web::get().to(list_experiments))
+                    .route("/experiments", web::post().to(create_experiment))
+                    .route("/experiments/{id}/stop", web::post().to(stop_experiment))
+                    .route("/experiments/{id}/results", web::get().to(get_experiment_results))
             )
             .service(actix_files::Files::new("/", "./static").index_file("index.html"))
     })
     .bind("127.0.0.1:8080")?
     .run()
-    .await
+    .await?;
+    
+    println!("Writing final snapshot...");
+    shutdown_engine.checkpoint()
 }
\ No newline at end of file
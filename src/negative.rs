@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::io;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::implicit::Feedback;
+use crate::storage::Storage;
+use crate::{Candidate, Video};
+
+// Score multiplier for each category of a video the user asked to see less of.
+const LESS_OF_CATEGORY_PENALTY: f64 = 0.5;
+
+// What a user has asked not to be shown, with when they asked.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NegativeFeedback {
+    pub not_interested: HashMap<String, DateTime<Utc>>,
+    pub blocked_channels: HashMap<String, DateTime<Utc>>,
+    pub less_of_categories: HashMap<String, DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum NegativeTarget {
+    Video(String),
+    Channel(String),
+    Category(String),
+}
+
+impl NegativeFeedback {
+    pub fn is_empty(&self) -> bool {
+        self.not_interested.is_empty() && self.blocked_channels.is_empty() && self.less_of_categories.is_empty()
+    }
+    
+    fn entries(&mut self, target: &NegativeTarget) -> (&mut HashMap<String, DateTime<Utc>>, String) {
+        match target {
+            NegativeTarget::Video(id) => (&mut self.not_interested, id.clone()),
+            NegativeTarget::Channel(id) => (&mut self.blocked_channels, id.clone()),
+            NegativeTarget::Category(id) => (&mut self.less_of_categories, id.clone()),
+        }
+    }
+    
+    pub fn contains(&self, target: &NegativeTarget) -> bool {
+        match target {
+            NegativeTarget::Video(id) => self.not_interested.contains_key(id),
+            NegativeTarget::Channel(id) => self.blocked_channels.contains_key(id),
+            NegativeTarget::Category(id) => self.less_of_categories.contains_key(id),
+        }
+    }
+    
+    pub fn add(&mut self, target: &NegativeTarget, timestamp: DateTime<Utc>) {
+        let (entries, id) = self.entries(target);
+        entries.insert(id, timestamp);
+    }
+    
+    pub fn remove(&mut self, target: &NegativeTarget) {
+        let (entries, id) = self.entries(target);
+        entries.remove(&id);
+    }
+    
+    pub fn blocks(&self, video: &Video) -> bool {
+        self.not_interested.contains_key(&video.id) || self.blocked_channels.contains_key(&video.channel_id)
+    }
+    
+    pub fn penalty(&self, video: &Video) -> f64 {
+        let matches = video.categories.iter()
+            .filter(|category| self.less_of_categories.contains_key(*category))
+            .count();
+        LESS_OF_CATEGORY_PENALTY.powi(matches as i32)
+    }
+    
+    // Drops videos the user is not interested in or whose channel they
+    // blocked, and scales down those in categories they want less of.
+    // Candidates that are not in the catalog can only be matched by id.
+    pub fn apply(&self, candidates: &mut Vec<Candidate>, storage: &dyn Storage) -> io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut kept = Vec::with_capacity(candidates.len());
+        for mut candidate in candidates.drain(..) {
+            let keep = match storage.get_video(&candidate.video_id)? {
+                Some(video) if self.blocks(&video) => false,
+                Some(video) => {
+                    candidate.score *= self.penalty(&video);
+                    true
+                }
+                None => !self.not_interested.contains_key(&candidate.video_id),
+            };
+            if keep {
+                kept.push(candidate);
+            }
+        }
+        *candidates = kept;
+        Ok(())
+    }
+    
+    // Adds every video the user is not interested in, and every video of a
+    // blocked channel, to their ratings as a negative event. Collaborative
+    // filtering then finds fewer neighbours among users who liked them.
+    pub fn add_negatives(&self, ratings: &mut HashMap<String, Feedback>, negative: Feedback, storage: &dyn Storage) -> io::Result<()> {
+        let mut video_ids: Vec<String> = self.not_interested.keys().cloned().collect();
+        for channel_id in self.blocked_channels.keys() {
+            video_ids.extend(storage.channel_video_ids(channel_id)?);
+        }
+        video_ids.sort();
+        video_ids.dedup();
+        
+        for video_id in video_ids {
+            let feedback = match ratings.get(&video_id) {
+                Some(rating) => {
+                    let confidence = rating.confidence + negative.confidence;
+                    Feedback {
+                        preference: (rating.preference * rating.confidence + negative.preference * negative.confidence) / confidence,
+                        confidence,
+                    }
+                }
+                None => negative,
+            };
+            ratings.insert(video_id, feedback);
+        }
+        Ok(())
+    }
+    
+    // Each list newest first.
+    pub fn list(&self) -> serde_json::Value {
+        let sorted = |entries: &HashMap<String, DateTime<Utc>>, key: &str| -> Vec<serde_json::Value> {
+            let mut entries: Vec<_> = entries.iter().collect();
+            entries.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            entries.into_iter().map(|(id, since)| serde_json::json!({key: id, "since": since})).collect()
+        };
+        serde_json::json!({
+            "not_interested": sorted(&self.not_interested, "video_id"),
+            "blocked_channels": sorted(&self.blocked_channels, "channel_id"),
+            "less_of_categories": sorted(&self.less_of_categories, "category"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::VideoMetrics;
+    
+    fn video(id: &str, channel_id: &str) -> Video {
+        Video {
+            id: id.to_string(),
+            title: id.to_string(),
+            channel_id: channel_id.to_string(),
+            duration: Duration::from_secs(600),
+            categories: vec!["music".to_string()],
+            tags: Vec::new(),
+            upload_date: Utc::now(),
+            metrics: VideoMetrics::default(),
+            embedding: Vec::new(),
+        }
+    }
+    
+    #[test]
+    fn negatives_cover_blocked_channels() {
+        let storage = MemoryStorage::new();
+        storage.put_video(video("video1", "channel1")).unwrap();
+        storage.put_video(video("video2", "channel1")).unwrap();
+        storage.put_video(video("video3", "channel2")).unwrap();
+        // Moving a video to another channel takes it out of the old one.
+        storage.put_video(video("video2", "channel2")).unwrap();
+        
+        let mut feedback = NegativeFeedback::default();
+        feedback.add(&NegativeTarget::Channel("channel1".to_string()), Utc::now());
+        feedback.add(&NegativeTarget::Video("video4".to_string()), Utc::now());
+        
+        let negative = Feedback { preference: -1.0, confidence: 5.0 };
+        let mut ratings = HashMap::from([("video1".to_string(), Feedback { preference: 1.0, confidence: 5.0 })]);
+        feedback.add_negatives(&mut ratings, negative, &storage).unwrap();
+        
+        let mut rated: Vec<_> = ratings.keys().cloned().collect();
+        rated.sort();
+        assert_eq!(rated, vec!["video1", "video4"]);
+        assert_eq!(ratings["video1"], Feedback { preference: 0.0, confidence: 10.0 });
+        assert_eq!(ratings["video4"], negative);
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Comment, Interaction, InteractionPatterns, User};
+
+// Weight of the newest event in the moving averages once a user has enough
+// history. Before that every event counts equally, so a new user's patterns
+// come from their first few events instead of creeping up from zero.
+const EMA_ALPHA: f64 = 0.1;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EventCounts {
+    pub watches: u64,
+    pub likes: u64,
+    pub dislikes: u64,
+    pub comments: u64,
+    pub comment_chars: u64,
+    pub shares: u64,
+}
+
+fn alpha(watches: u64) -> f64 {
+    EMA_ALPHA.max(1.0 / watches.max(1) as f64)
+}
+
+// A watch is a view that has not been liked or shared yet, so it pulls both
+// ratios down; a later like or share of it adds the same weight back.
+pub fn record_watch(patterns: &mut InteractionPatterns, watch_percentage: f64) {
+    patterns.counts.watches += 1;
+    let alpha = alpha(patterns.counts.watches);
+    patterns.avg_watch_percentage += alpha * (watch_percentage - patterns.avg_watch_percentage);
+    patterns.like_to_view_ratio *= 1.0 - alpha;
+    patterns.share_frequency *= 1.0 - alpha;
+}
+
+// Only the first like of a view moves the ratio, so liking the same view
+// again cannot push it past one like per view.
+pub fn record_like(patterns: &mut InteractionPatterns, is_like: bool, first_for_view: bool) {
+    if !is_like {
+        patterns.counts.dislikes += 1;
+        return;
+    }
+    patterns.counts.likes += 1;
+    if first_for_view && patterns.counts.watches > 0 {
+        patterns.like_to_view_ratio = (patterns.like_to_view_ratio + alpha(patterns.counts.watches)).min(1.0);
+    }
+}
+
+pub fn record_share(patterns: &mut InteractionPatterns) {
+    patterns.counts.shares += 1;
+    patterns.share_frequency = (patterns.share_frequency + alpha(patterns.counts.watches)).min(1.0);
+}
+
+// Comment length is a plain mean in characters over every comment.
+pub fn record_comment(patterns: &mut InteractionPatterns, text: &str) {
+    patterns.counts.comments += 1;
+    patterns.counts.comment_chars += text.chars().count() as u64;
+    patterns.avg_comment_length = (patterns.counts.comment_chars / patterns.counts.comments) as usize;
+}
+
+// Rebuilds the averages and counts by replaying the watch history in order,
+// with the likes and shares recorded on each watch, and the user's comments.
+// Likes and shares of videos the user never watched are not in the history
+// and drop out.
+pub fn recompute(user: &mut User, comments: &[Comment]) {
+    let patterns = &mut user.interaction_patterns;
+    patterns.counts = EventCounts::default();
+    patterns.avg_watch_percentage = 0.0;
+    patterns.avg_comment_length = 0;
+    patterns.like_to_view_ratio = 0.0;
+    patterns.share_frequency = 0.0;
+    
+    for watch in &user.watch_history {
+        let video_secs = watch.video_duration.as_secs_f64();
+        if video_secs > 0.0 {
+            record_watch(patterns, watch.watch_duration.as_secs_f64() / video_secs);
+        }
+        let mut liked = false;
+        for interaction in &watch.interactions {
+            match interaction {
+                Interaction::Like => {
+                    record_like(patterns, true, !liked);
+                    liked = true;
+                }
+                Interaction::Dislike => record_like(patterns, false, false),
+                Interaction::Share => record_share(patterns),
+                _ => {}
+            }
+        }
+    }
+    for comment in comments {
+        record_comment(patterns, &comment.text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::temporal::TemporalProfile;
+    
+    fn empty() -> InteractionPatterns {
+        InteractionPatterns {
+            avg_watch_percentage: 0.0,
+            avg_comment_length: 0,
+            time_of_day_preferences: HashMap::new(),
+            day_of_week_preferences: HashMap::new(),
+            like_to_view_ratio: 0.0,
+            share_frequency: 0.0,
+            temporal_profile: TemporalProfile::default(),
+            counts: EventCounts::default(),
+        }
+    }
+    
+    #[test]
+    fn likes_without_a_view_leave_the_ratio_alone() {
+        let mut patterns = empty();
+        record_like(&mut patterns, true, true);
+        
+        assert_eq!(patterns.counts.likes, 1);
+        assert_eq!(patterns.like_to_view_ratio, 0.0);
+    }
+    
+    #[test]
+    fn only_the_first_like_of_a_view_counts() {
+        let mut patterns = empty();
+        record_watch(&mut patterns, 0.5);
+        record_watch(&mut patterns, 0.5);
+        record_like(&mut patterns, true, true);
+        record_like(&mut patterns, true, false);
+        
+        assert_eq!(patterns.counts.likes, 2);
+        assert_eq!(patterns.like_to_view_ratio, 0.5);
+    }
+    
+    #[test]
+    fn comment_length_is_measured_in_characters() {
+        let mut patterns = empty();
+        record_comment(&mut patterns, "great video");
+        record_comment(&mut patterns, "meh");
+        
+        assert_eq!(patterns.avg_comment_length, 7);
+    }
+}
@@ -1,21 +1,20 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
 use std::cmp::Ordering;
-use rand::{rng, Rng};
+use std::io;
+use chrono::{DateTime, Utc};
 use crate::Video;
+use crate::randomness::Jitter;
+use crate::storage::Storage;
 
 pub fn popularity_based_recommendations(
     count: usize,
-    videos: &Arc<Mutex<HashMap<String, Video>>>
-) -> Vec<(String, f64)> {
+    storage: &dyn Storage,
+    jitter: &mut Jitter,
+    now: DateTime<Utc>
+) -> io::Result<Vec<(String, f64)>> {
     let mut recommendations = Vec::new();
-    let mut rng = rng();
     
-    let videos_guard = videos.lock().unwrap();
-    
-    
-    if !videos_guard.is_empty() {
-        for (video_id, video) in videos_guard.iter() {
+    if storage.video_count()? > 0 {
+        storage.for_each_video(&mut |video| {
             let view_score = (video.metrics.views as f64).log10().max(0.0);
             let like_ratio = if video.metrics.views > 0 {
                 video.metrics.likes as f64 / video.metrics.views as f64
@@ -23,14 +22,13 @@ pub fn popularity_based_recommendations(
                 0.0
             };
             
-            let now = chrono::Utc::now();
             let days_old = (now - video.upload_date).num_days().max(1) as f64;
             let recency_factor = 1.0 + (30.0 / days_old).min(3.0);
             
             let score = (view_score * 0.6 + like_ratio * 0.4) * recency_factor;
             
-            recommendations.push((video_id.clone(), score));
-        }
+            recommendations.push((video.id.clone(), score));
+        })?;
     } else {
         let dummy_videos = vec![
             "trending_1", "trending_2", "trending_3", "trending_4", "trending_5",
@@ -38,9 +36,9 @@ pub fn popularity_based_recommendations(
         ];
         
         for video_id in dummy_videos {
-            let views = rng.random_range(10000..1000000);
-            let view_score = (views as f64).log10();
-            let like_ratio = rng.random_range(0.6..0.95);
+            let views = jitter.between(10000.0, 1000000.0);
+            let view_score = views.log10();
+            let like_ratio = jitter.between(0.6, 0.95);
             
             let score = view_score * 0.6 + like_ratio * 0.4;
             recommendations.push((video_id.to_string(), score));
@@ -48,14 +46,15 @@ pub fn popularity_based_recommendations(
     }
     
     recommendations.sort_by(|a, b| {
-        b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal)
+        b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then_with(|| a.0.cmp(&b.0))
     });
     
     recommendations.truncate(count);
     
-    recommendations
+    Ok(recommendations)
 }
 
+#[allow(dead_code)]
 fn calculate_trending_score(video: &Video) -> f64 {
     let now = chrono::Utc::now();
     let video_age_hours = (now - video.upload_date).num_hours().max(1) as f64;
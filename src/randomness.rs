@@ -0,0 +1,147 @@
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Beta, Distribution};
+
+// Decides where the jitter in recommendation sources comes from. Seeded
+// policies derive a separate stream per source from the seed, the user and
+// (optionally) the UTC day, so identical requests return identical lists.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RngPolicy {
+    Entropy,
+    Fixed(u64),
+    PerUser(u64),
+    PerUserDay(u64),
+    Off,
+}
+
+impl RngPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "entropy" => return Some(RngPolicy::Entropy),
+            "off" => return Some(RngPolicy::Off),
+            _ => {}
+        }
+        
+        let (kind, seed) = value.split_once(':')?;
+        let seed = seed.parse().ok()?;
+        match kind {
+            "fixed" => Some(RngPolicy::Fixed(seed)),
+            "user" => Some(RngPolicy::PerUser(seed)),
+            "user-day" => Some(RngPolicy::PerUserDay(seed)),
+            _ => None,
+        }
+    }
+    
+    pub fn jitter(&self, source: &str, user_id: &str, now: DateTime<Utc>) -> Jitter {
+        let rng = match *self {
+            RngPolicy::Entropy => Some(StdRng::from_rng(&mut rand::rng())),
+            RngPolicy::Fixed(seed) => Some(seeded(seed, &[source])),
+            RngPolicy::PerUser(seed) => Some(seeded(seed, &[source, user_id])),
+            RngPolicy::PerUserDay(seed) => {
+                let day = now.date_naive().to_string();
+                Some(seeded(seed, &[source, user_id, &day]))
+            }
+            RngPolicy::Off => None,
+        };
+        Jitter { rng }
+    }
+}
+
+impl Default for RngPolicy {
+    fn default() -> Self {
+        RngPolicy::PerUserDay(0)
+    }
+}
+
+// FNV-1a keeps the derived seeds stable across builds and platforms, which
+// the standard library hashers do not promise.
+pub fn stable_hash(seed: u64, parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325 ^ seed;
+    for part in parts {
+        for byte in part.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+fn seeded(seed: u64, parts: &[&str]) -> StdRng {
+    StdRng::seed_from_u64(stable_hash(seed, parts))
+}
+
+// Source of noise for a single recommendation source. With jitter turned off
+// every draw lands in the middle of its range.
+pub struct Jitter {
+    rng: Option<StdRng>,
+}
+
+impl Jitter {
+    pub fn unit(&mut self) -> f64 {
+        match self.rng.as_mut() {
+            Some(rng) => rng.random(),
+            None => 0.5,
+        }
+    }
+    
+    pub fn between(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.unit()
+    }
+    
+    pub fn index(&mut self, len: usize) -> usize {
+        ((self.unit() * len as f64) as usize).min(len.saturating_sub(1))
+    }
+    
+    // Without an rng this returns the mean of the distribution.
+    pub fn beta(&mut self, alpha: f64, beta: f64) -> f64 {
+        match (self.rng.as_mut(), Beta::new(alpha, beta)) {
+            (Some(rng), Ok(distribution)) => distribution.sample(rng),
+            _ => alpha / (alpha + beta),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn draws(policy: RngPolicy, source: &str, user_id: &str, now: DateTime<Utc>) -> Vec<f64> {
+        let mut jitter = policy.jitter(source, user_id, now);
+        (0..5).map(|_| jitter.unit()).collect()
+    }
+    
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+    
+    #[test]
+    fn per_user_day_repeats_within_a_day() {
+        let policy = RngPolicy::PerUserDay(7);
+        let morning = at("2024-03-10T01:00:00Z");
+        let evening = at("2024-03-10T23:30:00Z");
+        
+        assert_eq!(draws(policy, "popularity", "user1", morning), draws(policy, "popularity", "user1", evening));
+    }
+    
+    #[test]
+    fn per_user_day_changes_with_day_user_source_and_seed() {
+        let policy = RngPolicy::PerUserDay(7);
+        let now = at("2024-03-10T12:00:00Z");
+        let base = draws(policy, "popularity", "user1", now);
+        
+        assert_ne!(base, draws(policy, "popularity", "user1", at("2024-03-11T12:00:00Z")));
+        assert_ne!(base, draws(policy, "popularity", "user2", now));
+        assert_ne!(base, draws(policy, "content_based", "user1", now));
+        assert_ne!(base, draws(RngPolicy::PerUserDay(8), "popularity", "user1", now));
+    }
+    
+    #[test]
+    fn off_draws_the_middle_of_the_range() {
+        let mut jitter = RngPolicy::Off.jitter("popularity", "user1", Utc::now());
+        
+        assert_eq!(jitter.between(2.0, 4.0), 3.0);
+        assert_eq!(jitter.index(10), 5);
+        assert_eq!(jitter.beta(2.0, 6.0), 0.25);
+    }
+}
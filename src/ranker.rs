@@ -0,0 +1,507 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use chrono::{DateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::evaluation::Source;
+use crate::impressions::{LogEntry, OutcomeKind};
+use crate::temporal::local_time;
+use crate::{Candidate, User, Video};
+
+const MODEL_PREFIX: &str = "ranker-v";
+const MODEL_SUFFIX: &str = ".json";
+const MIN_EXAMPLES: usize = 100;
+
+pub const FEATURE_NAMES: [&str; 17] = [
+    "collaborative",
+    "content_based",
+    "popularity",
+    "temporal",
+    "engagement",
+    "like_ratio",
+    "dislike_ratio",
+    "share_ratio",
+    "comment_ratio",
+    "completion_rate",
+    "avg_watch_percentage",
+    "log_views",
+    "log_age_days",
+    "subscribed",
+    "category_preference",
+    "hour_preference",
+    "watch_percentage_match",
+];
+
+const SCORED_SOURCES: [Source; 5] = [
+    Source::Collaborative,
+    Source::ContentBased,
+    Source::Popularity,
+    Source::Temporal,
+    Source::Engagement,
+];
+
+// The second-stage features of one candidate, in `FEATURE_NAMES` order. They
+// are logged with every impression, so a model is trained on exactly what the
+// engine saw when it served the list.
+pub fn features(candidate: &Candidate, video: &Video, user: Option<&User>, now: DateTime<Utc>) -> Vec<f64> {
+    let metrics = &video.metrics;
+    let per_view = |count: u64| if metrics.views > 0 { count as f64 / metrics.views as f64 } else { 0.0 };
+    let age_days = (now - video.upload_date).num_seconds().max(0) as f64 / 86_400.0;
+    
+    let mut features: Vec<f64> = SCORED_SOURCES.iter()
+        .map(|source| candidate.source_scores.get(source).copied().unwrap_or(0.0))
+        .collect();
+    features.extend([
+        per_view(metrics.likes),
+        per_view(metrics.dislikes),
+        per_view(metrics.share_count),
+        per_view(metrics.comment_count),
+        metrics.completion_rate,
+        metrics.avg_watch_percentage,
+        (metrics.views as f64).ln_1p(),
+        age_days.ln_1p(),
+    ]);
+    
+    match user {
+        Some(user) => {
+            let patterns = &user.interaction_patterns;
+            let max_hour = patterns.time_of_day_preferences.values().copied().fold(0.0, f64::max);
+            let hour = patterns.time_of_day_preferences.get(&(local_time(user, now).hour() as u8)).copied().unwrap_or(0.0);
+            features.extend([
+                if user.subscriptions.contains(&video.channel_id) { 1.0 } else { 0.0 },
+                video.categories.iter()
+                    .filter_map(|category| user.content_preferences.get(category).copied())
+                    .fold(0.0, f64::max),
+                if max_hour > 0.0 { hour / max_hour } else { 0.0 },
+                1.0 - (patterns.avg_watch_percentage - metrics.avg_watch_percentage).abs().min(1.0),
+            ]);
+        }
+        None => features.extend([0.0; 4]),
+    }
+    features
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrainingSummary {
+    pub examples: usize,
+    pub positives: usize,
+    pub holdout_examples: usize,
+    pub holdout_log_loss: f64,
+    pub holdout_auc: Option<f64>,
+}
+
+// Logistic regression on standardized features, predicting whether a served
+// slot is watched.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankerModel {
+    pub version: u32,
+    pub trained_at: DateTime<Utc>,
+    pub features: Vec<String>,
+    means: Vec<f64>,
+    scales: Vec<f64>,
+    weights: Vec<f64>,
+    bias: f64,
+    pub training: TrainingSummary,
+}
+
+impl RankerModel {
+    fn logit(&self, features: &[f64]) -> f64 {
+        self.bias + features.iter().zip(&self.means).zip(&self.scales).zip(&self.weights)
+            .map(|(((x, mean), scale), weight)| weight * (x - mean) / scale)
+            .sum::<f64>()
+    }
+    
+    pub fn predict(&self, features: &[f64]) -> f64 {
+        sigmoid(self.logit(features))
+    }
+    
+    pub fn summary(&self) -> serde_json::Value {
+        let weights: serde_json::Map<String, serde_json::Value> = self.features.iter().zip(&self.weights)
+            .map(|(name, weight)| (name.clone(), serde_json::json!(weight)))
+            .collect();
+        serde_json::json!({
+            "version": self.version,
+            "trainedAt": self.trained_at,
+            "training": self.training,
+            "bias": self.bias,
+            "weights": weights,
+        })
+    }
+}
+
+fn sigmoid(z: f64) -> f64 {
+    1.0 / (1.0 + (-z).exp())
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TrainingOptions {
+    pub epochs: usize,
+    pub learning_rate: f64,
+    pub l2: f64,
+    pub holdout_fraction: f64,
+}
+
+impl Default for TrainingOptions {
+    fn default() -> Self {
+        TrainingOptions {
+            epochs: 300,
+            learning_rate: 0.5,
+            l2: 0.001,
+            holdout_fraction: 0.2,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TrainError {
+    NotEnoughData,
+    Unavailable,
+    Io(io::Error),
+}
+
+impl From<io::Error> for TrainError {
+    fn from(error: io::Error) -> Self {
+        TrainError::Io(error)
+    }
+}
+
+struct Example {
+    features: Vec<f64>,
+    label: f64,
+}
+
+// Every logged slot with ranking features is an example, labelled positive
+// when a watch was attributed to it. The newest impressions are held out to
+// report how well the model generalises.
+fn examples(entries: &[LogEntry]) -> Vec<Example> {
+    let watched: HashSet<(&str, &str)> = entries.iter()
+        .filter_map(|entry| match entry {
+            LogEntry::Outcome(outcome) if outcome.kind == OutcomeKind::Watch => {
+                Some((outcome.request_id.as_str(), outcome.video_id.as_str()))
+            }
+            _ => None,
+        })
+        .collect();
+    
+    let mut impressions: Vec<_> = entries.iter()
+        .filter_map(|entry| match entry {
+            LogEntry::Impression(impression) => Some(impression),
+            LogEntry::Outcome(_) => None,
+        })
+        .collect();
+    impressions.sort_by_key(|impression| impression.timestamp);
+    
+    impressions.into_iter()
+        .flat_map(|impression| impression.items.iter().map(move |item| (impression, item)))
+        .filter(|(_, item)| item.rank_features.len() == FEATURE_NAMES.len())
+        .map(|(impression, item)| Example {
+            features: item.rank_features.clone(),
+            label: if watched.contains(&(impression.request_id.as_str(), item.video_id.as_str())) { 1.0 } else { 0.0 },
+        })
+        .collect()
+}
+
+fn log_loss(model: &RankerModel, examples: &[Example]) -> f64 {
+    let total: f64 = examples.iter()
+        .map(|example| {
+            let p = model.predict(&example.features).clamp(1e-12, 1.0 - 1e-12);
+            -(example.label * p.ln() + (1.0 - example.label) * (1.0 - p).ln())
+        })
+        .sum();
+    total / examples.len().max(1) as f64
+}
+
+// Probability that a random positive outscores a random negative, with ties
+// counting half.
+fn auc(model: &RankerModel, examples: &[Example]) -> Option<f64> {
+    let mut scored: Vec<(f64, f64)> = examples.iter()
+        .map(|example| (model.predict(&example.features), example.label))
+        .collect();
+    scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+    
+    let positives = scored.iter().filter(|(_, label)| *label > 0.5).count() as f64;
+    let negatives = scored.len() as f64 - positives;
+    if positives == 0.0 || negatives == 0.0 {
+        return None;
+    }
+    
+    let mut rank_sum = 0.0;
+    let mut start = 0;
+    while start < scored.len() {
+        let mut end = start;
+        while end < scored.len() && scored[end].0 == scored[start].0 {
+            end += 1;
+        }
+        let average_rank = (start + end + 1) as f64 / 2.0;
+        rank_sum += scored[start..end].iter().filter(|(_, label)| *label > 0.5).count() as f64 * average_rank;
+        start = end;
+    }
+    Some((rank_sum - positives * (positives + 1.0) / 2.0) / (positives * negatives))
+}
+
+pub fn train(entries: &[LogEntry], options: &TrainingOptions) -> Result<RankerModel, TrainError> {
+    let examples = examples(entries);
+    let positives = examples.iter().filter(|example| example.label > 0.5).count();
+    if examples.len() < MIN_EXAMPLES || positives == 0 || positives == examples.len() {
+        return Err(TrainError::NotEnoughData);
+    }
+    
+    let holdout = ((examples.len() as f64 * options.holdout_fraction.clamp(0.0, 0.5)) as usize).min(examples.len() - 1);
+    let (train_set, holdout_set) = examples.split_at(examples.len() - holdout);
+    let dimensions = FEATURE_NAMES.len();
+    let n = train_set.len() as f64;
+    
+    let mut means = vec![0.0; dimensions];
+    for example in train_set {
+        for (mean, x) in means.iter_mut().zip(&example.features) {
+            *mean += x / n;
+        }
+    }
+    let mut scales = vec![0.0; dimensions];
+    for example in train_set {
+        for ((scale, x), mean) in scales.iter_mut().zip(&example.features).zip(&means) {
+            *scale += (x - mean).powi(2) / n;
+        }
+    }
+    for scale in &mut scales {
+        *scale = if *scale > 1e-12 { scale.sqrt() } else { 1.0 };
+    }
+    
+    let standardized: Vec<Vec<f64>> = train_set.iter()
+        .map(|example| example.features.iter().zip(&means).zip(&scales).map(|((x, mean), scale)| (x - mean) / scale).collect())
+        .collect();
+    
+    // Full-batch gradient descent; the loss is convex, so this converges to
+    // the same weights every time.
+    let mut weights = vec![0.0; dimensions];
+    let mut bias = 0.0;
+    for _ in 0..options.epochs {
+        let mut gradient = vec![0.0; dimensions];
+        let mut bias_gradient = 0.0;
+        for (x, example) in standardized.iter().zip(train_set) {
+            let z = bias + x.iter().zip(&weights).map(|(x, w)| x * w).sum::<f64>();
+            let error = sigmoid(z) - example.label;
+            for (g, x) in gradient.iter_mut().zip(x) {
+                *g += error * x;
+            }
+            bias_gradient += error;
+        }
+        for (w, g) in weights.iter_mut().zip(&gradient) {
+            *w -= options.learning_rate * (g / n + options.l2 * *w);
+        }
+        bias -= options.learning_rate * bias_gradient / n;
+    }
+    
+    let mut model = RankerModel {
+        version: 0,
+        trained_at: Utc::now(),
+        features: FEATURE_NAMES.iter().map(|name| name.to_string()).collect(),
+        means,
+        scales,
+        weights,
+        bias,
+        training: TrainingSummary {
+            examples: train_set.len(),
+            positives: train_set.iter().filter(|example| example.label > 0.5).count(),
+            holdout_examples: holdout_set.len(),
+            holdout_log_loss: 0.0,
+            holdout_auc: None,
+        },
+    };
+    model.training.holdout_log_loss = log_loss(&model, holdout_set);
+    model.training.holdout_auc = auc(&model, holdout_set);
+    Ok(model)
+}
+
+// Which model the engine serves with on startup.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RankerChoice {
+    #[default]
+    Latest,
+    Version(u32),
+    Off,
+}
+
+impl RankerChoice {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "latest" => Some(RankerChoice::Latest),
+            "off" => Some(RankerChoice::Off),
+            _ => value.parse().ok().map(RankerChoice::Version),
+        }
+    }
+}
+
+// Each trained model is written once as `ranker-v<N>.json` and never
+// modified, so any version can be re-activated later.
+pub struct ModelStore {
+    dir: PathBuf,
+}
+
+impl ModelStore {
+    pub fn new(dir: PathBuf) -> Self {
+        ModelStore { dir }
+    }
+    
+    fn path(&self, version: u32) -> PathBuf {
+        self.dir.join(format!("{}{}{}", MODEL_PREFIX, version, MODEL_SUFFIX))
+    }
+    
+    pub fn versions(&self) -> io::Result<Vec<u32>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        
+        let mut versions = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(version) = name.to_str()
+                .and_then(|name| name.strip_prefix(MODEL_PREFIX))
+                .and_then(|name| name.strip_suffix(MODEL_SUFFIX))
+                .and_then(|version| version.parse().ok())
+            {
+                versions.push(version);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+    
+    pub fn load(&self, version: u32) -> io::Result<RankerModel> {
+        let file = fs::File::open(self.path(version))?;
+        let model: RankerModel = serde_json::from_reader(io::BufReader::new(file))?;
+        if model.features != FEATURE_NAMES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ranker v{} was trained on different features", version),
+            ));
+        }
+        Ok(model)
+    }
+    
+    pub fn load_choice(&self, choice: RankerChoice) -> io::Result<Option<RankerModel>> {
+        match choice {
+            RankerChoice::Off => Ok(None),
+            RankerChoice::Version(version) => self.load(version).map(Some),
+            RankerChoice::Latest => match self.versions()?.last() {
+                Some(&version) => self.load(version).map(Some),
+                None => Ok(None),
+            },
+        }
+    }
+    
+    // Assigns the next version number and writes the model through a
+    // temporary file, so a crash never leaves a half-written version behind.
+    pub fn save(&self, model: &mut RankerModel) -> io::Result<u32> {
+        fs::create_dir_all(&self.dir)?;
+        model.version = self.versions()?.last().map_or(1, |version| version + 1);
+        
+        let tmp_path = self.dir.join(format!("{}{}.tmp", MODEL_PREFIX, model.version));
+        fs::write(&tmp_path, serde_json::to_string_pretty(model)?)?;
+        fs::rename(&tmp_path, self.path(model.version))?;
+        Ok(model.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::impressions::{Impression, ImpressionItem, Outcome};
+    
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+    
+    fn example(x: f64, label: f64) -> Example {
+        let mut features = vec![0.0; FEATURE_NAMES.len()];
+        features[0] = x;
+        Example { features, label }
+    }
+    
+    fn model(weight: f64) -> RankerModel {
+        let dimensions = FEATURE_NAMES.len();
+        let mut weights = vec![0.0; dimensions];
+        weights[0] = weight;
+        RankerModel {
+            version: 0,
+            trained_at: Utc::now(),
+            features: FEATURE_NAMES.iter().map(|name| name.to_string()).collect(),
+            means: vec![0.0; dimensions],
+            scales: vec![1.0; dimensions],
+            weights,
+            bias: 0.0,
+            training: TrainingSummary {
+                examples: 0,
+                positives: 0,
+                holdout_examples: 0,
+                holdout_log_loss: 0.0,
+                holdout_auc: None,
+            },
+        }
+    }
+    
+    #[test]
+    fn auc_and_log_loss_match_hand_computed_values() {
+        // One positive-negative pair is tied, and counts half.
+        let examples = vec![example(0.0, 0.0), example(1.0, 1.0), example(1.0, 0.0), example(2.0, 1.0)];
+        assert_close(auc(&model(1.0), &examples).unwrap(), 3.5 / 4.0);
+        assert_eq!(auc(&model(1.0), &examples[1..2]), None);
+        
+        assert_close(log_loss(&model(0.0), &examples), 2f64.ln());
+        let p = sigmoid(2.0);
+        assert_close(log_loss(&model(1.0), &examples[3..]), -p.ln());
+    }
+    
+    #[test]
+    fn training_separates_watched_slots() {
+        let start = Utc::now();
+        let mut entries = Vec::new();
+        for i in 0..200 {
+            let request_id = format!("request{}", i);
+            let mut rank_features = vec![0.0; FEATURE_NAMES.len()];
+            rank_features[0] = (i % 2) as f64;
+            entries.push(LogEntry::Impression(Impression {
+                request_id: request_id.clone(),
+                user_id: "user1".to_string(),
+                timestamp: start + chrono::Duration::seconds(i),
+                variant: None,
+                context: None,
+                items: vec![ImpressionItem {
+                    video_id: "video1".to_string(),
+                    position: 1,
+                    score: 1.0,
+                    source_scores: HashMap::new(),
+                    features: Vec::new(),
+                    rank_features,
+                }],
+            }));
+            if i % 2 == 1 {
+                entries.push(LogEntry::Outcome(Outcome {
+                    request_id,
+                    user_id: "user1".to_string(),
+                    video_id: "video1".to_string(),
+                    position: 1,
+                    kind: OutcomeKind::Watch,
+                    watch_seconds: Some(60.0),
+                    timestamp: start + chrono::Duration::seconds(i),
+                    variant: None,
+                }));
+            }
+        }
+        
+        let model = train(&entries, &TrainingOptions::default()).unwrap();
+        assert_eq!(model.training.examples, 160);
+        assert_eq!(model.training.holdout_examples, 40);
+        assert_eq!(model.training.holdout_auc, Some(1.0));
+        assert!(model.training.holdout_log_loss < 0.1, "log loss {}", model.training.holdout_log_loss);
+        assert!(matches!(train(&entries[..10], &TrainingOptions::default()), Err(TrainError::NotEnoughData)));
+    }
+}
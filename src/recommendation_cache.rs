@@ -0,0 +1,188 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::Candidate;
+use crate::sharded::ShardedMap;
+
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    pub ttl: Duration,
+    pub precompute_count: usize,
+    pub precompute_interval: std::time::Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            ttl: Duration::minutes(15),
+            precompute_count: 20,
+            precompute_interval: std::time::Duration::from_secs(300),
+        }
+    }
+}
+
+struct CacheEntry {
+    candidates: Arc<[Candidate]>,
+    computed_at: DateTime<Utc>,
+}
+
+// The generation is bumped on every invalidation, so a list computed while the
+// user was interacting is discarded instead of overwriting the eviction.
+#[derive(Default)]
+struct CacheSlot {
+    generation: u64,
+    entry: Option<CacheEntry>,
+}
+
+pub struct RecommendationCache {
+    config: CacheConfig,
+    slots: ShardedMap<CacheSlot>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+    precomputed: AtomicU64,
+}
+
+impl RecommendationCache {
+    pub fn new(config: CacheConfig) -> Self {
+        RecommendationCache {
+            config,
+            slots: ShardedMap::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+            precomputed: AtomicU64::new(0),
+        }
+    }
+    
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+    
+    // Entries computed for fewer videos than requested count as a miss. Hits
+    // share the cached list instead of copying it under the shard lock.
+    pub fn get(&self, user_id: &str, count: usize) -> Option<Arc<[Candidate]>> {
+        let fresh = self.slots.read(user_id, |slot| {
+            slot.and_then(|slot| slot.entry.as_ref())
+                .filter(|entry| Utc::now() - entry.computed_at < self.config.ttl && entry.candidates.len() >= count)
+                .map(|entry| entry.candidates.clone())
+        });
+        
+        match fresh {
+            Some(candidates) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(candidates)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+    
+    pub fn generation(&self, user_id: &str) -> u64 {
+        self.slots.read(user_id, |slot| slot.map_or(0, |slot| slot.generation))
+    }
+    
+    pub fn insert(&self, user_id: &str, generation: u64, candidates: Vec<Candidate>) {
+        self.slots.upsert(user_id, |slot| {
+            if slot.generation == generation {
+                slot.entry = Some(CacheEntry {
+                    candidates: candidates.into(),
+                    computed_at: Utc::now(),
+                });
+            }
+        });
+    }
+    
+    pub fn record_precomputed(&self) {
+        self.precomputed.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    pub fn invalidate(&self, user_id: &str) {
+        let evicted = self.slots.upsert(user_id, |slot| {
+            slot.generation += 1;
+            slot.entry.take().is_some()
+        });
+        if evicted {
+            self.invalidations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    pub fn invalidate_all(&self) {
+        self.slots.for_each_mut(|_, slot| {
+            slot.generation += 1;
+            if slot.entry.take().is_some() {
+                self.invalidations.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    }
+    
+    pub fn purge_expired(&self) {
+        let now = Utc::now();
+        self.slots.for_each_mut(|_, slot| {
+            if slot.entry.as_ref().is_some_and(|entry| now - entry.computed_at >= self.config.ttl) {
+                slot.entry = None;
+            }
+        });
+    }
+    
+    pub fn stats(&self) -> serde_json::Value {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        
+        let mut entries = 0;
+        self.slots.for_each(|_, slot| entries += slot.entry.is_some() as usize);
+        
+        serde_json::json!({
+            "entries": entries,
+            "hits": hits,
+            "misses": misses,
+            "hitRate": if lookups > 0 { hits as f64 / lookups as f64 } else { 0.0 },
+            "invalidations": self.invalidations.load(Ordering::Relaxed),
+            "precomputed": self.precomputed.load(Ordering::Relaxed),
+            "ttlSeconds": self.config.ttl.num_seconds(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    
+    fn candidates(ids: &[&str]) -> Vec<Candidate> {
+        ids.iter()
+            .map(|id| Candidate { video_id: id.to_string(), score: 1.0, source_scores: HashMap::new() })
+            .collect()
+    }
+    
+    #[test]
+    fn invalidation_discards_lists_from_older_generations() {
+        let cache = RecommendationCache::new(CacheConfig::default());
+        let generation = cache.generation("user1");
+        cache.insert("user1", generation, candidates(&["video1", "video2"]));
+        assert_eq!(cache.get("user1", 2).unwrap().len(), 2);
+        // Asking for more than was computed is a miss.
+        assert!(cache.get("user1", 3).is_none());
+        
+        // A list computed before an invalidation must not land after it.
+        let stale = cache.generation("user1");
+        cache.invalidate("user1");
+        assert!(cache.get("user1", 1).is_none());
+        cache.insert("user1", stale, candidates(&["video1"]));
+        assert!(cache.get("user1", 1).is_none());
+        
+        cache.insert("user1", cache.generation("user1"), candidates(&["video3"]));
+        assert_eq!(cache.get("user1", 1).unwrap()[0].video_id, "video3");
+        
+        cache.invalidate_all();
+        assert!(cache.get("user1", 1).is_none());
+        
+        let stats = cache.stats();
+        assert_eq!(stats["invalidations"], 2);
+        assert_eq!(stats["entries"], 0);
+    }
+}
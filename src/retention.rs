@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Storage;
+use crate::{Interaction, VideoMetrics, WatchEvent};
+
+// A drop between two neighbouring points counts as a hotspot when it is at
+// least this many times the curve's average drop per interval.
+const HOTSPOT_FACTOR: f64 = 2.0;
+const MAX_HOTSPOTS: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct RetentionConfig {
+    // Intervals the video is split into; the curve has one more point.
+    pub resolution: usize,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig { resolution: 20 }
+    }
+}
+
+// Per-video counts the curve and rewatch rate are derived from. `reached`
+// holds, for each point of the curve, how many sessions got to it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RetentionCounts {
+    pub sessions: u64,
+    pub reached: Vec<u64>,
+    pub viewers: u64,
+    pub rewatchers: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RetentionPoint {
+    position: f32,
+    seconds: f64,
+    retention: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hotspot {
+    from: f32,
+    to: f32,
+    from_seconds: f64,
+    to_seconds: f64,
+    drop: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RetentionReport {
+    video_id: String,
+    resolution: usize,
+    sessions: u64,
+    rewatch_rate: f64,
+    curve: Vec<RetentionPoint>,
+    hotspots: Vec<Hotspot>,
+}
+
+// Which points of the curve a watch got to. Playback runs from the start;
+// skipped ranges move it forward without using watch time and rewound ranges
+// use watch time without moving it, so the furthest position reached is the
+// watch time plus what was skipped minus what was watched again. Points
+// inside a skipped range were not seen.
+fn reached_points(watch: &WatchEvent, resolution: usize) -> Vec<bool> {
+    let length = watch.video_duration.as_secs_f64();
+    let mut skips = Vec::new();
+    let mut rewound = 0.0;
+    for interaction in &watch.interactions {
+        match interaction {
+            Interaction::Skip(ranges) => skips.extend(ranges.iter().map(|(from, to)| (from.as_secs_f64(), to.as_secs_f64()))),
+            Interaction::Rewind(ranges) => {
+                rewound += ranges.iter().map(|(from, to)| from.saturating_sub(*to).as_secs_f64()).sum::<f64>();
+            }
+            _ => {}
+        }
+    }
+    let skipped: f64 = skips.iter().map(|(from, to)| (to - from).max(0.0)).sum();
+    let furthest = (watch.watch_duration.as_secs_f64() + skipped - rewound).clamp(0.0, length);
+    
+    (0..=resolution).map(|point| {
+        let at = length * point as f64 / resolution as f64;
+        at <= furthest && !skips.iter().any(|(from, to)| at > *from && at < *to)
+    }).collect()
+}
+
+impl RetentionCounts {
+    fn new(resolution: usize) -> Self {
+        RetentionCounts { reached: vec![0; resolution + 1], ..Default::default() }
+    }
+    
+    // `earlier_watches` is how often the user had watched the video before,
+    // so the second watch makes them a rewatcher.
+    fn record(&mut self, watch: &WatchEvent, earlier_watches: usize, resolution: usize) {
+        if self.reached.len() != resolution + 1 {
+            *self = RetentionCounts::new(resolution);
+        }
+        match earlier_watches {
+            0 => self.viewers += 1,
+            1 => self.rewatchers += 1,
+            _ => {}
+        }
+        if watch.video_duration.is_zero() {
+            return;
+        }
+        self.sessions += 1;
+        for (count, reached) in self.reached.iter_mut().zip(reached_points(watch, resolution)) {
+            if reached {
+                *count += 1;
+            }
+        }
+    }
+    
+    fn curve(&self) -> Vec<(f32, f32)> {
+        if self.sessions == 0 {
+            return Vec::new();
+        }
+        let intervals = self.reached.len().saturating_sub(1).max(1);
+        self.reached.iter().enumerate()
+            .map(|(point, count)| (point as f32 / intervals as f32, *count as f32 / self.sessions as f32))
+            .collect()
+    }
+    
+    fn rewatch_rate(&self) -> f64 {
+        if self.viewers == 0 {
+            return 0.0;
+        }
+        self.rewatchers as f64 / self.viewers as f64
+    }
+}
+
+pub fn record_watch(metrics: &mut VideoMetrics, watch: &WatchEvent, earlier_watches: usize, config: &RetentionConfig) {
+    metrics.retention.record(watch, earlier_watches, config.resolution);
+    metrics.retention_curve = metrics.retention.curve();
+    metrics.rewatch_rate = metrics.retention.rewatch_rate();
+}
+
+// Recounts every video from the users' watch histories, for a new resolution
+// or videos stored before the counts were kept.
+pub fn rebuild(storage: &dyn Storage, config: &RetentionConfig) -> io::Result<()> {
+    let mut counts: HashMap<String, RetentionCounts> = HashMap::new();
+    storage.for_each_user(&mut |user| {
+        let mut watched: HashMap<&str, usize> = HashMap::new();
+        for watch in &user.watch_history {
+            let earlier_watches = watched.entry(watch.video_id.as_str()).or_insert(0);
+            counts.entry(watch.video_id.clone())
+                .or_insert_with(|| RetentionCounts::new(config.resolution))
+                .record(watch, *earlier_watches, config.resolution);
+            *earlier_watches += 1;
+        }
+    })?;
+    
+    for video_id in storage.video_ids()? {
+        let retention = counts.remove(&video_id).unwrap_or_else(|| RetentionCounts::new(config.resolution));
+        storage.update_video(&video_id, &mut |video| {
+            video.metrics.retention_curve = retention.curve();
+            video.metrics.rewatch_rate = retention.rewatch_rate();
+            video.metrics.retention = retention.clone();
+        })?;
+    }
+    Ok(())
+}
+
+pub fn needs_rebuild(metrics: &VideoMetrics, config: &RetentionConfig) -> bool {
+    metrics.retention.reached.len() != config.resolution + 1
+}
+
+// The steepest drops between neighbouring points, where viewers leave well
+// faster than they do on average over the video.
+fn hotspots(curve: &[(f32, f32)], video_duration: Duration) -> Vec<Hotspot> {
+    let (Some(first), Some(last)) = (curve.first(), curve.last()) else {
+        return Vec::new();
+    };
+    let intervals = (curve.len() - 1).max(1);
+    let average = (first.1 - last.1) as f64 / intervals as f64;
+    let length = video_duration.as_secs_f64();
+    
+    let mut hotspots: Vec<Hotspot> = curve.windows(2).enumerate()
+        .filter(|(_, pair)| {
+            let drop = (pair[0].1 - pair[1].1) as f64;
+            drop > 0.0 && drop >= HOTSPOT_FACTOR * average
+        })
+        .map(|(interval, pair)| Hotspot {
+            from: pair[0].0,
+            to: pair[1].0,
+            from_seconds: length * interval as f64 / intervals as f64,
+            to_seconds: length * (interval + 1) as f64 / intervals as f64,
+            drop: pair[0].1 - pair[1].1,
+        })
+        .collect();
+    hotspots.sort_by(|a, b| b.drop.partial_cmp(&a.drop).unwrap_or(std::cmp::Ordering::Equal));
+    hotspots.truncate(MAX_HOTSPOTS);
+    hotspots
+}
+
+pub fn report(video_id: &str, metrics: &VideoMetrics, video_duration: Duration) -> RetentionReport {
+    let length = video_duration.as_secs_f64();
+    let intervals = metrics.retention_curve.len().saturating_sub(1).max(1);
+    RetentionReport {
+        video_id: video_id.to_string(),
+        resolution: metrics.retention.reached.len().saturating_sub(1),
+        sessions: metrics.retention.sessions,
+        rewatch_rate: metrics.rewatch_rate,
+        curve: metrics.retention_curve.iter().enumerate()
+            .map(|(point, (position, retention))| RetentionPoint {
+                position: *position,
+                seconds: length * point as f64 / intervals as f64,
+                retention: *retention,
+            })
+            .collect(),
+        hotspots: hotspots(&metrics.retention_curve, video_duration),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::*;
+    
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-6, "expected {}, got {}", expected, actual);
+    }
+    
+    fn watch(watched: u64, interactions: Vec<Interaction>) -> WatchEvent {
+        WatchEvent {
+            video_id: "v".to_string(),
+            timestamp: Utc::now(),
+            watch_duration: Duration::from_secs(watched),
+            video_duration: Duration::from_secs(100),
+            interactions,
+        }
+    }
+    
+    fn range(from: u64, to: u64) -> (Duration, Duration) {
+        (Duration::from_secs(from), Duration::from_secs(to))
+    }
+    
+    #[test]
+    fn skips_and_rewinds_move_the_furthest_point() {
+        // 60s watched, 20s skipped and 10s watched again: playback got to 70s,
+        // but the point at 30s sits inside the skipped range.
+        let skipped = watch(60, vec![Interaction::Skip(vec![range(20, 40)]), Interaction::Rewind(vec![range(50, 40)])]);
+        let reached: Vec<usize> = reached_points(&skipped, 10).iter().enumerate()
+            .filter(|(_, reached)| **reached)
+            .map(|(point, _)| point)
+            .collect();
+        assert_eq!(reached, vec![0, 1, 2, 4, 5, 6, 7]);
+        
+        // Rewinding more than was watched cannot go below the start.
+        let rewound = watch(5, vec![Interaction::Rewind(vec![range(90, 0)])]);
+        assert_eq!(reached_points(&rewound, 10), [vec![true], vec![false; 10]].concat());
+    }
+    
+    #[test]
+    fn curve_averages_reached_points_over_sessions() {
+        let mut counts = RetentionCounts::default();
+        counts.record(&watch(60, vec![Interaction::Skip(vec![range(20, 40)]), Interaction::Rewind(vec![range(50, 40)])]), 0, 10);
+        counts.record(&watch(100, Vec::new()), 1, 10);
+        counts.record(&watch(30, vec![Interaction::Rewind(vec![range(30, 0)])]), 2, 10);
+        
+        assert_eq!(counts.sessions, 3);
+        assert_eq!(counts.reached, vec![3, 2, 2, 1, 2, 2, 2, 2, 1, 1, 1]);
+        let curve = counts.curve();
+        assert_eq!(curve.len(), 11);
+        assert_close(curve[3].0, 0.3);
+        assert_close(curve[3].1, 1.0 / 3.0);
+        assert_close(curve[7].1, 2.0 / 3.0);
+        assert!((counts.rewatch_rate() - 1.0).abs() < 1e-9);
+    }
+}
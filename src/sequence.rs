@@ -0,0 +1,270 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::RwLock;
+
+use crate::sharded::ShardedMap;
+use crate::storage::Storage;
+use crate::WatchEvent;
+
+// Joins a two-video context into one map key. The separator cannot appear in
+// a video id sent as JSON text without being escaped by the client.
+const PAIR_SEPARATOR: char = '\u{1f}';
+// How many of the most common next watches the fallback draws from.
+const TOP_NEXT: usize = 500;
+
+#[derive(Clone, Debug)]
+pub struct SequenceConfig {
+    pub min_support: u32,
+    pub second_order_weight: f64,
+    pub first_order_weight: f64,
+}
+
+impl Default for SequenceConfig {
+    fn default() -> Self {
+        SequenceConfig {
+            min_support: 2,
+            second_order_weight: 0.5,
+            first_order_weight: 0.4,
+        }
+    }
+}
+
+fn pair_key(first: &str, second: &str) -> String {
+    format!("{}{}{}", first, PAIR_SEPARATOR, second)
+}
+
+// "A then B" counts over every user's whole watch history in order, at first
+// order (after A) and second order (after A then B). Transitions seen fewer
+// than `min_support` times are ignored as noise. Predictions interpolate the
+// two orders with the overall frequency of each video as a next watch, and a
+// context without enough support hands its weight down to the lower order.
+pub struct SequenceModel {
+    config: SequenceConfig,
+    first_order: ShardedMap<HashMap<String, u32>>,
+    second_order: ShardedMap<HashMap<String, u32>>,
+    next_counts: ShardedMap<u32>,
+    // Counts only grow, so a video can only enter the top list by passing its
+    // smallest entry, and keeping it up to date on every watch keeps it exact.
+    top_next: RwLock<TopNext>,
+}
+
+#[derive(Default)]
+struct TopNext {
+    total: u64,
+    videos: Vec<(String, u32)>,
+}
+
+impl TopNext {
+    fn record(&mut self, video_id: &str, count: u32) {
+        self.total += 1;
+        if let Some(entry) = self.videos.iter_mut().find(|(id, _)| id == video_id) {
+            entry.1 = count;
+            return;
+        }
+        if self.videos.len() < TOP_NEXT {
+            self.videos.push((video_id.to_string(), count));
+            return;
+        }
+        let smallest = (0..self.videos.len()).min_by_key(|&index| self.videos[index].1);
+        if let Some(smallest) = smallest
+            && self.videos[smallest].1 < count
+        {
+            self.videos[smallest] = (video_id.to_string(), count);
+        }
+    }
+}
+
+impl SequenceModel {
+    pub fn new(config: SequenceConfig) -> Self {
+        SequenceModel {
+            config,
+            first_order: ShardedMap::default(),
+            second_order: ShardedMap::default(),
+            next_counts: ShardedMap::default(),
+            top_next: RwLock::new(TopNext::default()),
+        }
+    }
+    
+    // Called once on recovery, before logged events are replayed through
+    // `record_watch`.
+    pub fn load_history(&self, storage: &dyn Storage) -> io::Result<()> {
+        storage.for_each_user(&mut |user| {
+            for (index, watch) in user.watch_history.iter().enumerate() {
+                self.record_watch(&user.watch_history[index.saturating_sub(2)..index], watch);
+            }
+        })
+    }
+    
+    // `previous` holds up to two watches that came right before `next`,
+    // oldest first.
+    pub fn record_watch(&self, previous: &[WatchEvent], next: &WatchEvent) {
+        let Some(last) = previous.last() else {
+            return;
+        };
+        if last.video_id == next.video_id {
+            return;
+        }
+        
+        let add = |targets: &mut HashMap<String, u32>| {
+            *targets.entry(next.video_id.clone()).or_insert(0) += 1;
+        };
+        self.first_order.upsert(&last.video_id, add);
+        if let [before, _] = previous
+            && before.video_id != last.video_id
+        {
+            self.second_order.upsert(&pair_key(&before.video_id, &last.video_id), add);
+        }
+        let count = self.next_counts.upsert(&next.video_id, |count| {
+            *count += 1;
+            *count
+        });
+        self.top_next.write().unwrap().record(&next.video_id, count);
+    }
+    
+    // Supported transitions out of a context as probabilities, or `None`
+    // when fewer than `min_support` transitions leave it.
+    fn distribution(&self, map: &ShardedMap<HashMap<String, u32>>, key: &str) -> Option<HashMap<String, f64>> {
+        map.read(key, |targets| {
+            let supported: Vec<(&String, u32)> = targets?.iter()
+                .filter(|(_, count)| **count >= self.config.min_support)
+                .map(|(target, count)| (target, *count))
+                .collect();
+            let total: u32 = supported.iter().map(|(_, count)| count).sum();
+            if total == 0 {
+                return None;
+            }
+            Some(supported.into_iter().map(|(target, count)| (target.clone(), count as f64 / total as f64)).collect())
+        })
+    }
+    
+    // Scores what is likely to be watched after `context` (up to the last two
+    // watches, oldest first), skipping anything in `exclude`. Without any
+    // supported transitions the list falls back to the most common next
+    // watches overall.
+    pub fn predict(&self, context: &[&str], count: usize, exclude: &HashSet<String>) -> Vec<(String, f64)> {
+        let Some(&last) = context.last() else {
+            return Vec::new();
+        };
+        
+        let mut remaining = 1.0;
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        let mut add = |distribution: Option<HashMap<String, f64>>, weight: f64, remaining: &mut f64| {
+            if let Some(distribution) = distribution {
+                for (target, probability) in distribution {
+                    *scores.entry(target).or_insert(0.0) += weight * *remaining * probability;
+                }
+                *remaining *= 1.0 - weight;
+            }
+        };
+        
+        if let [before, _] = context {
+            add(self.distribution(&self.second_order, &pair_key(before, last)), self.config.second_order_weight, &mut remaining);
+        }
+        add(self.distribution(&self.first_order, last), self.config.first_order_weight, &mut remaining);
+        
+        let top_next = self.top_next.read().unwrap();
+        if top_next.total > 0 {
+            for (video_id, count) in &top_next.videos {
+                *scores.entry(video_id.clone()).or_insert(0.0) += remaining * *count as f64 / top_next.total as f64;
+            }
+        }
+        drop(top_next);
+        
+        let mut scored: Vec<_> = scores.into_iter()
+            .filter(|(video_id, _)| !exclude.contains(video_id) && !context.contains(&video_id.as_str()))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(count);
+        scored
+    }
+    
+    pub fn stats(&self) -> serde_json::Value {
+        let count = |map: &ShardedMap<HashMap<String, u32>>| {
+            let mut supported = 0;
+            map.for_each(|_, targets| {
+                supported += targets.values().filter(|count| **count >= self.config.min_support).count();
+            });
+            supported
+        };
+        serde_json::json!({
+            "minSupport": self.config.min_support,
+            "firstOrderContexts": self.first_order.len(),
+            "secondOrderContexts": self.second_order.len(),
+            "supportedFirstOrder": count(&self.first_order),
+            "supportedSecondOrder": count(&self.second_order),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::*;
+    
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+    
+    fn watch(video_id: &str) -> WatchEvent {
+        WatchEvent {
+            video_id: video_id.to_string(),
+            timestamp: Utc::now(),
+            watch_duration: std::time::Duration::from_secs(60),
+            video_duration: std::time::Duration::from_secs(600),
+            interactions: Vec::new(),
+        }
+    }
+    
+    fn record(model: &SequenceModel, history: &[&str]) {
+        let history: Vec<WatchEvent> = history.iter().map(|video_id| watch(video_id)).collect();
+        for index in 1..history.len() {
+            model.record_watch(&history[index.saturating_sub(2)..index], &history[index]);
+        }
+    }
+    
+    #[test]
+    fn unsupported_contexts_fall_back_to_common_next_watches() {
+        let model = SequenceModel::new(SequenceConfig::default());
+        record(&model, &["a", "c"]);
+        record(&model, &["a", "c"]);
+        record(&model, &["a", "b"]);
+        record(&model, &["x", "b"]);
+        
+        // "a then b" was seen once, below the minimum support of two, so only
+        // "a then c" counts at first order and the rest goes to the fallback.
+        let scores: HashMap<String, f64> = model.predict(&["a"], 10, &HashSet::new()).into_iter().collect();
+        assert_close(scores["c"], 0.4 + 0.6 * 2.0 / 4.0);
+        assert_close(scores["b"], 0.6 * 2.0 / 4.0);
+        
+        // Nothing supported leaves "x": the fallback gets all the weight.
+        let scores: HashMap<String, f64> = model.predict(&["y", "x"], 10, &HashSet::new()).into_iter().collect();
+        assert_close(scores["c"], 0.5);
+        assert_close(scores["b"], 0.5);
+        
+        let excluded = HashSet::from(["c".to_string()]);
+        assert_eq!(model.predict(&["x"], 10, &excluded), vec![("b".to_string(), 0.5)]);
+    }
+    
+    #[test]
+    fn top_next_watches_stay_exact() {
+        let mut top = TopNext::default();
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for i in 0..(TOP_NEXT * 3) {
+            // Later videos are watched more often and must displace earlier ones.
+            let video_id = format!("video{}", i % (TOP_NEXT + 50));
+            for _ in 0..=(i / TOP_NEXT) {
+                let count = counts.entry(video_id.clone()).or_insert(0);
+                *count += 1;
+                top.record(&video_id, *count);
+            }
+        }
+        
+        let mut expected: Vec<u32> = counts.values().copied().collect();
+        expected.sort_by(|a, b| b.cmp(a));
+        let mut kept: Vec<u32> = top.videos.iter().map(|(_, count)| *count).collect();
+        kept.sort_by(|a, b| b.cmp(a));
+        assert_eq!(kept, expected[..TOP_NEXT]);
+        assert_eq!(top.total, counts.values().map(|count| *count as u64).sum::<u64>());
+        assert!(top.videos.iter().all(|(video_id, count)| counts[video_id] == *count));
+    }
+}
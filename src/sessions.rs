@@ -0,0 +1,228 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::sharded::ShardedMap;
+use crate::storage::Storage;
+use crate::{User, WatchEvent};
+
+pub const SESSION_HEADER: &str = "X-Session-Id";
+
+#[derive(Clone, Debug)]
+pub struct SessionConfig {
+    pub gap: Duration,
+    pub decay: f64,
+    pub max_context: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            gap: Duration::minutes(30),
+            decay: 0.5,
+            max_context: 5,
+        }
+    }
+}
+
+// A run of watches with no pause longer than the inactivity gap. The id is
+// derived from the user and the first watch, so it stays the same for as
+// long as the session keeps growing.
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub video_ids: Vec<String>,
+}
+
+fn breaks_session(previous: &WatchEvent, next: &WatchEvent, gap: Duration) -> bool {
+    next.timestamp < previous.timestamp || next.timestamp - previous.timestamp > gap
+}
+
+pub fn split_sessions(user: &User, gap: Duration) -> Vec<Session> {
+    let mut sessions: Vec<Session> = Vec::new();
+    let mut previous: Option<&WatchEvent> = None;
+    
+    for watch in &user.watch_history {
+        match (previous, sessions.last_mut()) {
+            (Some(previous), Some(session)) if !breaks_session(previous, watch, gap) => {
+                session.end = watch.timestamp;
+                session.video_ids.push(watch.video_id.clone());
+            }
+            _ => sessions.push(Session {
+                id: format!("{}:{}", user.id, watch.timestamp.timestamp_millis()),
+                start: watch.timestamp,
+                end: watch.timestamp,
+                video_ids: vec![watch.video_id.clone()],
+            }),
+        }
+        previous = Some(watch);
+    }
+    sessions
+}
+
+// Item-to-item transition counts between consecutive watches of the same
+// session, pooled over all users. A recommendation follows the transitions
+// out of the session's recent videos, with the latest weighted most, so it
+// tracks what the user is in the middle of rather than their whole history.
+pub struct SessionModel {
+    config: SessionConfig,
+    transitions: ShardedMap<HashMap<String, u32>>,
+}
+
+impl SessionModel {
+    pub fn new(config: SessionConfig) -> Self {
+        SessionModel {
+            config,
+            transitions: ShardedMap::default(),
+        }
+    }
+    
+    // Counts the transitions already in storage. Called once on recovery,
+    // before logged events are replayed through `record_watch`.
+    pub fn load_history(&self, storage: &dyn Storage) -> io::Result<()> {
+        storage.for_each_user(&mut |user| {
+            for pair in user.watch_history.windows(2) {
+                self.record_watch(&pair[0], &pair[1]);
+            }
+        })
+    }
+    
+    pub fn record_watch(&self, previous: &WatchEvent, next: &WatchEvent) {
+        if previous.video_id == next.video_id || breaks_session(previous, next, self.config.gap) {
+            return;
+        }
+        self.transitions.upsert(&previous.video_id, |targets| {
+            *targets.entry(next.video_id.clone()).or_insert(0) += 1;
+        });
+    }
+    
+    // The named session if the user has one with that id, otherwise the
+    // user's latest session while it is still open.
+    pub fn session_for(&self, user: &User, session_id: Option<&str>, now: DateTime<Utc>) -> Option<Session> {
+        let sessions = split_sessions(user, self.config.gap);
+        match session_id {
+            Some(session_id) => sessions.into_iter().find(|session| session.id == session_id),
+            None => sessions.into_iter().last().filter(|session| now - session.end <= self.config.gap),
+        }
+    }
+    
+    pub fn recommend(&self, session: &Session, count: usize) -> Vec<(String, f64)> {
+        let seen: HashSet<&String> = session.video_ids.iter().collect();
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        let mut weight = 1.0;
+        
+        for video_id in session.video_ids.iter().rev().take(self.config.max_context) {
+            self.transitions.read(video_id, |targets| {
+                let Some(targets) = targets else {
+                    return;
+                };
+                let total: u32 = targets.values().sum();
+                for (target, transitions) in targets {
+                    if !seen.contains(target) {
+                        *scores.entry(target.clone()).or_insert(0.0) += weight * *transitions as f64 / total as f64;
+                    }
+                }
+            });
+            weight *= self.config.decay;
+        }
+        
+        let mut scored: Vec<_> = scores.into_iter().collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(count);
+        scored
+    }
+    
+    pub fn stats(&self) -> serde_json::Value {
+        let mut transitions = 0u64;
+        self.transitions.for_each(|_, targets| {
+            transitions += targets.values().map(|count| *count as u64).sum::<u64>();
+        });
+        serde_json::json!({
+            "gapMinutes": self.config.gap.num_minutes(),
+            "videosWithTransitions": self.transitions.len(),
+            "transitions": transitions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Tz;
+    use super::*;
+    use crate::negative::NegativeFeedback;
+    
+    fn watch(video_id: &str, minute: i64) -> WatchEvent {
+        WatchEvent {
+            video_id: video_id.to_string(),
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap() + Duration::minutes(minute),
+            watch_duration: std::time::Duration::from_secs(60),
+            video_duration: std::time::Duration::from_secs(600),
+            interactions: Vec::new(),
+        }
+    }
+    
+    fn user(watch_history: Vec<WatchEvent>) -> User {
+        User {
+            id: "user1".to_string(),
+            subscriptions: HashSet::new(),
+            watch_history,
+            content_preferences: HashMap::new(),
+            interaction_patterns: Default::default(),
+            timezone: Tz::UTC,
+            preferences_updated_at: None,
+            short_term_preferences: HashMap::new(),
+            short_term_updated_at: None,
+            negative_feedback: NegativeFeedback::default(),
+        }
+    }
+    
+    #[test]
+    fn sessions_split_at_the_inactivity_gap() {
+        // A pause of exactly the gap continues the session; one minute more
+        // starts a new one, as does a watch logged out of order.
+        let user = user(vec![
+            watch("a", 0),
+            watch("b", 30),
+            watch("c", 61),
+            watch("d", 70),
+            watch("e", 65),
+        ]);
+        let sessions = split_sessions(&user, Duration::minutes(30));
+        
+        let videos: Vec<Vec<&str>> = sessions.iter()
+            .map(|session| session.video_ids.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(videos, vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+        assert_eq!(sessions[0].end, user.watch_history[1].timestamp);
+        assert_eq!(sessions[1].id, format!("user1:{}", user.watch_history[2].timestamp.timestamp_millis()));
+        
+        let model = SessionModel::new(SessionConfig::default());
+        let last = user.watch_history[4].timestamp;
+        assert!(model.session_for(&user, None, last + Duration::minutes(30)).is_some());
+        assert!(model.session_for(&user, None, last + Duration::minutes(31)).is_none());
+        assert_eq!(model.session_for(&user, Some(&sessions[0].id), last).unwrap().video_ids, vec!["a", "b"]);
+    }
+    
+    #[test]
+    fn transitions_stay_within_sessions() {
+        let model = SessionModel::new(SessionConfig::default());
+        model.record_watch(&watch("a", 0), &watch("b", 10));
+        model.record_watch(&watch("a", 0), &watch("c", 10));
+        model.record_watch(&watch("a", 0), &watch("c", 10));
+        model.record_watch(&watch("a", 0), &watch("d", 45));
+        
+        let session = Session {
+            id: "user1:0".to_string(),
+            start: watch("a", 0).timestamp,
+            end: watch("a", 0).timestamp,
+            video_ids: vec!["a".to_string()],
+        };
+        let recommendations = model.recommend(&session, 10);
+        assert_eq!(recommendations.len(), 2);
+        assert_eq!(recommendations[0].0, "c");
+        assert!((recommendations[0].1 - 2.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::RwLock;
+
+const SHARD_COUNT: usize = 16;
+
+// String-keyed map split across independently locked shards, so a writer only
+// blocks readers of keys that hash to the same shard. No method holds more
+// than one shard lock at a time, and callbacks must not re-enter the map.
+// Readers take the shard's read lock rather than a lock-free snapshot, and a
+// reader touching several keys may see some of an event's writes but not
+// others.
+pub struct ShardedMap<V> {
+    shards: Vec<RwLock<HashMap<String, V>>>,
+}
+
+impl<V> Default for ShardedMap<V> {
+    fn default() -> Self {
+        ShardedMap {
+            shards: (0..SHARD_COUNT).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+}
+
+impl<V> ShardedMap<V> {
+    fn shard(&self, key: &str) -> &RwLock<HashMap<String, V>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+    
+    pub fn read<R>(&self, key: &str, f: impl FnOnce(Option<&V>) -> R) -> R {
+        f(self.shard(key).read().unwrap().get(key))
+    }
+    
+    pub fn insert(&self, key: String, value: V) {
+        self.shard(&key).write().unwrap().insert(key, value);
+    }
+    
+    pub fn remove(&self, key: &str) -> Option<V> {
+        self.shard(key).write().unwrap().remove(key)
+    }
+    
+    pub fn update<R>(&self, key: &str, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        self.shard(key).write().unwrap().get_mut(key).map(f)
+    }
+    
+    pub fn upsert<R>(&self, key: &str, f: impl FnOnce(&mut V) -> R) -> R
+    where
+        V: Default,
+    {
+        let mut shard = self.shard(key).write().unwrap();
+        f(shard.entry(key.to_string()).or_default())
+    }
+    
+    pub fn keys(&self) -> Vec<String> {
+        self.shards.iter()
+            .flat_map(|shard| shard.read().unwrap().keys().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+    
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.read().unwrap().is_empty())
+    }
+    
+    pub fn for_each(&self, mut f: impl FnMut(&str, &V)) {
+        for shard in &self.shards {
+            for (key, value) in shard.read().unwrap().iter() {
+                f(key, value);
+            }
+        }
+    }
+    
+    pub fn for_each_mut(&self, mut f: impl FnMut(&str, &mut V)) {
+        for shard in &self.shards {
+            for (key, value) in shard.write().unwrap().iter_mut() {
+                f(key, value);
+            }
+        }
+    }
+}
+
+impl<V: Clone> ShardedMap<V> {
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.read(key, |value| value.cloned())
+    }
+}
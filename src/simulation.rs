@@ -0,0 +1,420 @@
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::contextual::{RequestContext, Surface};
+use crate::event_log::EngineEvent;
+use crate::negative::NegativeFeedback;
+use crate::patterns::EventCounts;
+use crate::randomness::Jitter;
+use crate::temporal::TemporalProfile;
+use crate::{InteractionPatterns, RecommendationEngine, User, Video};
+
+const RECOMMENDATIONS_PER_SESSION: usize = 10;
+const USER_PREFIX: &str = "sim-";
+pub const MAX_USERS: usize = 10_000;
+pub const MAX_DAYS: usize = 365;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Intensity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Intensity {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "low" => Some(Intensity::Low),
+            "medium" => Some(Intensity::Medium),
+            "high" => Some(Intensity::High),
+            _ => None,
+        }
+    }
+    
+    fn sessions_per_day(self) -> usize {
+        match self {
+            Intensity::Low => 1,
+            Intensity::Medium => 3,
+            Intensity::High => 6,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    pub user_count: usize,
+    pub days: usize,
+    pub intensity: Intensity,
+}
+
+#[derive(Debug)]
+pub enum StartError {
+    AlreadyRunning,
+    EmptyCatalog,
+    InvalidConfig(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for StartError {
+    fn from(error: io::Error) -> Self {
+        StartError::Io(error)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct SimulationTotals {
+    sessions: u64,
+    impressions: u64,
+    views: u64,
+    likes: u64,
+    dislikes: u64,
+    comments: u64,
+    shares: u64,
+    subscribes: u64,
+    watch_seconds: f64,
+}
+
+enum RunState {
+    Running,
+    Completed,
+    Failed(String),
+}
+
+pub struct SimulationRun {
+    config: SimulationConfig,
+    total_steps: u64,
+    completed_steps: AtomicU64,
+    state: Mutex<RunState>,
+    totals: Mutex<SimulationTotals>,
+}
+
+impl SimulationRun {
+    fn progress(&self) -> f64 {
+        if self.total_steps == 0 {
+            return 100.0;
+        }
+        self.completed_steps.load(Ordering::Relaxed) as f64 * 100.0 / self.total_steps as f64
+    }
+}
+
+// A synthetic viewer whose tastes are hidden from the engine; it only reveals
+// them through the interactions it chooses to make.
+struct SimulatedUser {
+    id: String,
+    tastes: HashMap<String, f64>,
+    active_hour: u32,
+    subscriptions: HashSet<String>,
+}
+
+impl SimulatedUser {
+    fn affinity(&self, video: &Video) -> f64 {
+        video.categories.iter()
+            .filter_map(|category| self.tastes.get(category))
+            .fold(0.0, |best: f64, &taste| best.max(taste))
+    }
+}
+
+// Simulated users are recognised by their id, which keeps their traffic out of
+// experiments, the impression log and the bandits.
+pub fn is_simulated(user_id: &str) -> bool {
+    user_id.starts_with(USER_PREFIX)
+}
+
+#[derive(Default)]
+pub struct Simulator {
+    current: Mutex<Option<Arc<SimulationRun>>>,
+}
+
+impl Simulator {
+    pub fn start(&self, engine: Arc<RecommendationEngine>, config: SimulationConfig) -> Result<(), StartError> {
+        if !(1..=MAX_USERS).contains(&config.user_count) {
+            return Err(StartError::InvalidConfig(format!("user_count must be between 1 and {}", MAX_USERS)));
+        }
+        if !(1..=MAX_DAYS).contains(&config.days) {
+            return Err(StartError::InvalidConfig(format!("days must be between 1 and {}", MAX_DAYS)));
+        }
+        
+        let mut current = self.current.lock().unwrap();
+        if let Some(run) = current.as_ref()
+            && matches!(*run.state.lock().unwrap(), RunState::Running)
+        {
+            return Err(StartError::AlreadyRunning);
+        }
+        if engine.storage.video_count()? == 0 {
+            return Err(StartError::EmptyCatalog);
+        }
+        
+        let run = Arc::new(SimulationRun {
+            total_steps: (config.user_count * config.days) as u64,
+            config,
+            completed_steps: AtomicU64::new(0),
+            state: Mutex::new(RunState::Running),
+            totals: Mutex::new(SimulationTotals::default()),
+        });
+        *current = Some(run.clone());
+        
+        // A panicking run still ends as failed, so it cannot block later runs.
+        std::thread::spawn(move || {
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| simulate(&engine, &run)))
+                .unwrap_or_else(|payload| Err(io::Error::other(panic_message(&*payload))));
+            *run.state.lock().unwrap() = match outcome {
+                Ok(()) => RunState::Completed,
+                Err(e) => {
+                    eprintln!("Simulation failed: {}", e);
+                    RunState::Failed(e.to_string())
+                }
+            };
+        });
+        
+        Ok(())
+    }
+    
+    pub fn status(&self) -> serde_json::Value {
+        let current = self.current.lock().unwrap();
+        let Some(run) = current.as_ref() else {
+            return serde_json::json!({"status": "idle", "progress": 0});
+        };
+        
+        match &*run.state.lock().unwrap() {
+            RunState::Running => serde_json::json!({"status": "running", "progress": run.progress()}),
+            RunState::Completed => serde_json::json!({"status": "completed", "progress": 100}),
+            RunState::Failed(message) => serde_json::json!({
+                "status": "failed",
+                "progress": run.progress(),
+                "message": message
+            }),
+        }
+    }
+    
+    // Results are aggregated as the run progresses, so they can be read while
+    // it is still going.
+    pub fn results(&self) -> Option<serde_json::Value> {
+        let run = self.current.lock().unwrap().clone()?;
+        let totals = run.totals.lock().unwrap().clone();
+        
+        let ratio = |numerator: f64, denominator: u64| {
+            if denominator > 0 { numerator / denominator as f64 } else { 0.0 }
+        };
+        
+        Some(serde_json::json!({
+            "userCount": run.config.user_count,
+            "days": run.config.days,
+            "intensity": run.config.intensity,
+            "progress": run.progress(),
+            "totalInteractions": totals.views + totals.likes + totals.dislikes
+                + totals.comments + totals.shares + totals.subscribes,
+            "metrics": {
+                "sessions": totals.sessions,
+                "impressions": totals.impressions,
+                "views": totals.views,
+                "likes": totals.likes,
+                "dislikes": totals.dislikes,
+                "comments": totals.comments,
+                "shares": totals.shares,
+                "subscribes": totals.subscribes,
+                "ctr": ratio(totals.views as f64, totals.impressions),
+                "avgWatchTime": ratio(totals.watch_seconds, totals.views).round(),
+                "engagementRate": ratio((totals.likes + totals.comments + totals.shares) as f64, totals.views)
+            }
+        }))
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    format!("simulation panicked: {}", message)
+}
+
+fn simulate(engine: &RecommendationEngine, run: &SimulationRun) -> io::Result<()> {
+    let config = &run.config;
+    
+    let mut categories = HashSet::new();
+    engine.storage.for_each_video(&mut |video| {
+        categories.extend(video.categories.iter().cloned());
+    })?;
+    
+    // Simulated days end at the current time, so recency-based sources see the
+    // generated history as recent activity.
+    let now = Utc::now();
+    let start = chrono::Duration::try_days(config.days as i64)
+        .and_then(|days| now.checked_sub_signed(days))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "simulated period is out of range"))?;
+    let run_id = now.timestamp();
+    let mut rng = engine.config.rng.jitter("simulation", &run_id.to_string(), now);
+    
+    let mut users: Vec<SimulatedUser> = (0..config.user_count).map(|i| SimulatedUser {
+        id: format!("{}{}-user{}", USER_PREFIX, run_id, i),
+        // Squaring skews tastes so most viewers care strongly about a few categories.
+        tastes: categories.iter().map(|category| (category.clone(), rng.unit().powi(2))).collect(),
+        active_hour: rng.index(24) as u32,
+        subscriptions: HashSet::new(),
+    }).collect();
+    
+    for user in &users {
+        engine.record(EngineEvent::CreateUser {
+            user: Box::new(new_user(&user.id)),
+            timestamp: start,
+        })?;
+    }
+    
+    for day in 0..config.days {
+        for user in &mut users {
+            for _ in 0..config.intensity.sessions_per_day() {
+                let hour = (user.active_hour + rng.index(3) as u32) % 24;
+                let session_start = start
+                    + chrono::Duration::days(day as i64)
+                    + chrono::Duration::hours(hour as i64)
+                    + chrono::Duration::minutes(rng.index(60) as i64);
+                
+                run_session(engine, run, user, session_start, &mut rng)?;
+            }
+            run.completed_steps.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    Ok(())
+}
+
+fn run_session(
+    engine: &RecommendationEngine,
+    run: &SimulationRun,
+    user: &mut SimulatedUser,
+    mut timestamp: DateTime<Utc>,
+    rng: &mut Jitter,
+) -> io::Result<()> {
+    let context = RequestContext {
+        local_hour: Some(timestamp.hour() as u8),
+        surface: Some(Surface::Home),
+        ..RequestContext::default()
+    };
+    let recommendations = engine.simulated_recommendations(&user.id, RECOMMENDATIONS_PER_SESSION, &context, timestamp)?;
+    let mut totals = SimulationTotals {
+        sessions: 1,
+        impressions: recommendations.len() as u64,
+        ..SimulationTotals::default()
+    };
+    
+    for video in &recommendations {
+        let affinity = user.affinity(video);
+        if rng.unit() >= 0.05 + 0.5 * affinity {
+            continue;
+        }
+        
+        let watch_fraction = (affinity * 0.8 + rng.between(-0.15, 0.3)).clamp(0.05, 1.0);
+        let watch_duration = video.duration.mul_f64(watch_fraction);
+        engine.record(EngineEvent::Watch {
+            user_id: user.id.clone(),
+            video_id: video.id.clone(),
+            watch_duration,
+            interactions: Vec::new(),
+            timestamp,
+        })?;
+        totals.views += 1;
+        totals.watch_seconds += watch_duration.as_secs_f64();
+        timestamp += chrono::Duration::from_std(watch_duration).unwrap_or_default();
+        
+        if rng.unit() < 0.3 * affinity {
+            record_like(engine, user, video, true, timestamp)?;
+            totals.likes += 1;
+        } else if affinity < 0.3 && rng.unit() < 0.1 {
+            record_like(engine, user, video, false, timestamp)?;
+            totals.dislikes += 1;
+        }
+        
+        if rng.unit() < 0.05 * affinity {
+            let text = if affinity > 0.5 { "great video, love it" } else { "not really for me, boring" };
+            engine.record(EngineEvent::Comment {
+                comment_id: format!("c-{}-{}-{}", video.id, user.id, timestamp.timestamp()),
+                user_id: user.id.clone(),
+                video_id: video.id.clone(),
+                text: text.to_string(),
+                timestamp,
+            })?;
+            totals.comments += 1;
+        }
+        
+        if rng.unit() < 0.03 * affinity {
+            engine.record(EngineEvent::Share {
+                user_id: user.id.clone(),
+                video_id: video.id.clone(),
+                timestamp,
+            })?;
+            totals.shares += 1;
+        }
+        
+        if !user.subscriptions.contains(&video.channel_id) && rng.unit() < 0.05 * affinity {
+            engine.record(EngineEvent::Subscribe {
+                user_id: user.id.clone(),
+                channel_id: video.channel_id.clone(),
+                timestamp,
+            })?;
+            user.subscriptions.insert(video.channel_id.clone());
+            totals.subscribes += 1;
+        }
+        
+        timestamp += chrono::Duration::seconds(5 + rng.index(55) as i64);
+    }
+    
+    let mut run_totals = run.totals.lock().unwrap();
+    run_totals.sessions += totals.sessions;
+    run_totals.impressions += totals.impressions;
+    run_totals.views += totals.views;
+    run_totals.likes += totals.likes;
+    run_totals.dislikes += totals.dislikes;
+    run_totals.comments += totals.comments;
+    run_totals.shares += totals.shares;
+    run_totals.subscribes += totals.subscribes;
+    run_totals.watch_seconds += totals.watch_seconds;
+    
+    Ok(())
+}
+
+fn record_like(
+    engine: &RecommendationEngine,
+    user: &SimulatedUser,
+    video: &Video,
+    is_like: bool,
+    timestamp: DateTime<Utc>,
+) -> io::Result<()> {
+    engine.record(EngineEvent::Like {
+        user_id: user.id.clone(),
+        video_id: video.id.clone(),
+        is_like,
+        timestamp,
+    })
+}
+
+// Simulated users start with no known preferences, like a real sign-up.
+fn new_user(id: &str) -> User {
+    User {
+        id: id.to_string(),
+        subscriptions: HashSet::new(),
+        watch_history: Vec::new(),
+        timezone: Tz::UTC,
+        preferences_updated_at: None,
+        short_term_preferences: HashMap::new(),
+        short_term_updated_at: None,
+        negative_feedback: NegativeFeedback::default(),
+        content_preferences: HashMap::new(),
+        interaction_patterns: InteractionPatterns {
+            avg_watch_percentage: 0.0,
+            avg_comment_length: 0,
+            time_of_day_preferences: HashMap::new(),
+            day_of_week_preferences: HashMap::new(),
+            temporal_profile: TemporalProfile::default(),
+            counts: EventCounts::default(),
+            like_to_view_ratio: 0.0,
+            share_frequency: 0.0,
+        },
+    }
+}
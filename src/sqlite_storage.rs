@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+use chrono::DateTime;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::storage::{Rating, Storage};
+use crate::{Comment, User, Video};
+
+// Applied in order; `PRAGMA user_version` records how many have run.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE users (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE videos (
+        id TEXT PRIMARY KEY,
+        channel_id TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE comments (
+        id TEXT PRIMARY KEY,
+        video_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE ratings (
+        user_id TEXT NOT NULL,
+        video_id TEXT NOT NULL,
+        rating REAL NOT NULL,
+        PRIMARY KEY (user_id, video_id)
+    );
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );",
+    "CREATE INDEX idx_videos_channel ON videos (channel_id);
+    CREATE INDEX idx_comments_video ON comments (video_id);
+    CREATE INDEX idx_ratings_video ON ratings (video_id);",
+    "ALTER TABLE ratings ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+    UPDATE ratings SET updated_at = CAST(strftime('%s', 'now') AS INTEGER) * 1000;",
+    "ALTER TABLE ratings ADD COLUMN confidence REAL NOT NULL DEFAULT 1.0;",
+];
+
+// Writes go through their own connection, so an event's open transaction is
+// only visible to the thread applying it. Every other reader uses the reader
+// connection and sees committed data only.
+pub struct SqliteStorage {
+    reader: Mutex<Connection>,
+    writer: Mutex<Connection>,
+    // The thread applying the current event, which reads its own writes.
+    event_thread: Mutex<Option<ThreadId>>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let mut writer = Connection::open(path)?;
+        writer.pragma_update(None, "journal_mode", "WAL")?;
+        // Checkpoints drop logged events once SQLite has committed them, so
+        // every commit has to be durable on its own.
+        writer.pragma_update(None, "synchronous", "FULL")?;
+        migrate(&mut writer)?;
+        
+        let reader = Connection::open(path)?;
+        reader.pragma_update(None, "query_only", true)?;
+        
+        Ok(SqliteStorage {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+            event_thread: Mutex::new(None),
+        })
+    }
+    
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        if *self.event_thread.lock().unwrap() == Some(thread::current().id()) {
+            self.writer.lock().unwrap()
+        } else {
+            self.reader.lock().unwrap()
+        }
+    }
+    
+    fn get<T: DeserializeOwned>(&self, sql: &str, id: &str) -> io::Result<Option<T>> {
+        let conn = self.conn();
+        let data: Option<String> = conn.query_row(sql, [id], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?;
+        data.map(|data| decode(&data)).transpose()
+    }
+    
+    fn for_each<T: DeserializeOwned>(&self, sql: &str, f: &mut dyn FnMut(&T)) -> io::Result<()> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached(sql).map_err(io::Error::other)?;
+        let mut rows = stmt.query([]).map_err(io::Error::other)?;
+        while let Some(row) = rows.next().map_err(io::Error::other)? {
+            let data: String = row.get(0).map_err(io::Error::other)?;
+            f(&decode(&data)?);
+        }
+        Ok(())
+    }
+    
+    fn ids(&self, sql: &str) -> io::Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached(sql).map_err(io::Error::other)?;
+        stmt.query_map([], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(io::Error::other)
+    }
+    
+    fn count(&self, sql: &str) -> io::Result<usize> {
+        let conn = self.conn();
+        let count: i64 = conn.query_row(sql, [], |row| row.get(0)).map_err(io::Error::other)?;
+        Ok(count as usize)
+    }
+    
+    fn execute(&self, sql: &str, params: impl rusqlite::Params) -> io::Result<()> {
+        let conn = self.writer.lock().unwrap();
+        conn.execute(sql, params).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn rating(value: f64, confidence: f64, updated_at: i64) -> Rating {
+    Rating {
+        value,
+        confidence,
+        updated_at: DateTime::from_timestamp_millis(updated_at).unwrap_or_default(),
+    }
+}
+
+fn user_rating(row: &Row) -> rusqlite::Result<(String, String, Rating)> {
+    Ok((row.get(0)?, row.get(1)?, rating(row.get(2)?, row.get(3)?, row.get(4)?)))
+}
+
+fn encode<T: Serialize>(value: &T) -> io::Result<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+fn decode<T: DeserializeOwned>(data: &str) -> io::Result<T> {
+    Ok(serde_json::from_str(data)?)
+}
+
+impl Storage for SqliteStorage {
+    fn get_user(&self, user_id: &str) -> io::Result<Option<User>> {
+        self.get("SELECT data FROM users WHERE id = ?1", user_id)
+    }
+    
+    fn put_user(&self, user: User) -> io::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO users (id, data) VALUES (?1, ?2)",
+            params![user.id, encode(&user)?],
+        )
+    }
+    
+    fn update_user(&self, user_id: &str, f: &mut dyn FnMut(&mut User)) -> io::Result<bool> {
+        match self.get_user(user_id)? {
+            Some(mut user) => {
+                f(&mut user);
+                self.put_user(user)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    
+    fn user_ids(&self) -> io::Result<Vec<String>> {
+        self.ids("SELECT id FROM users")
+    }
+    
+    fn user_count(&self) -> io::Result<usize> {
+        self.count("SELECT COUNT(*) FROM users")
+    }
+    
+    fn for_each_user(&self, f: &mut dyn FnMut(&User)) -> io::Result<()> {
+        self.for_each("SELECT data FROM users", f)
+    }
+    
+    fn get_video(&self, video_id: &str) -> io::Result<Option<Video>> {
+        self.get("SELECT data FROM videos WHERE id = ?1", video_id)
+    }
+    
+    fn put_video(&self, video: Video) -> io::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO videos (id, channel_id, data) VALUES (?1, ?2, ?3)",
+            params![video.id, video.channel_id, encode(&video)?],
+        )
+    }
+    
+    fn update_video(&self, video_id: &str, f: &mut dyn FnMut(&mut Video)) -> io::Result<bool> {
+        match self.get_video(video_id)? {
+            Some(mut video) => {
+                f(&mut video);
+                self.put_video(video)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    
+    fn video_ids(&self) -> io::Result<Vec<String>> {
+        self.ids("SELECT id FROM videos")
+    }
+    
+    fn video_count(&self) -> io::Result<usize> {
+        self.count("SELECT COUNT(*) FROM videos")
+    }
+    
+    fn for_each_video(&self, f: &mut dyn FnMut(&Video)) -> io::Result<()> {
+        self.for_each("SELECT data FROM videos", f)
+    }
+    
+    fn channel_video_ids(&self, channel_id: &str) -> io::Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached("SELECT id FROM videos WHERE channel_id = ?1").map_err(io::Error::other)?;
+        stmt.query_map([channel_id], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(io::Error::other)
+    }
+    
+    fn get_comment(&self, comment_id: &str) -> io::Result<Option<Comment>> {
+        self.get("SELECT data FROM comments WHERE id = ?1", comment_id)
+    }
+    
+    fn put_comment(&self, comment: Comment) -> io::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO comments (id, video_id, user_id, data) VALUES (?1, ?2, ?3, ?4)",
+            params![comment.id, comment.video_id, comment.user_id, encode(&comment)?],
+        )
+    }
+    
+    fn for_each_comment(&self, f: &mut dyn FnMut(&Comment)) -> io::Result<()> {
+        self.for_each("SELECT data FROM comments", f)
+    }
+    
+    fn user_ratings(&self, user_id: &str) -> io::Result<HashMap<String, Rating>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached("SELECT video_id, rating, confidence, updated_at FROM ratings WHERE user_id = ?1")
+            .map_err(io::Error::other)?;
+        stmt.query_map([user_id], |row| Ok((row.get(0)?, rating(row.get(1)?, row.get(2)?, row.get(3)?))))
+            .and_then(|rows| rows.collect())
+            .map_err(io::Error::other)
+    }
+    
+    fn get_rating(&self, user_id: &str, video_id: &str) -> io::Result<Option<Rating>> {
+        let conn = self.conn();
+        conn.query_row(
+            "SELECT rating, confidence, updated_at FROM ratings WHERE user_id = ?1 AND video_id = ?2",
+            [user_id, video_id],
+            |row| Ok(rating(row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+            .optional()
+            .map_err(io::Error::other)
+    }
+    
+    fn put_rating(&self, user_id: &str, video_id: &str, rating: Rating) -> io::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO ratings (user_id, video_id, rating, confidence, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user_id, video_id, rating.value, rating.confidence, rating.updated_at.timestamp_millis()],
+        )
+    }
+    
+    fn for_each_user_ratings(&self, f: &mut dyn FnMut(&str, &HashMap<String, Rating>)) -> io::Result<()> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached("SELECT user_id, video_id, rating, confidence, updated_at FROM ratings ORDER BY user_id")
+            .map_err(io::Error::other)?;
+        let mut rows = stmt.query([]).map_err(io::Error::other)?;
+        
+        let mut current: Option<String> = None;
+        let mut ratings = HashMap::new();
+        while let Some(row) = rows.next().map_err(io::Error::other)? {
+            let (user_id, video_id, rating) = user_rating(row).map_err(io::Error::other)?;
+            if current.as_deref() != Some(user_id.as_str()) {
+                if let Some(previous) = current.take() {
+                    f(&previous, &ratings);
+                    ratings.clear();
+                }
+                current = Some(user_id);
+            }
+            ratings.insert(video_id, rating);
+        }
+        if let Some(previous) = current {
+            f(&previous, &ratings);
+        }
+        Ok(())
+    }
+    
+    fn begin_event(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().execute_batch("BEGIN IMMEDIATE").map_err(io::Error::other)?;
+        *self.event_thread.lock().unwrap() = Some(thread::current().id());
+        Ok(())
+    }
+    
+    fn commit_event(&self, seq: u64) -> io::Result<()> {
+        let conn = self.writer.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('committed_seq', ?1)",
+            [seq.to_string()],
+        ).map_err(io::Error::other)?;
+        conn.execute_batch("COMMIT").map_err(io::Error::other)?;
+        *self.event_thread.lock().unwrap() = None;
+        Ok(())
+    }
+    
+    fn rollback_event(&self) {
+        *self.event_thread.lock().unwrap() = None;
+        let conn = self.writer.lock().unwrap();
+        if !conn.is_autocommit()
+            && let Err(e) = conn.execute_batch("ROLLBACK")
+        {
+            eprintln!("Failed to roll back event: {}", e);
+        }
+    }
+    
+    fn committed_seq(&self) -> io::Result<Option<u64>> {
+        let conn = self.conn();
+        let value: Option<String> = conn.query_row("SELECT value FROM meta WHERE key = 'committed_seq'", [], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?;
+        Ok(value.and_then(|value| value.parse().ok()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use chrono::Utc;
+    use super::*;
+    
+    #[test]
+    fn readers_only_see_committed_events() {
+        let dir = std::env::temp_dir().join(format!("recommend-sqlite-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = Arc::new(SqliteStorage::open(&dir.join("test.db")).unwrap());
+        let rating = Rating { value: 1.0, confidence: 1.0, updated_at: Utc::now() };
+        
+        storage.begin_event().unwrap();
+        storage.put_rating("user1", "video1", rating).unwrap();
+        // The thread applying the event reads its own writes; others do not.
+        assert!(storage.get_rating("user1", "video1").unwrap().is_some());
+        let reader = Arc::clone(&storage);
+        let seen = thread::spawn(move || reader.get_rating("user1", "video1").unwrap()).join().unwrap();
+        assert!(seen.is_none());
+        storage.commit_event(1).unwrap();
+        
+        let reader = Arc::clone(&storage);
+        let seen = thread::spawn(move || reader.get_rating("user1", "video1").unwrap()).join().unwrap();
+        assert!(seen.is_some());
+        assert_eq!(storage.committed_seq().unwrap(), Some(1));
+        
+        storage.begin_event().unwrap();
+        storage.put_rating("user1", "video2", rating).unwrap();
+        storage.rollback_event();
+        assert!(storage.get_rating("user1", "video2").unwrap().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::sharded::ShardedMap;
+use crate::{Comment, User, Video};
+
+// A user's accumulated feedback on a video as of its last update: the sum of
+// each event's preference weighted by its confidence, and the sum of those
+// confidences. Readers decay both to the time they read them, see
+// `DecayConfig`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(from = "StoredRating")]
+pub struct Rating {
+    pub value: f64,
+    pub confidence: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Rating {
+    pub fn preference(&self) -> f64 {
+        if self.confidence > 0.0 {
+            (self.value / self.confidence).clamp(-1.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Snapshots written before ratings had timestamps hold bare numbers, which
+// start decaying from when they are loaded. Ratings from before confidences
+// were kept count as a single event.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredRating {
+    Timestamped {
+        value: f64,
+        #[serde(default = "legacy_confidence")]
+        confidence: f64,
+        updated_at: DateTime<Utc>,
+    },
+    Plain(f64),
+}
+
+fn legacy_confidence() -> f64 {
+    1.0
+}
+
+impl From<StoredRating> for Rating {
+    fn from(stored: StoredRating) -> Self {
+        match stored {
+            StoredRating::Timestamped { value, confidence, updated_at } => Rating { value, confidence, updated_at },
+            StoredRating::Plain(value) => Rating { value, confidence: legacy_confidence(), updated_at: Utc::now() },
+        }
+    }
+}
+
+// Callbacks passed to the `for_each_*` methods must not call back into the
+// storage, since backends may hold a lock or an open cursor while iterating.
+pub trait Storage: Send + Sync {
+    fn get_user(&self, user_id: &str) -> io::Result<Option<User>>;
+    fn put_user(&self, user: User) -> io::Result<()>;
+    fn update_user(&self, user_id: &str, f: &mut dyn FnMut(&mut User)) -> io::Result<bool>;
+    fn user_ids(&self) -> io::Result<Vec<String>>;
+    fn user_count(&self) -> io::Result<usize>;
+    fn for_each_user(&self, f: &mut dyn FnMut(&User)) -> io::Result<()>;
+    
+    fn get_video(&self, video_id: &str) -> io::Result<Option<Video>>;
+    fn put_video(&self, video: Video) -> io::Result<()>;
+    fn update_video(&self, video_id: &str, f: &mut dyn FnMut(&mut Video)) -> io::Result<bool>;
+    fn video_ids(&self) -> io::Result<Vec<String>>;
+    fn video_count(&self) -> io::Result<usize>;
+    fn for_each_video(&self, f: &mut dyn FnMut(&Video)) -> io::Result<()>;
+    // Looked up through an index, without scanning the catalog.
+    fn channel_video_ids(&self, channel_id: &str) -> io::Result<Vec<String>>;
+    
+    fn get_comment(&self, comment_id: &str) -> io::Result<Option<Comment>>;
+    fn put_comment(&self, comment: Comment) -> io::Result<()>;
+    fn for_each_comment(&self, f: &mut dyn FnMut(&Comment)) -> io::Result<()>;
+    
+    fn user_ratings(&self, user_id: &str) -> io::Result<HashMap<String, Rating>>;
+    fn get_rating(&self, user_id: &str, video_id: &str) -> io::Result<Option<Rating>>;
+    fn put_rating(&self, user_id: &str, video_id: &str, rating: Rating) -> io::Result<()>;
+    fn for_each_user_ratings(&self, f: &mut dyn FnMut(&str, &HashMap<String, Rating>)) -> io::Result<()>;
+    
+    // Every backend applies each logged event atomically, so an event that
+    // fails part way is rolled back. Persistent backends also remember the
+    // sequence number of the last one, so recovery can skip the snapshot and
+    // replay only what they have not seen yet.
+    fn begin_event(&self) -> io::Result<()>;
+    fn commit_event(&self, seq: u64) -> io::Result<()>;
+    fn rollback_event(&self);
+    fn committed_seq(&self) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+// What a write replaced, so a failed event can put it back.
+enum Undo {
+    User(String, Option<Box<User>>),
+    Video(String, Option<Video>),
+    Comment(String, Option<Comment>),
+    Rating(String, String, Option<Rating>),
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    users: ShardedMap<User>,
+    videos: ShardedMap<Video>,
+    // Video ids by channel, kept up to date by `put_video`.
+    channels: ShardedMap<Vec<String>>,
+    comments: ShardedMap<Comment>,
+    ratings: ShardedMap<HashMap<String, Rating>>,
+    // Undo records of the event each thread is applying. Writes from threads
+    // outside an event are not journaled.
+    journals: Mutex<HashMap<ThreadId, Vec<Undo>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    fn journal(&self, undo: impl FnOnce() -> Undo) {
+        if let Some(journal) = self.journals.lock().unwrap().get_mut(&thread::current().id()) {
+            journal.push(undo());
+        }
+    }
+    
+    fn set_video(&self, video_id: &str, video: Option<Video>) {
+        let previous = self.videos.read(video_id, |previous| previous.map(|previous| previous.channel_id.clone()));
+        let channel_id = video.as_ref().map(|video| video.channel_id.clone());
+        if previous != channel_id {
+            if let Some(previous) = previous {
+                self.channels.update(&previous, |ids| ids.retain(|id| id != video_id));
+            }
+            if let Some(channel_id) = channel_id {
+                self.channels.upsert(&channel_id, |ids| ids.push(video_id.to_string()));
+            }
+        }
+        match video {
+            Some(video) => self.videos.insert(video_id.to_string(), video),
+            None => {
+                self.videos.remove(video_id);
+            }
+        }
+    }
+    
+    fn restore(&self, undo: Undo) {
+        match undo {
+            Undo::User(user_id, Some(user)) => self.users.insert(user_id, *user),
+            Undo::User(user_id, None) => {
+                self.users.remove(&user_id);
+            }
+            Undo::Video(video_id, video) => self.set_video(&video_id, video),
+            Undo::Comment(comment_id, Some(comment)) => self.comments.insert(comment_id, comment),
+            Undo::Comment(comment_id, None) => {
+                self.comments.remove(&comment_id);
+            }
+            Undo::Rating(user_id, video_id, rating) => self.ratings.upsert(&user_id, |ratings| match rating {
+                Some(rating) => {
+                    ratings.insert(video_id, rating);
+                }
+                None => {
+                    ratings.remove(&video_id);
+                }
+            }),
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get_user(&self, user_id: &str) -> io::Result<Option<User>> {
+        Ok(self.users.get(user_id))
+    }
+    
+    fn put_user(&self, user: User) -> io::Result<()> {
+        self.journal(|| Undo::User(user.id.clone(), self.users.get(&user.id).map(Box::new)));
+        self.users.insert(user.id.clone(), user);
+        Ok(())
+    }
+    
+    fn update_user(&self, user_id: &str, f: &mut dyn FnMut(&mut User)) -> io::Result<bool> {
+        self.journal(|| Undo::User(user_id.to_string(), self.users.get(user_id).map(Box::new)));
+        Ok(self.users.update(user_id, f).is_some())
+    }
+    
+    fn user_ids(&self) -> io::Result<Vec<String>> {
+        Ok(self.users.keys())
+    }
+    
+    fn user_count(&self) -> io::Result<usize> {
+        Ok(self.users.len())
+    }
+    
+    fn for_each_user(&self, f: &mut dyn FnMut(&User)) -> io::Result<()> {
+        self.users.for_each(|_, user| f(user));
+        Ok(())
+    }
+    
+    fn get_video(&self, video_id: &str) -> io::Result<Option<Video>> {
+        Ok(self.videos.get(video_id))
+    }
+    
+    fn put_video(&self, video: Video) -> io::Result<()> {
+        self.journal(|| Undo::Video(video.id.clone(), self.videos.get(&video.id)));
+        self.set_video(&video.id.clone(), Some(video));
+        Ok(())
+    }
+    
+    // Callers only change metrics here, never the channel.
+    fn update_video(&self, video_id: &str, f: &mut dyn FnMut(&mut Video)) -> io::Result<bool> {
+        self.journal(|| Undo::Video(video_id.to_string(), self.videos.get(video_id)));
+        Ok(self.videos.update(video_id, f).is_some())
+    }
+    
+    fn video_ids(&self) -> io::Result<Vec<String>> {
+        Ok(self.videos.keys())
+    }
+    
+    fn video_count(&self) -> io::Result<usize> {
+        Ok(self.videos.len())
+    }
+    
+    fn for_each_video(&self, f: &mut dyn FnMut(&Video)) -> io::Result<()> {
+        self.videos.for_each(|_, video| f(video));
+        Ok(())
+    }
+    
+    fn channel_video_ids(&self, channel_id: &str) -> io::Result<Vec<String>> {
+        Ok(self.channels.get(channel_id).unwrap_or_default())
+    }
+    
+    fn get_comment(&self, comment_id: &str) -> io::Result<Option<Comment>> {
+        Ok(self.comments.get(comment_id))
+    }
+    
+    fn put_comment(&self, comment: Comment) -> io::Result<()> {
+        self.journal(|| Undo::Comment(comment.id.clone(), self.comments.get(&comment.id)));
+        self.comments.insert(comment.id.clone(), comment);
+        Ok(())
+    }
+    
+    fn for_each_comment(&self, f: &mut dyn FnMut(&Comment)) -> io::Result<()> {
+        self.comments.for_each(|_, comment| f(comment));
+        Ok(())
+    }
+    
+    fn user_ratings(&self, user_id: &str) -> io::Result<HashMap<String, Rating>> {
+        Ok(self.ratings.get(user_id).unwrap_or_default())
+    }
+    
+    fn get_rating(&self, user_id: &str, video_id: &str) -> io::Result<Option<Rating>> {
+        Ok(self.ratings.read(user_id, |ratings| ratings?.get(video_id).copied()))
+    }
+    
+    fn put_rating(&self, user_id: &str, video_id: &str, rating: Rating) -> io::Result<()> {
+        self.journal(|| Undo::Rating(user_id.to_string(), video_id.to_string(), self.get_rating(user_id, video_id).ok().flatten()));
+        self.ratings.upsert(user_id, |ratings| {
+            ratings.insert(video_id.to_string(), rating);
+        });
+        Ok(())
+    }
+    
+    fn for_each_user_ratings(&self, f: &mut dyn FnMut(&str, &HashMap<String, Rating>)) -> io::Result<()> {
+        self.ratings.for_each(f);
+        Ok(())
+    }
+    
+    fn begin_event(&self) -> io::Result<()> {
+        self.journals.lock().unwrap().insert(thread::current().id(), Vec::new());
+        Ok(())
+    }
+    
+    fn commit_event(&self, _seq: u64) -> io::Result<()> {
+        self.journals.lock().unwrap().remove(&thread::current().id());
+        Ok(())
+    }
+    
+    fn rollback_event(&self) {
+        let journal = self.journals.lock().unwrap().remove(&thread::current().id());
+        for undo in journal.into_iter().flatten().rev() {
+            self.restore(undo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+    use crate::VideoMetrics;
+    
+    fn video(id: &str, channel_id: &str) -> Video {
+        Video {
+            id: id.to_string(),
+            title: id.to_string(),
+            channel_id: channel_id.to_string(),
+            duration: Duration::from_secs(600),
+            categories: vec!["music".to_string()],
+            tags: Vec::new(),
+            upload_date: Utc::now(),
+            metrics: VideoMetrics::default(),
+            embedding: Vec::new(),
+        }
+    }
+    
+    #[test]
+    fn rollback_restores_memory_state() {
+        let storage = MemoryStorage::new();
+        storage.put_video(video("video1", "channel1")).unwrap();
+        let rating = Rating { value: 1.0, confidence: 2.0, updated_at: Utc::now() };
+        storage.put_rating("user1", "video1", rating).unwrap();
+        
+        storage.begin_event().unwrap();
+        storage.put_video(video("video1", "channel2")).unwrap();
+        storage.put_video(video("video2", "channel2")).unwrap();
+        storage.update_video("video1", &mut |video| video.title = "changed".to_string()).unwrap();
+        storage.put_rating("user1", "video1", Rating { value: 5.0, ..rating }).unwrap();
+        storage.put_rating("user1", "video2", rating).unwrap();
+        storage.rollback_event();
+        
+        assert_eq!(storage.get_video("video1").unwrap().unwrap().title, "video1");
+        assert!(storage.get_video("video2").unwrap().is_none());
+        assert_eq!(storage.channel_video_ids("channel1").unwrap(), vec!["video1"]);
+        assert!(storage.channel_video_ids("channel2").unwrap().is_empty());
+        assert_eq!(storage.get_rating("user1", "video1").unwrap().unwrap().value, 1.0);
+        assert!(storage.get_rating("user1", "video2").unwrap().is_none());
+        
+        // A committed event keeps its writes.
+        storage.begin_event().unwrap();
+        storage.put_video(video("video2", "channel2")).unwrap();
+        storage.commit_event(1).unwrap();
+        storage.rollback_event();
+        assert_eq!(storage.channel_video_ids("channel2").unwrap(), vec!["video2"]);
+    }
+}
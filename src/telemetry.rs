@@ -0,0 +1,115 @@
+use std::time::Duration;
+use serde::Deserialize;
+
+use crate::Interaction;
+
+// Playback speeds a player can actually be set to.
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 4.0;
+
+// Speeding through a video is read as skimming it.
+const SKIM_SPEED: f32 = 1.5;
+
+// Player events as clients send them, in seconds from the start of the video.
+// A rewind goes from a later position back to an earlier one and a skip from
+// an earlier position forward to a later one.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlayerInteraction {
+    Pause { at_seconds: Vec<f64> },
+    Rewind { ranges: Vec<(f64, f64)> },
+    Skip { ranges: Vec<(f64, f64)> },
+    ChangePlaybackSpeed { speed: f32 },
+    OpenCommentSection,
+}
+
+// Rejects negative, non-finite and out-of-range watch times, which the plain
+// conversion would panic on.
+pub fn watch_duration(seconds: f64) -> Result<Duration, &'static str> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| "watch_seconds must be a non-negative number")
+}
+
+// A whole watch of one video with everything the player reported during it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WatchSessionRequest {
+    pub user_id: String,
+    pub video_id: String,
+    pub watch_seconds: f64,
+    #[serde(default)]
+    pub interactions: Vec<PlayerInteraction>,
+}
+
+impl WatchSessionRequest {
+    // Checks every position against the video's length and converts the
+    // events to the interactions stored on the watch.
+    pub fn validate(&self, video_duration: Duration) -> Result<Vec<Interaction>, &'static str> {
+        watch_duration(self.watch_seconds)?;
+        let length = video_duration.as_secs_f64();
+        let position = |seconds: f64| {
+            if seconds.is_finite() && (0.0..=length).contains(&seconds) {
+                Ok(Duration::from_secs_f64(seconds))
+            } else {
+                Err("positions must be within the video")
+            }
+        };
+        let range = |(from, to): &(f64, f64)| Ok((position(*from)?, position(*to)?));
+        
+        self.interactions.iter().map(|interaction| match interaction {
+            PlayerInteraction::Pause { at_seconds } => {
+                Ok(Interaction::Pause(at_seconds.iter().map(|at| position(*at)).collect::<Result<_, _>>()?))
+            }
+            PlayerInteraction::Rewind { ranges } => {
+                if ranges.iter().any(|(from, to)| to >= from) {
+                    return Err("a rewind must end before it starts");
+                }
+                Ok(Interaction::Rewind(ranges.iter()
+                    .map(range)
+                    .collect::<Result<_, &'static str>>()?))
+            }
+            PlayerInteraction::Skip { ranges } => {
+                if ranges.iter().any(|(from, to)| to <= from) {
+                    return Err("a skip must end after it starts");
+                }
+                Ok(Interaction::Skip(ranges.iter()
+                    .map(range)
+                    .collect::<Result<_, &'static str>>()?))
+            }
+            PlayerInteraction::ChangePlaybackSpeed { speed } => {
+                if !(MIN_SPEED..=MAX_SPEED).contains(speed) {
+                    return Err("playback speed must be between 0.25 and 4");
+                }
+                Ok(Interaction::ChangePlaybackSpeed(*speed))
+            }
+            PlayerInteraction::OpenCommentSection => Ok(Interaction::OpenCommentSection),
+        }).collect()
+    }
+}
+
+// What the player events say about interest beyond the watch time, added to
+// the watch's preference and rating adjustment. Going back to see something
+// again and opening the comments count for the video; skipping parts of it,
+// by the share skipped, and speeding through it count against.
+pub fn interest_signal(interactions: &[Interaction], video_duration: Duration) -> f64 {
+    let length = video_duration.as_secs_f64();
+    let mut rewinds = 0;
+    let mut skipped = 0.0;
+    let mut signal = 0.0;
+    
+    for interaction in interactions {
+        match interaction {
+            Interaction::Rewind(ranges) => rewinds += ranges.len(),
+            Interaction::Skip(ranges) => {
+                skipped += ranges.iter().map(|(from, to)| to.saturating_sub(*from).as_secs_f64()).sum::<f64>();
+            }
+            Interaction::ChangePlaybackSpeed(speed) if *speed >= SKIM_SPEED => signal -= 0.1,
+            Interaction::OpenCommentSection => signal += 0.1,
+            _ => {}
+        }
+    }
+    
+    signal += (0.1 * rewinds as f64).min(0.3);
+    if length > 0.0 {
+        signal -= 0.5 * (skipped / length).min(1.0);
+    }
+    signal
+}
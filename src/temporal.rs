@@ -1,50 +1,157 @@
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
-use chrono::{Utc, Timelike};
+use std::io;
+use chrono::{DateTime, Datelike, Utc, Timelike};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
 
+use crate::storage::Storage;
 use crate::{User, Video};
 
+// Neighbouring hours count half, so a histogram with few watches still says
+// something about the hours around them.
+const NEIGHBOUR_WEIGHT: f64 = 0.5;
+
+// How long a video may be relative to what the user usually watches at that
+// hour before its length stops counting as a match at all.
+const DURATION_RATIO_LIMIT: f64 = 4.0;
+
+// Watches per local hour and weekday, and what was watched at each hour.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TemporalProfile {
+    hour_watches: HashMap<u8, u32>,
+    weekday_watches: HashMap<u8, u32>,
+    hour_categories: HashMap<u8, HashMap<String, u32>>,
+    hour_duration_secs: HashMap<u8, f64>,
+}
+
+impl TemporalProfile {
+    pub fn is_empty(&self) -> bool {
+        self.hour_watches.is_empty()
+    }
+    
+    fn record(&mut self, local_time: DateTime<Tz>, video: &Video) {
+        let hour = local_time.hour() as u8;
+        let watches = self.hour_watches.entry(hour).or_insert(0);
+        *watches += 1;
+        let mean = self.hour_duration_secs.entry(hour).or_insert(0.0);
+        *mean += (video.duration.as_secs_f64() - *mean) / *watches as f64;
+        
+        let categories = self.hour_categories.entry(hour).or_default();
+        for category in &video.categories {
+            *categories.entry(category.clone()).or_insert(0) += 1;
+        }
+        *self.weekday_watches.entry(local_time.weekday().num_days_from_monday() as u8).or_insert(0) += 1;
+    }
+    
+    // Each hour or weekday relative to the busiest one.
+    fn relative(counts: &HashMap<u8, u32>) -> HashMap<u8, f64> {
+        let max = counts.values().copied().max().unwrap_or(0);
+        if max == 0 {
+            return HashMap::new();
+        }
+        counts.iter().map(|(key, count)| (*key, *count as f64 / max as f64)).collect()
+    }
+    
+    // How well the video fits what the user watches around this hour: the
+    // share of those watches in the video's best matching category, plus how
+    // close its length is to their average length on a log scale. Between 0
+    // and 2, and 0 when nothing has been watched around this hour.
+    pub fn match_score(&self, hour: u8, video: &Video) -> f64 {
+        let mut watches = 0.0;
+        let mut duration_secs = 0.0;
+        let mut categories: HashMap<&str, f64> = HashMap::new();
+        
+        for (offset, weight) in [(23, NEIGHBOUR_WEIGHT), (0, 1.0), (1, NEIGHBOUR_WEIGHT)] {
+            let hour = (hour + offset) % 24;
+            let Some(count) = self.hour_watches.get(&hour) else {
+                continue;
+            };
+            let count = weight * *count as f64;
+            watches += count;
+            duration_secs += count * self.hour_duration_secs.get(&hour).copied().unwrap_or(0.0);
+            for (category, category_count) in self.hour_categories.get(&hour).into_iter().flatten() {
+                *categories.entry(category.as_str()).or_insert(0.0) += weight * *category_count as f64;
+            }
+        }
+        if watches == 0.0 {
+            return 0.0;
+        }
+        
+        let category_match = video.categories.iter()
+            .filter_map(|category| categories.get(category.as_str()))
+            .fold(0.0, |best: f64, count| best.max(count / watches));
+        
+        let typical_secs = duration_secs / watches;
+        let video_secs = video.duration.as_secs_f64();
+        let duration_match = if typical_secs > 0.0 && video_secs > 0.0 {
+            (1.0 - (video_secs / typical_secs).ln().abs() / DURATION_RATIO_LIMIT.ln()).max(0.0)
+        } else {
+            0.0
+        };
+        
+        category_match + duration_match
+    }
+}
+
+pub fn local_time(user: &User, timestamp: DateTime<Utc>) -> DateTime<Tz> {
+    timestamp.with_timezone(&user.timezone)
+}
+
+// Adds a watch to the user's histograms and refreshes the hourly and weekday
+// preferences derived from them.
+pub fn learn_watch_time(user: &mut User, video: &Video, timestamp: DateTime<Utc>) {
+    let local_time = local_time(user, timestamp);
+    let patterns = &mut user.interaction_patterns;
+    patterns.temporal_profile.record(local_time, video);
+    patterns.time_of_day_preferences = TemporalProfile::relative(&patterns.temporal_profile.hour_watches);
+    patterns.day_of_week_preferences = TemporalProfile::relative(&patterns.temporal_profile.weekday_watches);
+}
+
+// Relearns the histograms from the whole watch history, for a new timezone
+// or a user stored before they were kept. Watches of videos no longer in the
+// catalog are left out.
+pub fn rebuild_temporal_profile(user: &mut User, videos: &HashMap<String, Video>) {
+    user.interaction_patterns.temporal_profile = TemporalProfile::default();
+    let watches: Vec<_> = user.watch_history.iter()
+        .map(|watch| (watch.video_id.clone(), watch.timestamp))
+        .collect();
+    for (video_id, timestamp) in watches {
+        if let Some(video) = videos.get(&video_id) {
+            learn_watch_time(user, video, timestamp);
+        }
+    }
+}
+
 pub fn get_temporal_recommendations(
     user_id: &str,
     count: usize,
-    users: &Arc<Mutex<HashMap<String, User>>>,
-    videos: &Arc<Mutex<HashMap<String, Video>>>
-) -> Vec<(String, f64)> {
+    storage: &dyn Storage,
+    now: DateTime<Utc>
+) -> io::Result<Vec<(String, f64)>> {
     let mut recommendations = Vec::new();
     let mut scored_videos: HashMap<String, f64> = HashMap::new();
     
-    let user_subscriptions = {
-        let users_guard = users.lock().unwrap();
-        if let Some(user) = users_guard.get(user_id) {
-            user.subscriptions.clone()
-        } else {
-            return recommendations;
-        }
-    };
-    
-    let time_preferences = {
-        let users_guard = users.lock().unwrap();
-        if let Some(user) = users_guard.get(user_id) {
-            user.interaction_patterns.time_of_day_preferences.clone()
-        } else {
-            HashMap::new()
-        }
+    let Some(user) = storage.get_user(user_id)? else {
+        return Ok(recommendations);
     };
     
-    let current_hour = Utc::now().hour() as u8;
-    
-    let videos_guard = videos.lock().unwrap();
-    
-    let now = SystemTime::now();
-    let now_secs = now.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+    // Matching is done in the user's own timezone. A user who rarely watches
+    // on this weekday gets a weaker time-of-day boost than on their usual days.
+    let local_now = local_time(&user, now);
+    let current_hour = local_now.hour() as u8;
+    let patterns = &user.interaction_patterns;
+    let hour_preference = patterns.time_of_day_preferences.get(&current_hour).copied().unwrap_or(0.0);
+    let weekday_preference = patterns.day_of_week_preferences
+        .get(&(local_now.weekday().num_days_from_monday() as u8))
+        .copied()
+        .unwrap_or(0.0);
+    let activity = hour_preference * (0.5 + 0.5 * weekday_preference);
     
-    for (video_id, video) in videos_guard.iter() {
+    storage.for_each_video(&mut |video| {
         let mut score = 0.0;
         
-        if user_subscriptions.contains(&video.channel_id) {
-            let video_upload_timestamp = video.upload_date.timestamp() as u64;
-            let days_since_upload = ((now_secs as i64 - video_upload_timestamp as i64).abs() as f64) / (24.0 * 60.0 * 60.0);
+        if user.subscriptions.contains(&video.channel_id) {
+            let days_since_upload = ((now - video.upload_date).num_seconds().abs() as f64) / (24.0 * 60.0 * 60.0);
             
             if days_since_upload < 1.0 {
                 score += 5.0;
@@ -59,8 +166,8 @@ pub fn get_temporal_recommendations(
             }
         }
         
-        if let Some(time_preference) = time_preferences.get(&current_hour) {
-            score += *time_preference;
+        if activity > 0.0 {
+            score += activity * patterns.temporal_profile.match_score(current_hour, video);
         }
         
         let is_trending = video.metrics.views > 1000 && 
@@ -70,16 +177,18 @@ pub fn get_temporal_recommendations(
         }
         
         if score > 0.0 {
-            scored_videos.insert(video_id.clone(), score);
+            scored_videos.insert(video.id.clone(), score);
         }
-    }
+    })?;
     
     let mut scored_list: Vec<(String, f64)> = scored_videos.into_iter().collect();
-    scored_list.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    scored_list.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.cmp(&b.0))
+    });
     
     recommendations = scored_list.into_iter().take(count).collect();
     
-    recommendations
+    Ok(recommendations)
 }
 
 pub fn get_dummy_temporal_recommendations(_user_id: &str, count: usize) -> Vec<(String, f64)> {
@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::evaluation::{EvaluationReport, EvaluationSplit, Source};
+use crate::storage::Storage;
+use crate::{BlendConfig, EngineConfig};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Metric {
+    Precision,
+    Recall,
+    Map,
+    Ndcg,
+    Mrr,
+    HitRate,
+}
+
+impl Metric {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "precision" => Some(Metric::Precision),
+            "recall" => Some(Metric::Recall),
+            "map" => Some(Metric::Map),
+            "ndcg" => Some(Metric::Ndcg),
+            "mrr" => Some(Metric::Mrr),
+            "hit_rate" => Some(Metric::HitRate),
+            _ => None,
+        }
+    }
+    
+    fn score(self, report: &EvaluationReport) -> f64 {
+        match self {
+            Metric::Precision => report.precision,
+            Metric::Recall => report.recall,
+            Metric::Map => report.map,
+            Metric::Ndcg => report.ndcg,
+            Metric::Mrr => report.mrr,
+            Metric::HitRate => report.hit_rate,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Strategy {
+    // Every combination of `steps` evenly spaced levels per weight.
+    Grid { steps: usize },
+    Random { trials: usize, seed: u64 },
+    // Nudges one knob at a time from the current config, halving the step
+    // whenever no neighbour improves on it.
+    CoordinateAscent { rounds: usize, step: f64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct TuningConfig {
+    pub strategy: Strategy,
+    pub metric: Metric,
+    pub k: usize,
+    pub test_fraction: f64,
+    pub threads: usize,
+}
+
+pub struct Trial {
+    pub blend: BlendConfig,
+    pub report: EvaluationReport,
+    pub score: f64,
+}
+
+// Returns every evaluated configuration, best first.
+pub fn tune(storage: &dyn Storage, base: &EngineConfig, config: &TuningConfig) -> io::Result<Vec<Trial>> {
+    let split = EvaluationSplit::validation(storage, config.test_fraction)?;
+    
+    let mut trials = match config.strategy {
+        Strategy::Grid { steps } => evaluate_all(&split, base, grid(&base.blend, steps)?, config),
+        Strategy::Random { trials, seed } => {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let candidates = (0..trials).map(|_| random_blend(&mut rng)).collect();
+            evaluate_all(&split, base, candidates, config)
+        }
+        Strategy::CoordinateAscent { rounds, step } => coordinate_ascent(&split, base, rounds, step, config),
+    }?;
+    
+    trials.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(trials)
+}
+
+fn evaluate_all(
+    split: &EvaluationSplit,
+    base: &EngineConfig,
+    candidates: Vec<BlendConfig>,
+    config: &TuningConfig,
+) -> io::Result<Vec<Trial>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(candidates.len()));
+    
+    std::thread::scope(|scope| {
+        for _ in 0..config.threads.clamp(1, candidates.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(blend) = candidates.get(index) else {
+                    break;
+                };
+                
+                let engine_config = EngineConfig { blend: blend.clone(), ..base.clone() };
+                let trial = split.evaluate(&engine_config, Source::Blend, config.k)
+                    .map(|report| Trial { blend: blend.clone(), score: config.metric.score(&report), report });
+                results.lock().unwrap().push((index, trial));
+            });
+        }
+    });
+    
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, trial)| trial).collect()
+}
+
+fn coordinate_ascent(
+    split: &EvaluationSplit,
+    base: &EngineConfig,
+    rounds: usize,
+    mut step: f64,
+    config: &TuningConfig,
+) -> io::Result<Vec<Trial>> {
+    let mut trials = evaluate_all(split, base, vec![normalized(base.blend.clone())], config)?;
+    let mut current = 0;
+    
+    for _ in 0..rounds {
+        let neighbours = neighbours(&trials[current].blend, step);
+        let first = trials.len();
+        trials.extend(evaluate_all(split, base, neighbours, config)?);
+        
+        let best = (first..trials.len()).max_by(|&a, &b| trials[a].score.total_cmp(&trials[b].score));
+        match best {
+            Some(best) if trials[best].score > trials[current].score => current = best,
+            _ => step /= 2.0,
+        }
+    }
+    
+    Ok(trials)
+}
+
+const WEIGHTS: usize = 7;
+const MAX_GRID_SIZE: usize = 100_000;
+
+fn weights_mut(blend: &mut BlendConfig) -> [&mut f64; WEIGHTS] {
+    [
+        &mut blend.collaborative,
+        &mut blend.content_based,
+        &mut blend.popularity,
+        &mut blend.temporal,
+        &mut blend.engagement,
+        &mut blend.session,
+        &mut blend.sequence,
+    ]
+}
+
+// Scores are combined linearly, so only the ratios between weights matter;
+// normalising keeps equivalent configs from being evaluated twice.
+fn normalized(mut blend: BlendConfig) -> BlendConfig {
+    let total: f64 = weights_mut(&mut blend).iter().map(|weight| **weight).sum();
+    if total > 0.0 {
+        for weight in weights_mut(&mut blend) {
+            *weight /= total;
+        }
+    }
+    blend
+}
+
+fn grid(base: &BlendConfig, steps: usize) -> io::Result<Vec<BlendConfig>> {
+    let steps = steps.max(2);
+    let size = steps.checked_pow(WEIGHTS as u32)
+        .filter(|size| *size <= MAX_GRID_SIZE)
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a grid of {} steps over {} weights exceeds {} configurations", steps, WEIGHTS, MAX_GRID_SIZE),
+        ))?;
+    let levels: Vec<f64> = (0..steps).map(|i| i as f64 / (steps - 1) as f64).collect();
+    
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for index in 0..size {
+        let mut blend = base.clone();
+        let mut rest = index;
+        for weight in weights_mut(&mut blend) {
+            *weight = levels[rest % steps];
+            rest /= steps;
+        }
+        
+        let blend = normalized(blend);
+        let mut key = blend.clone();
+        let key: Vec<i64> = weights_mut(&mut key).iter().map(|weight| (**weight * 1e6).round() as i64).collect();
+        if key.iter().any(|weight| *weight > 0) && seen.insert(key) {
+            candidates.push(blend);
+        }
+    }
+    Ok(candidates)
+}
+
+fn random_blend(rng: &mut StdRng) -> BlendConfig {
+    let mut blend = BlendConfig::default();
+    for weight in weights_mut(&mut blend) {
+        *weight = rng.random();
+    }
+    blend.candidate_multiplier = rng.random_range(1..=4);
+    normalized(blend)
+}
+
+fn neighbours(blend: &BlendConfig, step: f64) -> Vec<BlendConfig> {
+    let mut candidates = Vec::new();
+    for index in 0..WEIGHTS {
+        for delta in [step, -step] {
+            let mut candidate = blend.clone();
+            let weight = &mut weights_mut(&mut candidate)[index];
+            **weight = (**weight + delta).max(0.0);
+            candidates.push(normalized(candidate));
+        }
+    }
+    
+    for multiplier in [blend.candidate_multiplier + 1, blend.candidate_multiplier.saturating_sub(1)] {
+        if multiplier >= 1 && multiplier != blend.candidate_multiplier {
+            candidates.push(BlendConfig { candidate_multiplier: multiplier, ..blend.clone() });
+        }
+    }
+    
+    candidates.retain(|candidate| candidate != blend);
+    candidates
+}
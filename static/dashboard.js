@@ -23,7 +23,9 @@ async function fetchSystemStats() {
         document.getElementById('total-videos').textContent = data.videoCount;
         document.getElementById('total-users').textContent = data.userCount;
         document.getElementById('interactions-today').textContent = data.interactionsToday;
-        document.getElementById('recommendation-quality').textContent = `${data.recommendationQuality.toFixed(1)}%`;
+        document.getElementById('recommendation-quality').textContent = data.recommendationQuality === null
+            ? 'n/a'
+            : `${data.recommendationQuality.toFixed(1)}%`;
         
         users = data.users;
         videos = data.videos;
@@ -327,6 +329,13 @@ async function runSimulation() {
             body: JSON.stringify({ user_count: userCount, days, intensity })
         });
         
+        if (!response.ok) {
+            const error = await response.json();
+            simulationProgress.classList.add('d-none');
+            resultsContainer.innerHTML = `<div class="alert alert-danger">${error.message}</div>`;
+            return;
+        }
+        
         const simulationInterval = setInterval(() => {
             fetch('/api/simulation-status')
                 .then(res => res.json())
@@ -334,7 +343,10 @@ async function runSimulation() {
                     const progress = status.progress;
                     progressBar.style.width = `${progress}%`;
                     
-                    if (progress >= 100) {
+                    if (status.status === 'failed') {
+                        clearInterval(simulationInterval);
+                        resultsContainer.innerHTML = `<div class="alert alert-danger">Simulation failed: ${status.message}</div>`;
+                    } else if (progress >= 100) {
                         clearInterval(simulationInterval);
                         completeSimulation();
                     }